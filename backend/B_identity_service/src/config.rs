@@ -1,7 +1,10 @@
-use std::{env, str::FromStr};
+use std::{collections::HashMap, env, str::FromStr};
 
 use chrono::Duration;
+use ipnet::IpNet;
+use jsonwebtoken::Algorithm;
 use once_cell::sync::Lazy;
+use strum::EnumString;
 
 fn get_string(key: &str) -> String {
     env::var(key).unwrap_or_else(|_| {
@@ -9,6 +12,14 @@ fn get_string(key: &str) -> String {
     })
 }
 
+fn get_optional_string(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+fn get_string_or(key: &str, default: &str) -> String {
+    get_optional_string(key).unwrap_or_else(|| default.to_string())
+}
+
 fn get_bool(key: &str) -> bool {
     match env::var(key)
         .unwrap_or_else(|_| panic!("Environment variable `{}` is not set", key))
@@ -24,6 +35,13 @@ fn get_bool(key: &str) -> bool {
     }
 }
 
+fn get_bool_or(key: &str, default: bool) -> bool {
+    match get_optional_string(key) {
+        Some(_) => get_bool(key),
+        None => default,
+    }
+}
+
 fn get_number<F: FromStr>(key: &str) -> F {
     env::var(key)
         .unwrap_or_else(|_| panic!("Environment variable `{}` is not set", key))
@@ -31,17 +49,160 @@ fn get_number<F: FromStr>(key: &str) -> F {
         .unwrap_or_else(|_| panic!("Invalid usize value for `{}`", key))
 }
 
+fn get_number_or<F: FromStr>(key: &str, default: F) -> F {
+    match get_optional_string(key) {
+        Some(_) => get_number(key),
+        None => default,
+    }
+}
+
+/// `id:secret` pairs separated by commas
+fn get_credentials(key: &str) -> HashMap<String, String> {
+    get_string(key)
+        .split(',')
+        .filter(|c| !c.trim().is_empty())
+        .map(|c| {
+            let (id, secret) = c.trim().split_once(':')
+                .unwrap_or_else(|| panic!("Invalid credentials in `{}`: expected `id:secret`", key));
+            (id.to_string(), secret.to_string())
+        })
+        .collect()
+}
+
+/// `id:value|value|...` entries separated by commas, empty if unset
+fn get_grants(key: &str) -> HashMap<String, Vec<String>> {
+    get_optional_string(key)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|g| !g.trim().is_empty())
+        .map(|g| {
+            let (id, values) = g.trim().split_once(':')
+                .unwrap_or_else(|| panic!("Invalid entry in `{}`: expected `id:value|value`", key));
+            (id.to_string(), values.split('|').map(str::trim).filter(|v| !v.is_empty()).map(str::to_string).collect())
+        })
+        .collect()
+}
+
+/// Values separated by commas, empty if unset
+fn get_list(key: &str) -> Vec<String> {
+    get_optional_string(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Addresses or CIDR ranges separated by commas, empty if unset
+fn get_networks(key: &str) -> Vec<IpNet> {
+    get_optional_string(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(|n| {
+            n.parse::<IpNet>()
+                .or_else(|_| n.parse::<std::net::IpAddr>().map(IpNet::from))
+                .unwrap_or_else(|_| panic!("Invalid address or range in `{}`: `{}`", key, n))
+        })
+        .collect()
+}
+
+fn get_enum<F: FromStr>(key: &str) -> F {
+    let value = get_string(key);
+    value
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid value for `{}`: `{}`", key, value))
+}
+
+/// Optional variables added after deployments existed
+fn get_enum_or<F: FromStr>(key: &str, default: F) -> F {
+    match get_optional_string(key) {
+        Some(_) => get_enum(key),
+        None => default,
+    }
+}
+
+/// What an account with an unverified email is not allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum EmailVerificationPolicy {
+    Off,
+    Login,
+    Scopes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum LoginThrottleBackend {
+    Memory,
+    Postgres,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum PaymentProviderKind {
+    Fake,
+    Stripe,
+}
 
 pub struct Config {
     pub rabbitmq_url: String,
     pub postgres_url: String,
     pub socket: String,
-    pub jwt_access_secret: String,
+    pub jwt_access_algorithm: Algorithm,
+    pub jwt_access_kid: String,
+    pub jwt_access_secret: Option<String>, // HS* algorithms
+    pub jwt_access_private_key_file: Option<String>, // RS*/EdDSA, PEM
+    pub jwt_access_public_key_file: Option<String>, // RS*/EdDSA, PEM
+    pub jwt_access_keyring_file: Option<String>, // JSON manifest, replaces the single key above
+    pub jwt_access_keyring_reload_time: std::time::Duration, // both keyring files
     pub jwt_access_exp_time: Duration,
+    pub jwt_access_entitlements: bool, // embed the accessible courses in access tokens
+    pub jwt_refresh_kid: String,
     pub jwt_refresh_secret: String,
+    pub jwt_refresh_keyring_file: Option<String>, // same manifest as the access one, replaces the secret above
     pub jwt_refresh_exp_time: Duration,
     pub jwt_refresh_cookie_name: String,
     pub jwt_domain: String,
+    pub user_cache_exp_time: Duration,
+    pub frontend_url: String,
+    pub mail_outbox_dir: String,
+    pub password_reset_exp_time: Duration,
+    pub email_verification: EmailVerificationPolicy,
+    pub email_verification_exp_time: Duration,
+    pub email_verification_resend_time: Duration,
+    pub email_change_exp_time: Duration,
+    pub storage_local_dir: String,
+    pub mfa_issuer: String,
+    pub mfa_pending_exp_time: Duration,
+    pub login_throttle_backend: LoginThrottleBackend,
+    pub login_account_free_attempts: u32,
+    pub login_ip_free_attempts: u32,
+    pub login_lockout_base_time: Duration,
+    pub login_lockout_max_time: Duration,
+    pub trusted_proxies: Vec<IpNet>, // peers allowed to set `X-Forwarded-For`
+    pub internal_clients: HashMap<String, String>, // client id -> secret, for `/api/internal`
+    pub account_deletion_grace_time: Duration,
+    pub account_deletion_sweep_time: std::time::Duration,
+    pub outbox_poll_time: std::time::Duration,
+    pub command_max_attempts: u32,
+    pub command_retry_time: std::time::Duration,
+    pub command_senders: HashMap<String, Vec<String>>, // broker user -> command types it may send
+    pub command_grantable_roles: Vec<String>, // roles the role commands may grant or revoke
+    pub public_url: String,
+    pub payment_provider: PaymentProviderKind,
+    pub stripe_api_url: String,
+    pub stripe_secret_key: Option<String>, // required when `payment_provider` is stripe
+    pub payment_webhook_secret: String,
+    pub payment_webhook_tolerance: Duration,
+    pub refund_window: Duration, // buyers can refund themselves for that long after paying
+    pub seller_name: String, // shown on invoices
+    pub seller_address: String,
+    pub seller_vat_id: Option<String>,
+    pub invoice_series_prefix: String, // series are `<prefix>-<year>`, and `<prefix>-CN-<year>` for credit notes
+    pub invoice_tax_rate: i32, // basis points, prices include it. The rate of the seller's country, for every buyer: no rates per buyer country (e.g. EU OSS)
 }
 impl Config {
     fn new() -> Self {
@@ -49,14 +210,60 @@ impl Config {
             rabbitmq_url: get_string("RABBITMQ_URL"),
             postgres_url: get_string("POSTGRES_URL"),
             socket: get_string("LISTEN_SOCKET"),
-            jwt_access_secret: get_string("JWT_ACCESS_SECRET"),
+            jwt_access_algorithm: get_enum_or("JWT_ACCESS_ALGORITHM", Algorithm::HS256),
+            jwt_access_kid: get_string_or("JWT_ACCESS_KID", "default"),
+            jwt_access_secret: get_optional_string("JWT_ACCESS_SECRET"),
+            jwt_access_private_key_file: get_optional_string("JWT_ACCESS_PRIVATE_KEY_FILE"),
+            jwt_access_public_key_file: get_optional_string("JWT_ACCESS_PUBLIC_KEY_FILE"),
+            jwt_access_keyring_file: get_optional_string("JWT_ACCESS_KEYRING_FILE"),
+            jwt_access_keyring_reload_time: std::time::Duration::from_secs(get_number_or("JWT_ACCESS_KEYRING_RELOAD_SECONDS", 30)),
             jwt_access_exp_time: Duration::hours(get_number("JWT_ACCESS_HOURS")),
+            jwt_access_entitlements: get_bool_or("JWT_ACCESS_ENTITLEMENTS", false),
+            jwt_refresh_kid: get_string_or("JWT_REFRESH_KID", "default"),
             jwt_refresh_secret: get_string("JWT_REFRESH_SECRET"),
+            jwt_refresh_keyring_file: get_optional_string("JWT_REFRESH_KEYRING_FILE"),
             jwt_refresh_exp_time: Duration::hours(get_number("JWT_REFRESH_HOURS")),
             jwt_refresh_cookie_name: "refresh_token".to_string(),
             jwt_domain: get_string("JWT_DOMAIN"),
+            user_cache_exp_time: Duration::seconds(get_number("USER_CACHE_SECONDS")),
+            frontend_url: get_string("FRONTEND_URL"),
+            mail_outbox_dir: get_string("MAIL_OUTBOX_DIR"),
+            password_reset_exp_time: Duration::minutes(get_number("PASSWORD_RESET_MINUTES")),
+            email_verification: get_enum("EMAIL_VERIFICATION"),
+            email_verification_exp_time: Duration::hours(get_number("EMAIL_VERIFICATION_HOURS")),
+            email_verification_resend_time: Duration::seconds(get_number("EMAIL_VERIFICATION_RESEND_SECONDS")),
+            email_change_exp_time: Duration::hours(get_number("EMAIL_CHANGE_HOURS")),
+            storage_local_dir: get_string("STORAGE_LOCAL_DIR"),
+            mfa_issuer: get_string("MFA_ISSUER"),
+            mfa_pending_exp_time: Duration::minutes(get_number("MFA_PENDING_MINUTES")),
+            login_throttle_backend: get_enum("LOGIN_THROTTLE_BACKEND"),
+            login_account_free_attempts: get_number("LOGIN_ACCOUNT_FREE_ATTEMPTS"),
+            login_ip_free_attempts: get_number("LOGIN_IP_FREE_ATTEMPTS"),
+            login_lockout_base_time: Duration::seconds(get_number("LOGIN_LOCKOUT_BASE_SECONDS")),
+            login_lockout_max_time: Duration::seconds(get_number("LOGIN_LOCKOUT_MAX_SECONDS")),
+            trusted_proxies: get_networks("TRUSTED_PROXIES"),
+            internal_clients: get_credentials("INTERNAL_CLIENTS"),
+            account_deletion_grace_time: Duration::days(get_number("ACCOUNT_DELETION_GRACE_DAYS")),
+            account_deletion_sweep_time: std::time::Duration::from_secs(60 * get_number::<u64>("ACCOUNT_DELETION_SWEEP_MINUTES")),
+            outbox_poll_time: std::time::Duration::from_millis(get_number("OUTBOX_POLL_MILLIS")),
+            command_max_attempts: get_number("COMMAND_MAX_ATTEMPTS"),
+            command_retry_time: std::time::Duration::from_secs(get_number("COMMAND_RETRY_SECONDS")),
+            command_senders: get_grants("COMMAND_SENDERS"),
+            command_grantable_roles: get_list("COMMAND_GRANTABLE_ROLES"),
+            public_url: get_string("PUBLIC_URL"),
+            payment_provider: get_enum("PAYMENT_PROVIDER"),
+            stripe_api_url: get_string("STRIPE_API_URL"),
+            stripe_secret_key: get_optional_string("STRIPE_SECRET_KEY"),
+            payment_webhook_secret: get_string("PAYMENT_WEBHOOK_SECRET"),
+            payment_webhook_tolerance: Duration::seconds(get_number("PAYMENT_WEBHOOK_TOLERANCE_SECONDS")),
+            refund_window: Duration::days(get_number("REFUND_WINDOW_DAYS")),
+            seller_name: get_string("SELLER_NAME"),
+            seller_address: get_string("SELLER_ADDRESS"),
+            seller_vat_id: get_optional_string("SELLER_VAT_ID"),
+            invoice_series_prefix: get_string("INVOICE_SERIES_PREFIX"),
+            invoice_tax_rate: get_number("INVOICE_TAX_RATE_BASIS_POINTS"),
         }
     }
 }
 
-pub const CONFIG: Lazy<Config> = Lazy::new(Config::new);
\ No newline at end of file
+pub static CONFIG: Lazy<Config> = Lazy::new(Config::new);
\ No newline at end of file
@@ -1,5 +1,5 @@
 use std::{error::Error, fmt::Display, panic::Location};
-use axum::{extract::{multipart::MultipartRejection, rejection::{BytesRejection, JsonRejection, PathRejection, QueryRejection}}, http::StatusCode, response::IntoResponse};
+use axum::{extract::{multipart::MultipartRejection, rejection::{BytesRejection, FormRejection, JsonRejection, PathRejection, QueryRejection}}, http::{HeaderValue, StatusCode, header::RETRY_AFTER}, response::IntoResponse};
 use sea_orm::DbErr;
 use serde::{ser::SerializeStruct, Serialize};
 use strum::IntoStaticStr;
@@ -39,6 +39,8 @@ pub enum LocalErrKind {
     VideoResolutionTooLow,
     InvalidVideoFormat,
     InvalidImageFormat,
+    ImageTooLarge,
+    ImageResolutionTooLow,
     StoreVideo,
     StoreImage,
     VideoNotFound,
@@ -49,6 +51,43 @@ pub enum LocalErrKind {
     Unauthorized,
     InvalidAccessToken,
     InvalidRefreshToken,
+    InvalidPasswordResetToken,
+    InvalidEmailVerificationToken,
+    InvalidEmailChangeToken,
+    InvalidMfaToken,
+    InvalidMfaCode,
+    MfaAlreadyEnabled,
+    MfaNotEnrolled,
+    EmailNotVerified,
+    EmailVerificationThrottled,
+    TooManyLoginAttempts,
+    InvalidClientCredentials,
+    MissingPermission,
+    UnknownRole,
+    CannotManageSelf,
+
+    // payments
+    CourseNotForSale,
+    CourseAlreadyPurchased,
+    CheckoutPending,
+    PaymentProviderError,
+    PaymentRefused, // the provider answered that it won't do it, nothing happened there
+    InvalidWebhookSignature,
+    WebhookProcessingFailed,
+    UnknownPlan,
+    SubscriptionAlreadyActive,
+    NoActiveSubscription,
+    InvalidPlanChange,
+    TooManyAccessChecks,
+    UnknownCoupon,
+    CouponExpired,
+    CouponExhausted,
+    CouponNotApplicable,
+    CouponCodeTaken,
+    InvalidCoupon,
+    OrderNotRefundable,
+    RefundWindowExpired,
+    InvalidRefundAmount,
 
     // extract
     JsonRejection,
@@ -57,6 +96,7 @@ pub enum LocalErrKind {
     PathRejection,
     WebSocketUpgradeRejection,
     MultipartRejection,
+    FormRejection,
 
     Code500,
     NotFound,
@@ -67,6 +107,7 @@ pub struct LocalErr {
     pub error: LocalErrKind,
     pub msg: Option<String>,
     pub code: StatusCode,
+    pub retry_after: Option<u64>, // seconds, sent as the `Retry-After` header
 }
 
 pub type LocalResult<T> = Result<T, LocalErr>;
@@ -113,13 +154,18 @@ impl Serialize for ErrRespInner {
 
 impl LocalErr {
     pub fn new(e: LocalErrKind, code: StatusCode) -> Self {
-        Self { error: e, code, msg: None }
+        Self { error: e, code, msg: None, retry_after: None }
     }
 
     pub fn with_msg(mut self, msg: impl Into<String>) -> Self {
         self.msg = Some(msg.into());
         self
     }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
 }
 
 
@@ -129,6 +175,9 @@ impl IntoResponse for LocalErr {
 
         let mut resp = body.into_response();
         *resp.status_mut() = self.code;
+        if let Some(seconds) = self.retry_after {
+            resp.headers_mut().insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
         resp
     }
 }
@@ -145,6 +194,12 @@ impl From<QueryRejection> for LocalErr {
     }
 }
 
+impl From<FormRejection> for LocalErr {
+    fn from(value: FormRejection) -> Self {
+        Self::new(LocalErrKind::FormRejection, StatusCode::BAD_REQUEST).with_msg(value.body_text())
+    }
+}
+
 impl From<BytesRejection> for LocalErr {
     fn from(value: BytesRejection) -> Self {
         Self::new(LocalErrKind::BytesRejection, StatusCode::BAD_REQUEST).with_msg(value.body_text())
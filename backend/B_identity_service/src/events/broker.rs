@@ -0,0 +1,29 @@
+use lapin::{Connection, ConnectionProperties, ExchangeKind, options::ExchangeDeclareOptions, types::FieldTable, uri::AMQPUri};
+
+use crate::config::CONFIG;
+
+/// Topic exchange of the identity events
+pub const EXCHANGE: &str = "identity.events";
+
+pub async fn connect() -> lapin::Result<Connection> {
+    let properties = ConnectionProperties::default().with_connection_name("identity_service".into());
+    let connection = Connection::connect(&CONFIG.rabbitmq_url, properties).await?;
+
+    let channel = connection.create_channel().await?;
+    channel.exchange_declare(
+        EXCHANGE,
+        ExchangeKind::Topic,
+        ExchangeDeclareOptions { durable: true, ..Default::default() },
+        FieldTable::default(),
+    ).await?;
+    channel.close(200, "declared").await?;
+
+    Ok(connection)
+}
+
+/// Broker user of this service, from `RABBITMQ_URL`
+pub fn username() -> String {
+    CONFIG.rabbitmq_url.parse::<AMQPUri>()
+        .map(|uri| uri.authority.userinfo.username)
+        .unwrap_or_default()
+}
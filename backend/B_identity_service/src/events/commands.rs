@@ -0,0 +1,130 @@
+use axum::http::StatusCode;
+use sea_orm::{ColumnTrait, Condition};
+use serde::Deserialize;
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind}, models::entity::user, state::AppState};
+
+/// Message sent by another service to `COMMANDS_EXCHANGE`, with the command type as routing key.
+/// `id` is the idempotency key: a command whose id was already processed is acknowledged and ignored
+#[derive(Deserialize)]
+pub struct CommandEnvelope {
+    pub id: uuid::Uuid,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum Command {
+    #[serde(rename = "user.grant_role")]
+    GrantRole(RoleCommand),
+    #[serde(rename = "user.revoke_role")]
+    RevokeRole(RoleCommand),
+    #[serde(rename = "course.set_price")]
+    SetCoursePrice(SetCoursePriceCommand),
+    #[serde(rename = "course.remove_price")]
+    RemoveCoursePrice(RemoveCoursePriceCommand),
+}
+
+impl Command {
+    pub fn command_type(&self) -> &'static str {
+        match self {
+            Self::GrantRole(_) => "user.grant_role",
+            Self::RevokeRole(_) => "user.revoke_role",
+            Self::SetCoursePrice(_) => "course.set_price",
+            Self::RemoveCoursePrice(_) => "course.remove_price",
+        }
+    }
+}
+
+/// Whether the broker user `sender` may send `command` (see `COMMAND_SENDERS`)
+pub fn is_allowed(sender: &str, command: &Command) -> bool {
+    CONFIG.command_senders.get(sender)
+        .is_some_and(|types| types.iter().any(|t| t == command.command_type()))
+}
+
+#[derive(Deserialize)]
+pub struct RoleCommand {
+    pub user_id: uuid::Uuid,
+    pub role: String,
+}
+
+/// Makes the course purchasable at this price
+#[derive(Deserialize)]
+pub struct SetCoursePriceCommand {
+    pub course_id: uuid::Uuid,
+    pub title: String,
+    pub amount: i64, // minor units
+    pub currency: String, // ISO 4217
+}
+
+#[derive(Deserialize)]
+pub struct RemoveCoursePriceCommand {
+    pub course_id: uuid::Uuid,
+}
+
+pub enum CommandError {
+    Retry(String), // might work later (e.g. database down)
+    Reject(String), // will never work, dead-lettered right away
+}
+
+impl From<LocalErr> for CommandError {
+    fn from(e: LocalErr) -> Self {
+        match e.code.is_server_error() {
+            true => Self::Retry(e.to_string()),
+            false => Self::Reject(e.to_string()),
+        }
+    }
+}
+
+/// Handlers must be safe to run twice: a crash between handling and recording
+/// the message id means it will be redelivered
+pub async fn handle(state: &AppState, command: Command) -> Result<(), CommandError> {
+    match command {
+        Command::GrantRole(c) => set_role(state, c, true).await,
+        Command::RevokeRole(c) => set_role(state, c, false).await,
+        Command::SetCoursePrice(c) => set_course_price(state, c).await,
+        Command::RemoveCoursePrice(c) => Ok(state.payments_service.remove_course_price(c.course_id).await?),
+    }
+}
+
+async fn set_role(state: &AppState, command: RoleCommand, granted: bool) -> Result<(), CommandError> {
+    if !CONFIG.command_grantable_roles.contains(&command.role) {
+        return Err(CommandError::Reject(format!("role `{}` can't be changed by commands", command.role)))
+    }
+    if !state.users_service.exists_user(Condition::all().add(user::Column::Id.eq(command.user_id))).await? {
+        return Err(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND).into())
+    }
+
+    let mut roles = state.roles_service.get_user_roles(command.user_id).await?;
+    if roles.contains(&command.role) == granted {
+        return Ok(())
+    }
+
+    match granted {
+        true => roles.push(command.role),
+        false => roles.retain(|r| *r != command.role),
+    }
+    roles.sort();
+
+    // bumps the version, like an admin change
+    state.roles_service.set_user_roles(command.user_id, &roles, None).await?;
+    state.users_service.forget_version(command.user_id).await;
+    Ok(())
+}
+
+
+async fn set_course_price(state: &AppState, command: SetCoursePriceCommand) -> Result<(), CommandError> {
+    if command.amount <= 0 {
+        return Err(CommandError::Reject("`amount` must be positive".to_string()))
+    }
+    if command.currency.len() != 3 || !command.currency.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(CommandError::Reject("`currency` must be an ISO 4217 code".to_string()))
+    }
+    if command.title.is_empty() || command.title.len() > 255 {
+        return Err(CommandError::Reject("`title` must have between 1 and 255 bytes".to_string()))
+    }
+
+    let currency = command.currency.to_lowercase();
+    Ok(state.payments_service.set_course_price(command.course_id, &command.title, command.amount, &currency).await?)
+}
\ No newline at end of file
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use lapin::{BasicProperties, Channel, ExchangeKind, message::Delivery, options::{BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, BasicQosOptions, ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions}, types::{AMQPValue, FieldTable}};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config::CONFIG, events::{broker, commands::{self, CommandEnvelope, CommandError}}, state::AppState};
+
+/// Where other services send their commands
+const COMMANDS_EXCHANGE: &str = "identity.commands";
+const COMMANDS_QUEUE: &str = "identity.commands";
+/// Failed commands wait here `COMMAND_RETRY_SECONDS`, then go back to `COMMANDS_EXCHANGE`
+const RETRY_EXCHANGE: &str = "identity.commands.retry";
+const RETRY_QUEUE: &str = "identity.commands.retry";
+/// Commands that can't be processed, kept for inspection
+const DEAD_EXCHANGE: &str = "identity.commands.dead";
+const DEAD_QUEUE: &str = "identity.commands.dead";
+
+const ATTEMPTS_HEADER: &str = "x-attempts";
+const ERROR_HEADER: &str = "x-last-error";
+/// Original sender of a command republished for a retry (the `user_id` property is then ours)
+const SENDER_HEADER: &str = "x-sender";
+
+const PREFETCH: u16 = 10;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Consumes commands until `shutdown` is cancelled, reconnecting while the broker is down.
+/// The message being handled when shutdown starts is finished and acknowledged first
+pub async fn run(state: AppState, shutdown: CancellationToken) {
+    loop {
+        match consume(&state, &shutdown).await {
+            Ok(()) => return,
+            Err(e) => eprintln!("command consumer: {}, reconnecting", e),
+        }
+
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+        }
+    }
+}
+
+async fn consume(state: &AppState, shutdown: &CancellationToken) -> anyhow::Result<()> {
+    let connection = broker::connect().await?;
+    let channel = connection.create_channel().await?;
+    declare_topology(&channel).await?;
+
+    channel.basic_qos(PREFETCH, BasicQosOptions::default()).await?;
+    let mut consumer = channel.basic_consume(COMMANDS_QUEUE, "identity_service", BasicConsumeOptions::default(), FieldTable::default()).await?;
+
+    loop {
+        let delivery = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            delivery = consumer.next() => delivery,
+        };
+        let Some(delivery) = delivery else {
+            anyhow::bail!("consumer stream ended")
+        };
+        process(state, &channel, delivery?).await?;
+    }
+
+    // prefetched messages that weren't acknowledged are requeued by the broker
+    channel.close(200, "shutting down").await?;
+    connection.close(200, "shutting down").await?;
+    Ok(())
+}
+
+async fn declare_topology(channel: &Channel) -> lapin::Result<()> {
+    let durable = ExchangeDeclareOptions { durable: true, ..Default::default() };
+    for exchange in [COMMANDS_EXCHANGE, RETRY_EXCHANGE, DEAD_EXCHANGE] {
+        channel.exchange_declare(exchange, ExchangeKind::Topic, durable, FieldTable::default()).await?;
+    }
+
+    let mut retry_arguments = FieldTable::default();
+    retry_arguments.insert("x-message-ttl".into(), AMQPValue::LongUInt(CONFIG.command_retry_time.as_millis() as u32));
+    retry_arguments.insert("x-dead-letter-exchange".into(), AMQPValue::LongString(COMMANDS_EXCHANGE.into()));
+
+    let durable = QueueDeclareOptions { durable: true, ..Default::default() };
+    for (queue, exchange, arguments) in [
+        (COMMANDS_QUEUE, COMMANDS_EXCHANGE, FieldTable::default()),
+        (RETRY_QUEUE, RETRY_EXCHANGE, retry_arguments),
+        (DEAD_QUEUE, DEAD_EXCHANGE, FieldTable::default()),
+    ] {
+        channel.queue_declare(queue, durable, arguments).await?;
+        channel.queue_bind(queue, exchange, "#", QueueBindOptions::default(), FieldTable::default()).await?;
+    }
+    Ok(())
+}
+
+/// Handles one delivery and always acknowledges it: on failure it is republished to the retry or dead exchange.
+/// Only broker errors are returned
+async fn process(state: &AppState, channel: &Channel, delivery: Delivery) -> lapin::Result<()> {
+    let result = match serde_json::from_slice::<CommandEnvelope>(&delivery.data) {
+        Ok(envelope) => match sender(&delivery) {
+            Some(sender) if commands::is_allowed(&sender, &envelope.command) => handle(state, envelope).await,
+            sender => Err(CommandError::Reject(format!("`{}` can't be sent by {:?}", envelope.command.command_type(), sender))),
+        },
+        Err(e) => Err(CommandError::Reject(format!("invalid command: {}", e))),
+    };
+
+    let attempts = attempts(&delivery) + 1;
+    match result {
+        Ok(()) => {},
+        Err(CommandError::Retry(e)) if attempts < CONFIG.command_max_attempts => {
+            republish(channel, &delivery, RETRY_EXCHANGE, attempts, &e).await?;
+        },
+        Err(CommandError::Retry(e) | CommandError::Reject(e)) => {
+            eprintln!("command dead-lettered after {} attempts: {}", attempts, e);
+            republish(channel, &delivery, DEAD_EXCHANGE, attempts, &e).await?;
+        },
+    }
+
+    delivery.ack(BasicAckOptions::default()).await
+}
+
+async fn handle(state: &AppState, envelope: CommandEnvelope) -> Result<(), CommandError> {
+    if state.processed_messages_service.is_processed(envelope.id).await? {
+        return Ok(())
+    }
+
+    let command_type = envelope.command.command_type();
+    commands::handle(state, envelope.command).await?;
+    state.processed_messages_service.mark_processed(envelope.id, command_type).await?;
+    Ok(())
+}
+
+/// The broker user that published the command. RabbitMQ rejects messages whose `user_id` property
+/// isn't the user of the publishing connection, so it can't be forged (`None` when it isn't set)
+fn sender(delivery: &Delivery) -> Option<String> {
+    let user_id = delivery.properties.user_id().as_ref()?.to_string();
+    if user_id != broker::username() {
+        return Some(user_id)
+    }
+
+    // republished by `republish`, unless the sender shares our broker user
+    let original = delivery.properties.headers()
+        .as_ref()
+        .and_then(|h| h.inner().get(SENDER_HEADER).cloned())
+        .and_then(|v| v.as_long_string().map(|s| s.to_string()))
+        .filter(|s| !s.is_empty());
+    Some(original.unwrap_or(user_id))
+}
+
+fn attempts(delivery: &Delivery) -> u32 {
+    delivery.properties.headers()
+        .as_ref()
+        .and_then(|h| h.inner().get(ATTEMPTS_HEADER).cloned())
+        .and_then(|v| v.as_long_uint())
+        .unwrap_or(0)
+}
+
+async fn republish(channel: &Channel, delivery: &Delivery, exchange: &str, attempts: u32, error: &str) -> lapin::Result<()> {
+    let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+    headers.insert(ATTEMPTS_HEADER.into(), AMQPValue::LongUInt(attempts));
+    headers.insert(ERROR_HEADER.into(), AMQPValue::LongString(error.into()));
+    headers.insert(SENDER_HEADER.into(), AMQPValue::LongString(sender(delivery).unwrap_or_default().into()));
+
+    // the broker only accepts our own user as `user_id`
+    let properties: BasicProperties = delivery.properties.clone()
+        .with_headers(headers)
+        .with_user_id(broker::username().into());
+    channel.basic_publish(exchange, delivery.routing_key.as_str(), BasicPublishOptions::default(), &delivery.data, properties)
+        .await?
+        .await
+        .map(|_| ())
+}
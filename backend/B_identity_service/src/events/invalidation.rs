@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use lapin::{options::{BasicConsumeOptions, QueueBindOptions, QueueDeclareOptions}, types::FieldTable};
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{events::{EXCHANGE, broker}, state::AppState};
+
+/// Events published with a bump of the user version, or of the entitlements version (`entitlement.changed`, only with `JWT_ACCESS_ENTITLEMENTS`)
+const ROUTING_KEYS: [&str; 5] = ["user.role_changed", "user.password_changed", "user.updated", "user.deleted", "entitlement.changed"];
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+struct ReceivedEvent {
+    data: ReceivedEventData,
+}
+
+#[derive(Deserialize)]
+struct ReceivedEventData {
+    user_id: uuid::Uuid,
+}
+
+/// Forgets the cached version of users changed by any instance, so tokens invalidated elsewhere are
+/// rejected here too without waiting for `USER_CACHE_SECONDS`. Each instance reads its own exclusive queue,
+/// missed events (broker down) are still bounded by the cache expiration
+pub async fn run(state: AppState, shutdown: CancellationToken) {
+    loop {
+        match consume(&state, &shutdown).await {
+            Ok(()) => return,
+            Err(e) => eprintln!("cache invalidation: {}, reconnecting", e),
+        }
+
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+        }
+    }
+}
+
+async fn consume(state: &AppState, shutdown: &CancellationToken) -> anyhow::Result<()> {
+    let connection = broker::connect().await?;
+    let channel = connection.create_channel().await?;
+
+    let options = QueueDeclareOptions { exclusive: true, auto_delete: true, ..Default::default() };
+    let queue = channel.queue_declare("", options, FieldTable::default()).await?;
+    for routing_key in ROUTING_KEYS {
+        channel.queue_bind(queue.name().as_str(), EXCHANGE, routing_key, QueueBindOptions::default(), FieldTable::default()).await?;
+    }
+
+    let options = BasicConsumeOptions { no_ack: true, exclusive: true, ..Default::default() };
+    let mut consumer = channel.basic_consume(queue.name().as_str(), "identity_service_cache", options, FieldTable::default()).await?;
+
+    loop {
+        let delivery = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            delivery = consumer.next() => delivery,
+        };
+        let Some(delivery) = delivery else {
+            anyhow::bail!("consumer stream ended")
+        };
+
+        match serde_json::from_slice::<ReceivedEvent>(&delivery?.data) {
+            Ok(event) => state.users_service.forget_version(event.data.user_id).await,
+            Err(e) => eprintln!("cache invalidation: unreadable event: {}", e),
+        }
+    }
+
+    connection.close(200, "shutting down").await?;
+    Ok(())
+}
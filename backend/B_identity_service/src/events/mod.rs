@@ -0,0 +1,171 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::entity::subscription::SubscriptionStatus;
+
+mod broker;
+mod publisher;
+mod commands;
+pub mod consumer;
+pub mod invalidation;
+
+pub use broker::EXCHANGE;
+pub use publisher::EventPublisher;
+
+/// Identity changes other services react to (e.g. Backend A creates profiles and enrollments).
+///
+/// Published on the `EXCHANGE` topic exchange with the event type as routing key, wrapped in an `EventEnvelope`.
+/// A breaking change to a payload must bump its `schema_version`
+pub enum IdentityEvent {
+    UserRegistered(UserRegistered),
+    UserUpdated(UserUpdated),
+    UserDeleted(UserDeleted),
+    PasswordChanged(PasswordChanged),
+    RoleChanged(RoleChanged),
+    OrderPaid(OrderPaid),
+    OrderRefunded(OrderRefunded),
+    SubscriptionChanged(SubscriptionChanged),
+    EntitlementChanged(EntitlementChanged),
+}
+
+impl IdentityEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Self::UserRegistered(_) => "user.registered",
+            Self::UserUpdated(_) => "user.updated",
+            Self::UserDeleted(_) => "user.deleted",
+            Self::PasswordChanged(_) => "user.password_changed",
+            Self::RoleChanged(_) => "user.role_changed",
+            Self::OrderPaid(_) => "order.paid",
+            Self::OrderRefunded(_) => "order.refunded",
+            Self::SubscriptionChanged(_) => "subscription.changed",
+            Self::EntitlementChanged(_) => "entitlement.changed",
+        }
+    }
+
+    pub fn schema_version(&self) -> i16 {
+        match self {
+            Self::UserRegistered(_) => 1,
+            Self::UserUpdated(_) => 1,
+            Self::UserDeleted(_) => 1,
+            Self::PasswordChanged(_) => 1,
+            Self::RoleChanged(_) => 1,
+            Self::OrderPaid(_) => 1,
+            Self::OrderRefunded(_) => 1,
+            Self::SubscriptionChanged(_) => 1,
+            Self::EntitlementChanged(_) => 1,
+        }
+    }
+
+    pub fn data(&self) -> serde_json::Value {
+        let data = match self {
+            Self::UserRegistered(e) => serde_json::to_value(e),
+            Self::UserUpdated(e) => serde_json::to_value(e),
+            Self::UserDeleted(e) => serde_json::to_value(e),
+            Self::PasswordChanged(e) => serde_json::to_value(e),
+            Self::RoleChanged(e) => serde_json::to_value(e),
+            Self::OrderPaid(e) => serde_json::to_value(e),
+            Self::OrderRefunded(e) => serde_json::to_value(e),
+            Self::SubscriptionChanged(e) => serde_json::to_value(e),
+            Self::EntitlementChanged(e) => serde_json::to_value(e),
+        };
+        data.unwrap_or_default() // plain structs, can't fail
+    }
+}
+
+#[derive(Serialize)]
+pub struct UserRegistered {
+    pub user_id: uuid::Uuid,
+    pub username: String,
+    pub email: String,
+    pub roles: Vec<String>,
+    pub creation_date: DateTime<Utc>,
+}
+
+/// Snapshot of the public profile after the change
+#[derive(Serialize)]
+pub struct UserUpdated {
+    pub user_id: uuid::Uuid,
+    pub username: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub avatar: Option<String>,
+    pub banner: Option<String>,
+    pub is_active: bool,
+}
+
+/// The account was anonymised, copies of its data must be purged
+#[derive(Serialize)]
+pub struct UserDeleted {
+    pub user_id: uuid::Uuid,
+    pub deletion_date: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct PasswordChanged {
+    pub user_id: uuid::Uuid,
+    pub change_date: DateTime<Utc>,
+}
+
+/// Every role the user has after the change
+#[derive(Serialize)]
+pub struct RoleChanged {
+    pub user_id: uuid::Uuid,
+    pub roles: Vec<String>,
+}
+
+/// The user bought the course
+#[derive(Serialize)]
+pub struct OrderPaid {
+    pub order_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub course_id: uuid::Uuid,
+    pub amount: i64, // minor units
+    pub currency: String,
+    pub payment_date: DateTime<Utc>,
+}
+
+/// One per succeeded refund, access to the course is revoked once the order is refunded in full
+#[derive(Serialize)]
+pub struct OrderRefunded {
+    pub order_id: uuid::Uuid,
+    pub refund_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub course_id: uuid::Uuid,
+    pub amount: i64, // minor units, of this refund
+    pub refunded_amount: i64, // of the order so far
+    pub currency: String,
+    pub fully_refunded: bool,
+    pub refund_date: DateTime<Utc>,
+}
+
+/// Snapshot of the subscription after the change
+#[derive(Serialize)]
+pub struct SubscriptionChanged {
+    pub subscription_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub plan: String, // code
+    pub status: SubscriptionStatus,
+    pub current_period_end: DateTime<Utc>,
+    pub cancel_at_period_end: bool,
+}
+
+/// Access to the course after an entitlement was granted or revoked
+#[derive(Serialize)]
+pub struct EntitlementChanged {
+    pub user_id: uuid::Uuid,
+    pub course_id: uuid::Uuid,
+    pub has_access: bool, // through any source
+}
+
+/// What is actually published
+#[derive(Serialize)]
+pub struct EventEnvelope<'a> {
+    pub id: uuid::Uuid, // outbox id, consumers deduplicate on it
+    #[serde(rename = "type")]
+    pub event_type: &'a str,
+    pub schema_version: i16,
+    pub occurred_at: DateTime<Utc>,
+    pub source: &'static str,
+    pub data: &'a serde_json::Value,
+}
@@ -0,0 +1,97 @@
+use chrono::Utc;
+use lapin::{BasicProperties, Channel, Connection, options::{BasicPublishOptions, ConfirmSelectOptions}};
+use sea_orm::DatabaseConnection;
+
+use crate::{config::CONFIG, error::{LocalErr, LocalResult, MapErrPrint}, events::{EXCHANGE, EventEnvelope, broker}, models::{entity::outbox_event, repository::outbox::OutboxRepository}};
+
+/// Events relayed per outbox poll
+const BATCH_SIZE: u64 = 100;
+
+/// Relays the outbox to RabbitMQ. While the broker is down events just wait in the outbox
+#[derive(Clone)]
+pub struct EventPublisher {
+    outbox: OutboxRepository,
+}
+
+impl EventPublisher {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { outbox: OutboxRepository::new(db) }
+    }
+
+    pub async fn run(self) {
+        let mut broker: Option<(Connection, Channel)> = None;
+        let mut interval = tokio::time::interval(CONFIG.outbox_poll_time);
+
+        loop {
+            interval.tick().await;
+
+            let channel = match &broker {
+                Some((connection, channel)) if connection.status().connected() && channel.status().connected() => channel,
+                _ => match Self::open_channel().await {
+                    Ok(opened) => &broker.insert(opened).1,
+                    Err(e) => {
+                        broker = None;
+                        eprintln!("event publisher can't reach rabbitmq: {}", e);
+                        continue;
+                    }
+                }
+            };
+
+            if let Err(e) = self.relay(channel).await {
+                eprintln!("event publisher: {}", e);
+            }
+        }
+    }
+
+    async fn open_channel() -> lapin::Result<(Connection, Channel)> {
+        let connection = broker::connect().await?;
+        let channel = connection.create_channel().await?;
+        channel.confirm_select(ConfirmSelectOptions::default()).await?;
+        Ok((connection, channel))
+    }
+
+    /// Publishes a batch in order, stopping at the first failure so later events don't overtake it
+    async fn relay(&self, channel: &Channel) -> LocalResult<()> {
+        let (txn, events) = self.outbox.lock_pending(BATCH_SIZE).await?;
+
+        for event in &events {
+            match Self::publish(channel, event).await {
+                Ok(()) => OutboxRepository::mark_published(&txn, event.id).await?,
+                Err(e) => {
+                    OutboxRepository::mark_failed(&txn, event.id, e.to_string()).await?;
+                    break;
+                }
+            }
+        }
+
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    async fn publish(channel: &Channel, event: &outbox_event::Model) -> anyhow::Result<()> {
+        let envelope = EventEnvelope {
+            id: event.id,
+            event_type: &event.event_type,
+            schema_version: event.schema_version,
+            occurred_at: event.creation_date.to_utc(),
+            source: "identity_service",
+            data: &event.payload,
+        };
+        let body = serde_json::to_vec(&envelope)?;
+
+        let properties = BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_delivery_mode(2) // persistent
+            .with_message_id(event.id.to_string().into())
+            .with_type(event.event_type.clone().into())
+            .with_timestamp(Utc::now().timestamp() as u64);
+
+        let confirmation = channel.basic_publish(EXCHANGE, &event.event_type, BasicPublishOptions::default(), &body, properties)
+            .await?
+            .await?;
+
+        match confirmation.is_ack() {
+            true => Ok(()),
+            false => anyhow::bail!("event {} was not acknowledged by the broker", event.id),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{extract::{ConnectInfo, FromRequestParts}, http::{HeaderMap, StatusCode}};
+use ipnet::IpNet;
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind}};
+
+/// Address of the client: the peer, or what `TRUSTED_PROXIES` put in `X-Forwarded-For`
+#[derive(Debug)]
+pub struct ClientIp(pub IpAddr);
+
+/// Walks `X-Forwarded-For` from the right (the entries appended by our proxies) and stops at the first
+/// untrusted address. Entries left of it were sent by the client and can't be trusted
+fn resolve(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|n| n.contains(ip));
+    if !is_trusted(&peer) {
+        return peer
+    }
+
+    let mut client = peer;
+    let forwarded = headers.get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .collect::<Vec<_>>();
+
+    for entry in forwarded.into_iter().rev() {
+        let Ok(ip) = entry.trim().parse::<IpAddr>() else { break };
+        client = ip;
+        if !is_trusted(&ip) {
+            break
+        }
+    }
+    client
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = LocalErr;
+
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .ok_or(LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+        Ok(Self(resolve(peer, &parts.headers, &CONFIG.trusted_proxies)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        assert_eq!(resolve(ip("203.0.113.7"), &forwarded("198.51.100.1"), &trusted), ip("203.0.113.7"));
+        assert_eq!(resolve(ip("10.0.0.2"), &forwarded("198.51.100.1"), &[]), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn stops_at_the_first_untrusted_forwarded_address() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        // the client made up 1.1.1.1, the load balancer appended the real address
+        let headers = forwarded("1.1.1.1, 198.51.100.1, 10.0.0.3");
+        assert_eq!(resolve(ip("10.0.0.2"), &headers, &trusted), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn malformed_entries_are_not_followed() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        assert_eq!(resolve(ip("10.0.0.2"), &forwarded("198.51.100.1, unknown"), &trusted), ip("10.0.0.2"));
+        assert_eq!(resolve(ip("10.0.0.2"), &HeaderMap::new(), &trusted), ip("10.0.0.2"));
+    }
+}
@@ -0,0 +1,29 @@
+use axum::extract::{Request, FromRequest};
+use axum::extract::rejection::FormRejection;
+use axum::http::StatusCode;
+use axum::extract::Form as AxumForm;
+
+use crate::error::LocalErr;
+
+/// `application/x-www-form-urlencoded` body
+pub struct Form<T>(pub T);
+
+impl<S, T> FromRequest<S> for Form<T>
+where
+    AxumForm<T>: FromRequest<S, Rejection = FormRejection>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, LocalErr);
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match AxumForm::<T>::from_request(req, state).await {
+            Ok(value) => Ok(Self(value.0)),
+            Err(rejection) => {
+                let status = rejection.status();
+                let err = LocalErr::from(rejection);
+    
+                Err((status, err))
+            }
+        }
+    }
+}
@@ -2,10 +2,18 @@ mod path;
 mod json;
 mod query;
 mod multipart;
+mod form;
+mod service_client;
+mod permission;
 mod user_id;
+mod client_ip;
 
 pub use path::Path;
 pub use json::Json;
 pub use query::Query;
 pub use multipart::Multipart;
+pub use form::Form;
+pub use service_client::ServiceClient;
+pub use permission::{RequirePermission, permissions};
 pub use user_id::*;
+pub use client_ip::ClientIp;
@@ -0,0 +1,65 @@
+use std::marker::PhantomData;
+
+use axum::{extract::FromRequestParts, http::{StatusCode, request::Parts}};
+
+use crate::{error::{LocalErr, LocalErrKind}, extract::UserId, state::AppState};
+
+/// A row of the `permissions` table
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+pub mod permissions {
+    use super::Permission;
+
+    pub struct ReadUsers;
+    impl Permission for ReadUsers {
+        const NAME: &'static str = "users.read";
+    }
+
+    pub struct ManageUsers;
+    impl Permission for ManageUsers {
+        const NAME: &'static str = "users.manage";
+    }
+
+    pub struct AssignRoles;
+    impl Permission for AssignRoles {
+        const NAME: &'static str = "roles.assign";
+    }
+
+    pub struct ManagePayments;
+    impl Permission for ManagePayments {
+        const NAME: &'static str = "payments.manage";
+    }
+
+    pub struct ManageEntitlements;
+    impl Permission for ManageEntitlements {
+        const NAME: &'static str = "entitlements.manage";
+    }
+
+    pub struct ManageCoupons;
+    impl Permission for ManageCoupons {
+        const NAME: &'static str = "coupons.manage";
+    }
+}
+
+/// Like `UserId`, but one of the user roles must grant `P` (403 otherwise)
+#[derive(Debug)]
+pub struct RequirePermission<P: Permission> {
+    pub user_id: uuid::Uuid,
+    _permission: PhantomData<P>,
+}
+
+impl<P: Permission> FromRequestParts<AppState> for RequirePermission<P> {
+    type Rejection = LocalErr;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let UserId(user_id) = UserId::from_request_parts(parts, state).await?;
+
+        if !state.roles_service.has_permission(user_id, P::NAME).await? {
+            return Err(LocalErr::new(LocalErrKind::MissingPermission, StatusCode::FORBIDDEN)
+                .with_msg(format!("missing permission `{}`", P::NAME)))
+        }
+        Ok(Self { user_id, _permission: PhantomData })
+    }
+}
@@ -0,0 +1,31 @@
+use axum::{extract::FromRequestParts, http::{StatusCode, header::AUTHORIZATION, request::Parts}};
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind}, utils::token::hash_token};
+
+/// Another backend, authenticated with HTTP Basic and the credentials in `INTERNAL_CLIENTS`
+#[derive(Debug)]
+pub struct ServiceClient(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for ServiceClient {
+    type Rejection = LocalErr;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let invalid = || LocalErr::new(LocalErrKind::InvalidClientCredentials, StatusCode::UNAUTHORIZED);
+
+        let credentials = parts.headers.get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Basic "))
+            .and_then(|b| STANDARD.decode(b).ok())
+            .and_then(|b| String::from_utf8(b).ok())
+            .ok_or_else(invalid)?;
+
+        let (id, secret) = credentials.split_once(':').ok_or_else(invalid)?;
+
+        // compare digests so the comparison time doesn't depend on the secret
+        match CONFIG.internal_clients.get(id) {
+            Some(expected) if hash_token(expected) == hash_token(secret) => Ok(Self(id.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
@@ -1,5 +1,16 @@
 use axum::{extract::FromRequestParts, http::{StatusCode, header::AUTHORIZATION}};
-use crate::{error::{LocalErr, LocalErrKind, MapErrPrint}, state::AppState};
+use sea_orm::{ColumnTrait, Condition};
+use crate::{config::{CONFIG, EmailVerificationPolicy}, error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, models::entity::user, state::AppState, utils::jwt::JwtClaims};
+
+/// Validates the access token and checks it was issued for the current user version (and courses)
+async fn validate_access_token(state: &AppState, token: &str) -> LocalResult<JwtClaims> {
+    let claims = state.jwt_service.validate_access_token(token)?;
+
+    if !state.users_service.is_current_access(claims.user_id, claims.version, claims.entitlements_version).await? {
+        return Err(LocalErr::new(LocalErrKind::InvalidAccessToken, StatusCode::UNAUTHORIZED));
+    }
+    Ok(claims)
+}
 
 #[derive(Debug)]
 pub struct UserId(pub uuid::Uuid);
@@ -19,7 +30,7 @@ impl FromRequestParts<AppState> for UserId {
             None => return Err(LocalErr::new(LocalErrKind::Unauthorized, StatusCode::UNAUTHORIZED))
         };
 
-        let claims = state.jwt_service.validate_access_token(token)?;
+        let claims = validate_access_token(state, token).await?;
         Ok(Self(claims.user_id))
     }
 }
@@ -38,7 +49,7 @@ impl FromRequestParts<AppState> for OptionalUserId {
         if let Some(t) = token {
             let token_without_prefix = &t["Bearer ".len()..]; // Strip the "Bearer " prefix
             
-            match state.jwt_service.validate_access_token(token_without_prefix) {
+            match validate_access_token(state, token_without_prefix).await {
                 Ok(claims) => Ok(Self(Some(claims.user_id))),
                 Err(_) => Ok(Self(None))
             }            
@@ -46,4 +57,28 @@ impl FromRequestParts<AppState> for OptionalUserId {
             Ok(Self(None))
         }
     }
+}
+
+/// Like `UserId`, but the user must also have verified their email (unless `EMAIL_VERIFICATION=off`)
+#[derive(Debug)]
+pub struct VerifiedUserId(pub uuid::Uuid);
+
+impl FromRequestParts<AppState> for VerifiedUserId {
+    type Rejection = LocalErr;
+
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let UserId(user_id) = UserId::from_request_parts(parts, state).await?;
+        if CONFIG.email_verification == EmailVerificationPolicy::Off {
+            return Ok(Self(user_id))
+        }
+
+        let verified = state.users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+            .await?
+            .is_some_and(|u| u.email_verified_at.is_some());
+
+        match verified {
+            true => Ok(Self(user_id)),
+            false => Err(LocalErr::new(LocalErrKind::EmailNotVerified, StatusCode::FORBIDDEN))
+        }
+    }
 }
\ No newline at end of file
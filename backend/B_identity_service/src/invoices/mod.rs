@@ -0,0 +1,148 @@
+use crate::models::entity::invoice::{self, InvoiceLine};
+
+mod pdf;
+
+use pdf::{Font, PAGE_HEIGHT, PAGE_WIDTH, Pdf};
+
+const MARGIN: f32 = 50.0;
+const LINE_HEIGHT: f32 = 14.0;
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// e.g. `12.50 EUR`, amounts are in cents
+pub fn format_amount(amount: i64, currency: &str) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    format!("{}{}.{:02} {}", sign, amount.abs() / 100, amount.abs() % 100, currency.to_uppercase())
+}
+
+/// e.g. `20%`, `5.5%`
+fn format_rate(basis_points: i32) -> String {
+    let percent = format!("{:.2}", basis_points as f64 / 100.0);
+    format!("{}%", percent.trim_end_matches('0').trim_end_matches('.'))
+}
+
+fn status(invoice: &invoice::Model) -> &'static str {
+    match invoice.credited_invoice_id {
+        Some(_) => "Refunded.",
+        None => "Paid.",
+    }
+}
+
+/// Self-contained page, printable as is
+pub fn render_html(invoice: &invoice::Model) -> String {
+    let number = escape_html(&invoice.invoice_number());
+    let title = invoice.title();
+    let currency = &invoice.currency;
+
+    let vat_id = invoice.seller_vat_id.as_deref()
+        .map(|id| format!("<br>VAT ID: {}", escape_html(id)))
+        .unwrap_or_default();
+    let lines: String = invoice.lines().iter()
+        .map(|line| format!(
+            "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td></tr>\n",
+            escape_html(&line.description),
+            line.quantity,
+            format_amount(line.unit_amount, currency),
+            format_amount(line.amount, currency),
+        ))
+        .collect();
+
+    format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>{title} {number}</title>\n\
+         <style>body{{font-family:sans-serif;max-width:800px;margin:40px auto}}table{{width:100%;border-collapse:collapse}}\
+         td,th{{padding:6px;border-bottom:1px solid #ddd;text-align:left}}.n{{text-align:right}}</style></head><body>\n\
+         <h1>{title} {number}</h1>\n<p>Issued on {date}</p>\n\
+         <p><strong>Seller</strong><br>{seller}<br>{seller_address}{vat_id}</p>\n\
+         <p><strong>Billed to</strong><br>{buyer}<br>{buyer_email}</p>\n\
+         <table>\n<tr><th>Description</th><th class=\"n\">Quantity</th><th class=\"n\">Unit price</th><th class=\"n\">Amount</th></tr>\n{lines}\
+         <tr><td colspan=\"3\" class=\"n\">Subtotal</td><td class=\"n\">{subtotal}</td></tr>\n\
+         <tr><td colspan=\"3\" class=\"n\">VAT {rate}</td><td class=\"n\">{tax}</td></tr>\n\
+         <tr><td colspan=\"3\" class=\"n\"><strong>Total</strong></td><td class=\"n\"><strong>{total}</strong></td></tr>\n\
+         </table>\n<p>{status}</p>\n</body></html>",
+        date = invoice.issue_date.format("%Y-%m-%d"),
+        status = status(invoice),
+        seller = escape_html(&invoice.seller_name),
+        seller_address = escape_html(&invoice.seller_address),
+        buyer = escape_html(&invoice.buyer_name),
+        buyer_email = escape_html(&invoice.buyer_email),
+        subtotal = format_amount(invoice.subtotal_amount, currency),
+        rate = format_rate(invoice.tax_rate),
+        tax = format_amount(invoice.tax_amount, currency),
+        total = format_amount(invoice.total_amount, currency),
+    )
+}
+
+/// Same content as `render_html`
+pub fn render_pdf(invoice: &invoice::Model) -> Vec<u8> {
+    let currency = &invoice.currency;
+    let mut pdf = Pdf::new();
+    let mut y = PAGE_HEIGHT - MARGIN;
+
+    pdf.text(MARGIN, y, 20.0, Font::Bold, &format!("{} {}", invoice.title(), invoice.invoice_number()));
+    y -= 2.0 * LINE_HEIGHT;
+    pdf.text(MARGIN, y, 10.0, Font::Regular, &format!("Issued on {}", invoice.issue_date.format("%Y-%m-%d")));
+    y -= 2.0 * LINE_HEIGHT;
+
+    let mut seller = vec![invoice.seller_name.clone(), invoice.seller_address.clone()];
+    seller.extend(invoice.seller_vat_id.as_ref().map(|id| format!("VAT ID: {}", id)));
+    let buyer = [invoice.buyer_name.clone(), invoice.buyer_email.clone()];
+
+    pdf.text(MARGIN, y, 10.0, Font::Bold, "Seller");
+    pdf.text(PAGE_WIDTH / 2.0, y, 10.0, Font::Bold, "Billed to");
+    for i in 0..seller.len().max(buyer.len()) {
+        y -= LINE_HEIGHT;
+        if let Some(text) = seller.get(i) {
+            pdf.text(MARGIN, y, 10.0, Font::Regular, text);
+        }
+        if let Some(text) = buyer.get(i) {
+            pdf.text(PAGE_WIDTH / 2.0, y, 10.0, Font::Regular, text);
+        }
+    }
+    y -= 2.0 * LINE_HEIGHT;
+
+    let columns = [MARGIN, 330.0, 380.0, 470.0];
+    let row = |pdf: &mut Pdf, y: f32, font: Font, cells: [&str; 4]| {
+        for (x, cell) in columns.iter().zip(cells) {
+            pdf.text(*x, y, 10.0, font, cell);
+        }
+    };
+
+    row(&mut pdf, y, Font::Bold, ["Description", "Qty", "Unit price", "Amount"]);
+    y -= 6.0;
+    pdf.line(MARGIN, y, PAGE_WIDTH - MARGIN, y);
+
+    let lines: Vec<InvoiceLine> = invoice.lines();
+    for line in &lines {
+        y -= LINE_HEIGHT;
+        if y < MARGIN + 4.0 * LINE_HEIGHT {
+            pdf.add_page();
+            y = PAGE_HEIGHT - MARGIN;
+        }
+        let quantity = line.quantity.to_string();
+        let unit_amount = format_amount(line.unit_amount, currency);
+        let amount = format_amount(line.amount, currency);
+        row(&mut pdf, y, Font::Regular, [&line.description, &quantity, &unit_amount, &amount]);
+    }
+
+    y -= 8.0;
+    pdf.line(MARGIN, y, PAGE_WIDTH - MARGIN, y);
+    let totals = [
+        ("Subtotal".to_string(), invoice.subtotal_amount, Font::Regular),
+        (format!("VAT {}", format_rate(invoice.tax_rate)), invoice.tax_amount, Font::Regular),
+        ("Total".to_string(), invoice.total_amount, Font::Bold),
+    ];
+    for (label, amount, font) in totals {
+        y -= LINE_HEIGHT;
+        pdf.text(columns[2], y, 10.0, font, &label);
+        pdf.text(columns[3], y, 10.0, font, &format_amount(amount, currency));
+    }
+
+    y -= 2.0 * LINE_HEIGHT;
+    pdf.text(MARGIN, y, 10.0, Font::Regular, status(invoice));
+    pdf.finish()
+}
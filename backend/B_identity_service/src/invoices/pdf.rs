@@ -0,0 +1,157 @@
+use std::fmt::Write;
+
+/// A4, in points
+pub const PAGE_WIDTH: f32 = 595.0;
+pub const PAGE_HEIGHT: f32 = 842.0;
+
+#[derive(Clone, Copy)]
+pub enum Font {
+    Regular,
+    Bold,
+}
+
+/// Minimal PDF 1.4 writer: text in the standard Helvetica fonts and lines, enough for invoices.
+/// Standard fonts need no embedding, text is encoded in WinAnsi (Latin-1 and `€`), other characters become `?`
+pub struct Pdf {
+    pages: Vec<String>, // content streams
+}
+
+/// PDF string literal in WinAnsi encoding
+fn encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('(');
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            },
+            ' '..='~' => out.push(c),
+            '€' => out.push_str("\\200"),
+            '\u{a0}'..='\u{ff}' => { let _ = write!(out, "\\{:03o}", c as u32); },
+            _ => out.push('?'),
+        }
+    }
+    out.push(')');
+    out
+}
+
+impl Pdf {
+    pub fn new() -> Self {
+        Self { pages: vec![String::new()] }
+    }
+
+    pub fn add_page(&mut self) {
+        self.pages.push(String::new());
+    }
+
+    /// `y` is the baseline, from the bottom of the page
+    pub fn text(&mut self, x: f32, y: f32, size: f32, font: Font, text: &str) {
+        let font = match font {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        };
+        let page = self.pages.last_mut().expect("there is always a page");
+        let _ = writeln!(page, "BT /{} {} Tf {} {} Td {} Tj ET", font, size, x, y, encode(text));
+    }
+
+    pub fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        let page = self.pages.last_mut().expect("there is always a page");
+        let _ = writeln!(page, "0.5 w {} {} m {} {} l S", x1, y1, x2, y2);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        // 1: catalog, 2: page tree, 3 and 4: fonts, then a page and its content for each page
+        let page_ids: Vec<usize> = (0..self.pages.len()).map(|i| 5 + 2 * i).collect();
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                page_ids.iter().map(|id| format!("{} 0 R", id)).collect::<Vec<_>>().join(" "),
+                page_ids.len(),
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_string(),
+        ];
+        for (content, id) in self.pages.iter().zip(&page_ids) {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH, PAGE_HEIGHT, id + 1,
+            ));
+            objects.push(format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content));
+        }
+
+        let mut out = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            let _ = write!(out, "{} 0 obj\n{}\nendobj\n", i + 1, object);
+        }
+
+        let xref = out.len();
+        let _ = write!(out, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(out, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(out, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref);
+
+        // only ASCII was written, non-ASCII text is escaped
+        out.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offsets listed in the xref table, and the one after `startxref`
+    fn xref(pdf: &str) -> (Vec<usize>, usize) {
+        let start: usize = pdf.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+        let offsets = pdf[start..].lines()
+            .skip(3) // `xref`, the subsection header and the free entry
+            .take_while(|l| l.ends_with(" n "))
+            .map(|l| l[..10].parse().unwrap())
+            .collect();
+        (offsets, start)
+    }
+
+    fn render(pages: usize) -> String {
+        let mut pdf = Pdf::new();
+        for i in 0..pages {
+            if i > 0 {
+                pdf.add_page();
+            }
+            pdf.text(50.0, 800.0, 12.0, Font::Bold, "Invoice (draft) 12.50 €");
+            pdf.text(50.0, 780.0, 10.0, Font::Regular, "Café crème, 日本");
+            pdf.line(50.0, 770.0, 545.0, 770.0);
+        }
+        String::from_utf8(pdf.finish()).unwrap()
+    }
+
+    #[test]
+    fn xref_offsets_point_at_their_objects() {
+        for pages in [1, 3] {
+            let pdf = render(pages);
+            let (offsets, start) = xref(&pdf);
+
+            assert_eq!(offsets.len(), 4 + 2 * pages);
+            for (i, offset) in offsets.iter().enumerate() {
+                assert!(pdf[*offset..].starts_with(&format!("{} 0 obj\n", i + 1)), "object {} isn't at {}", i + 1, offset);
+            }
+            assert!(pdf[start..].starts_with("xref\n"));
+        }
+    }
+
+    #[test]
+    fn output_is_ascii() {
+        assert!(render(1).is_ascii());
+    }
+
+    #[test]
+    fn text_is_escaped_in_winansi() {
+        assert_eq!(encode("a (b) \\ c"), "(a \\(b\\) \\\\ c)");
+        assert_eq!(encode("12 €"), "(12 \\200)");
+        assert_eq!(encode("é"), "(\\351)");
+        assert_eq!(encode("日本"), "(??)");
+    }
+}
@@ -0,0 +1,34 @@
+use crate::{config::CONFIG, error::LocalResult, state::AppState, utils::image::{self, ImageKind}};
+
+/// Accounts anonymised per sweep, the rest wait for the next one
+const BATCH_SIZE: u64 = 100;
+
+/// Anonymises the accounts whose deletion grace period is over, every `ACCOUNT_DELETION_SWEEP_MINUTES`
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(CONFIG.account_deletion_sweep_time);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = sweep(&state).await {
+            eprintln!("account deletion sweep failed: {}", e);
+        }
+    }
+}
+
+async fn sweep(state: &AppState) -> LocalResult<()> {
+    let users = state.users_service.anonymize_due_users(BATCH_SIZE).await?;
+
+    for user in &users {
+        if let Some(avatar) = &user.avatar {
+            image::delete_image(&state.storage, ImageKind::Avatar, avatar).await;
+        }
+        if let Some(banner) = &user.banner {
+            image::delete_image(&state.storage, ImageKind::Banner, banner).await;
+        }
+    }
+
+    if !users.is_empty() {
+        println!("anonymised {} deleted accounts", users.len());
+    }
+    Ok(())
+}
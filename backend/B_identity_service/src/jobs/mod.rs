@@ -0,0 +1 @@
+pub mod account_deletion;
\ No newline at end of file
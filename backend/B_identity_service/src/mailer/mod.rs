@@ -0,0 +1,19 @@
+use async_trait::async_trait;
+
+use crate::error::LocalResult;
+
+mod outbox;
+
+pub use outbox::OutboxMailer;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> LocalResult<()>;
+}
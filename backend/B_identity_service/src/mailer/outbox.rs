@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::Utc;
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, mailer::{Mail, Mailer}};
+
+/// Development mailer: writes every email to a file in `dir` instead of sending it
+pub struct OutboxMailer {
+    dir: PathBuf,
+}
+
+impl OutboxMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+}
+
+#[async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, mail: Mail) -> LocalResult<()> {
+        let filename = format!("{}-{}.eml", Utc::now().format("%Y%m%dT%H%M%S%.3f"), uuid::Uuid::new_v4());
+        let path = self.dir.join(filename);
+        let content = format!("To: {}\nSubject: {}\n\n{}\n", mail.to, mail.subject, mail.body);
+
+        tokio::fs::write(&path, content)
+            .await
+            .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+        println!("mail to {} written to {}", mail.to, path.display());
+        Ok(())
+    }
+}
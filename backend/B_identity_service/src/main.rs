@@ -1,5 +1,8 @@
+use std::net::SocketAddr;
+
 use axum::Router;
 use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 
 use crate::{config::CONFIG, state::AppState};
 
@@ -12,6 +15,13 @@ mod state;
 mod db;
 mod models;
 mod utils;
+mod mailer;
+mod storage;
+mod throttle;
+mod jobs;
+mod events;
+mod payments;
+mod invoices;
 mod extract;
 mod routes;
 mod openapi;
@@ -29,12 +39,20 @@ async fn main() {
         .await
         .expect("Failed to initialize app state");
 
+    tokio::spawn(app_state.jwt_service.clone().watch_keyring());
+    tokio::spawn(jobs::account_deletion::run(app_state.clone()));
+    tokio::spawn(app_state.publisher.clone().run());
+
+    let shutdown = CancellationToken::new();
+    let consumer = tokio::spawn(events::consumer::run(app_state.clone(), shutdown.clone()));
+    tokio::spawn(events::invalidation::run(app_state.clone(), shutdown.clone()));
+
     let app = Router::new()
         .merge(router::api_routes())
         .merge(router::swagger_routes())
         .with_state(app_state.clone())
         .layer(cors::cors())
-        .into_make_service();
+        .into_make_service_with_connect_info::<SocketAddr>();
     
     let listener = TcpListener::bind(CONFIG.socket.to_string())
         .await
@@ -43,16 +61,19 @@ async fn main() {
     println!("listening on http://{}", CONFIG.socket);
 
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(shutdown_signal(shutdown))
         .await
         .expect("Server error during shutdown");
 
+    let _ = consumer.await;
+
     app_state.close()
         .await
         .expect("Failed to close app state")
 }
 
-async fn shutdown_signal() {
+async fn shutdown_signal(shutdown: CancellationToken) {
     let _ = tokio::signal::ctrl_c().await;
     println!("Ctrl-C received, gracefully shutting down");
+    shutdown.cancel();
 }
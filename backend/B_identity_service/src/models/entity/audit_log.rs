@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+use strum::IntoStaticStr;
+
+/// Stored in `action`
+#[derive(Debug, Clone, Copy, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditAction {
+    UserViewed,
+    UserDeactivated,
+    UserReactivated,
+    UserLoggedOut,
+    PasswordResetForced,
+    RolesChanged,
+    EntitlementGranted,
+    EntitlementRevoked,
+    RefundIssued,
+}
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "audit_log")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid,
+    pub actor_id: Option<uuid::Uuid>,
+    pub action: String,
+    pub target_user_id: Option<uuid::Uuid>,
+    pub details: Json,
+    pub creation_date: DateTimeWithTimeZone,
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::TargetUserId",
+        to = "super::user::Column::Id",
+        on_delete = "SetNull"
+    )]
+    TargetUser,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TargetUser.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
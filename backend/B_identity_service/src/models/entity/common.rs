@@ -13,11 +13,11 @@ impl Password {
     pub fn hash_password(self) -> LocalResult<Self> {
         hash(self.0, 10)
             .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))
-            .map(|hashed| Self(hashed))
+            .map(Self)
     }
 
     pub fn verify_password(&self, rhs: &String) -> LocalResult<()> {
-        let result = verify(&rhs, &self.0)
+        let result = verify(rhs, &self.0)
             .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))?;
 
         match result {
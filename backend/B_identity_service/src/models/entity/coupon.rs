@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, EnumIter, DeriveActiveEnum, PartialEq, Eq, ToSchema)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(10))")]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountType {
+    #[sea_orm(string_value = "percent")]
+    Percent,
+    #[sea_orm(string_value = "fixed")]
+    Fixed,
+}
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "coupons")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid,
+    pub code: String,
+    pub discount_type: DiscountType,
+    pub discount_value: i64, // percent, or minor units
+    pub currency: Option<String>, // fixed discounts
+    pub expiration_date: Option<DateTimeWithTimeZone>,
+    pub max_redemptions: Option<i32>,
+    pub max_redemptions_per_user: i32,
+    pub is_active: bool,
+    pub created_by: Option<uuid::Uuid>,
+    pub creation_date: DateTimeWithTimeZone,
+}
+
+impl Model {
+    /// Amount taken off the price, `None` if the coupon can't apply to this currency.
+    /// Percentages are rounded down, the discount never exceeds the price
+    pub fn discount(&self, amount: i64, currency: &str) -> Option<i64> {
+        match self.discount_type {
+            DiscountType::Percent => Some(amount * self.discount_value / 100),
+            DiscountType::Fixed if self.currency.as_deref() == Some(currency) => Some(self.discount_value.min(amount)),
+            DiscountType::Fixed => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::coupon_target::Entity")]
+    CouponTarget,
+    #[sea_orm(has_many = "super::coupon_redemption::Entity")]
+    CouponRedemption,
+}
+
+impl Related<super::coupon_target::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::CouponTarget.def()
+    }
+}
+
+impl Related<super::coupon_redemption::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::CouponRedemption.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coupon(discount_type: DiscountType, discount_value: i64, currency: Option<&str>) -> Model {
+        Model {
+            id: uuid::Uuid::new_v4(),
+            code: "TEST".to_string(),
+            discount_type,
+            discount_value,
+            currency: currency.map(str::to_string),
+            expiration_date: None,
+            max_redemptions: None,
+            max_redemptions_per_user: 1,
+            is_active: true,
+            created_by: None,
+            creation_date: chrono::Utc::now().into(),
+        }
+    }
+
+    #[test]
+    fn percentages_are_rounded_down_in_any_currency() {
+        let coupon = coupon(DiscountType::Percent, 15, None);
+        assert_eq!(coupon.discount(999, "eur"), Some(149));
+        assert_eq!(coupon.discount(999, "usd"), Some(149));
+        assert_eq!(coupon.discount(6, "eur"), Some(0));
+    }
+
+    #[test]
+    fn full_percentages_take_the_whole_price() {
+        assert_eq!(coupon(DiscountType::Percent, 100, None).discount(4_999, "eur"), Some(4_999));
+    }
+
+    #[test]
+    fn fixed_discounts_never_exceed_the_price() {
+        let coupon = coupon(DiscountType::Fixed, 1_000, Some("eur"));
+        assert_eq!(coupon.discount(4_999, "eur"), Some(1_000));
+        assert_eq!(coupon.discount(500, "eur"), Some(500));
+    }
+
+    #[test]
+    fn fixed_discounts_only_apply_to_their_currency() {
+        assert_eq!(coupon(DiscountType::Fixed, 1_000, Some("eur")).discount(4_999, "usd"), None);
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "coupon_redemptions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid,
+    pub coupon_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub order_id: Option<uuid::Uuid>, // course purchases
+    pub provider: Option<String>,
+    pub provider_session_id: Option<String>, // none when the coupon covers the whole price
+    pub discount_amount: i64, // minor units
+    pub creation_date: DateTimeWithTimeZone,
+    pub release_date: Option<DateTimeWithTimeZone>,
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::coupon::Entity",
+        from = "Column::CouponId",
+        to = "super::coupon::Column::Id"
+    )]
+    Coupon,
+    #[sea_orm(
+        belongs_to = "super::order::Entity",
+        from = "Column::OrderId",
+        to = "super::order::Column::Id"
+    )]
+    Order,
+}
+
+impl Related<super::coupon::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Coupon.def()
+    }
+}
+
+impl Related<super::order::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Order.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, EnumIter, DeriveActiveEnum, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(10))")]
+#[serde(rename_all = "snake_case")]
+pub enum CouponTargetType {
+    #[sea_orm(string_value = "course")]
+    Course,
+    #[sea_orm(string_value = "plan")]
+    Plan,
+}
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "coupon_targets")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub coupon_id: uuid::Uuid,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub target_type: CouponTargetType,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub target_id: uuid::Uuid, // course id, or plan id
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::coupon::Entity",
+        from = "Column::CouponId",
+        to = "super::coupon::Column::Id"
+    )]
+    Coupon,
+}
+
+impl Related<super::coupon::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Coupon.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "course_prices")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub course_id: uuid::Uuid,
+    pub title: String,
+    pub amount: i64, // minor units
+    pub currency: String,
+    pub update_date: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
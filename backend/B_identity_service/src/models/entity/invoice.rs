@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+use utoipa::ToSchema;
+
+/// Item of the `lines` column, amounts are in minor units with taxes included
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct InvoiceLine {
+    pub description: String,
+    pub quantity: i64,
+    pub unit_amount: i64,
+    pub amount: i64, // negative for discounts
+}
+
+/// Rows can't be updated nor deleted (database trigger)
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "invoices")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid,
+    pub series: String,
+    pub number: i64,
+    pub user_id: uuid::Uuid,
+    pub order_id: Option<uuid::Uuid>,
+    pub subscription_id: Option<uuid::Uuid>,
+    pub provider: String,
+    pub provider_charge_id: String,
+    pub seller_name: String,
+    pub seller_address: String,
+    pub seller_vat_id: Option<String>,
+    pub buyer_name: String,
+    pub buyer_email: String,
+    pub lines: Json,
+    pub currency: String,
+    pub tax_rate: i32, // basis points
+    pub subtotal_amount: i64,
+    pub tax_amount: i64,
+    pub total_amount: i64,
+    pub issue_date: DateTimeWithTimeZone,
+    pub credited_invoice_id: Option<uuid::Uuid>, // credit notes, their amounts are negative
+    pub refund_id: Option<uuid::Uuid>,
+}
+
+impl Model {
+    /// e.g. `INV-2026-000042`
+    pub fn invoice_number(&self) -> String {
+        format!("{}-{:06}", self.series, self.number)
+    }
+
+    /// Invoice or credit note
+    pub fn title(&self) -> &'static str {
+        match self.credited_invoice_id {
+            Some(_) => "Credit note",
+            None => "Invoice",
+        }
+    }
+
+    pub fn lines(&self) -> Vec<InvoiceLine> {
+        serde_json::from_value(self.lines.clone()).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+    #[sea_orm(
+        belongs_to = "super::order::Entity",
+        from = "Column::OrderId",
+        to = "super::order::Column::Id"
+    )]
+    Order,
+    #[sea_orm(
+        belongs_to = "super::subscription::Entity",
+        from = "Column::SubscriptionId",
+        to = "super::subscription::Column::Id"
+    )]
+    Subscription,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl Related<super::order::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Order.def()
+    }
+}
+
+impl Related<super::subscription::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Subscription.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
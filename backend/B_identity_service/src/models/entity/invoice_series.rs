@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "invoice_series")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub code: String, // `<prefix>-<year>`
+    pub next_number: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,2 +1,27 @@
 pub mod user;
-pub mod common;
\ No newline at end of file
+pub mod common;
+pub mod session;
+pub mod user_token;
+pub mod user_mfa;
+pub mod mfa_recovery_code;
+pub mod login_attempt;
+pub mod role;
+pub mod role_permission;
+pub mod user_role;
+pub mod audit_log;
+pub mod outbox_event;
+pub mod processed_message;
+pub mod course_price;
+pub mod order;
+pub mod payment;
+pub mod payment_webhook_event;
+pub mod plan;
+pub mod subscription;
+pub mod subscription_checkout;
+pub mod entitlement;
+pub mod coupon;
+pub mod coupon_target;
+pub mod coupon_redemption;
+pub mod refund;
+pub mod invoice;
+pub mod invoice_series;
\ No newline at end of file
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, EnumIter, DeriveActiveEnum, PartialEq, Eq, ToSchema)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(20))")]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "paid")]
+    Paid,
+    #[sea_orm(string_value = "canceled")]
+    Canceled,
+    #[sea_orm(string_value = "refunded")]
+    Refunded, // in full, partial refunds leave the order paid
+    #[sea_orm(string_value = "disputed")]
+    Disputed,
+    #[sea_orm(string_value = "refund_due")]
+    RefundDue, // paid, but the course was already bought (or the order canceled): nothing is granted, the payment has to be refunded
+}
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "orders")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub course_id: uuid::Uuid,
+    pub amount: i64, // minor units, what is charged
+    pub discount_amount: i64, // taken off by a coupon
+    pub refunded_amount: i64,
+    pub currency: String,
+    pub status: OrderStatus,
+    pub creation_date: DateTimeWithTimeZone,
+    pub update_date: DateTimeWithTimeZone,
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+    #[sea_orm(has_many = "super::payment::Entity")]
+    Payment,
+    #[sea_orm(has_many = "super::refund::Entity")]
+    Refund,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl Related<super::payment::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Payment.def()
+    }
+}
+
+impl Related<super::refund::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Refund.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+
+/// `student`, `instructor` and `admin` are seeded by the migration
+pub const STUDENT: &str = "student";
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "roles")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
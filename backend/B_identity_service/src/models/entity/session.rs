@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "sessions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid, // refresh token `jti`
+    pub family_id: uuid::Uuid, // shared by every rotation of the same login
+    pub user_id: uuid::Uuid,
+    pub creation_date: DateTimeWithTimeZone,
+    pub expiration_date: DateTimeWithTimeZone,
+    pub revocation_date: Option<DateTimeWithTimeZone>,
+    pub replaced_by: Option<uuid::Uuid>,
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, EnumIter, DeriveActiveEnum, PartialEq, Eq, ToSchema)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(20))")]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    #[sea_orm(string_value = "trialing")]
+    Trialing,
+    #[sea_orm(string_value = "active")]
+    Active,
+    #[sea_orm(string_value = "past_due")]
+    PastDue, // renewal failed, the provider is retrying
+    #[sea_orm(string_value = "canceled")]
+    Canceled, // ended by the user (immediately or at the end of the period)
+    #[sea_orm(string_value = "expired")]
+    Expired, // ended because it was never paid
+}
+
+impl SubscriptionStatus {
+    /// Hasn't ended, a user has at most one
+    pub fn is_live(self) -> bool {
+        matches!(self, Self::Trialing | Self::Active | Self::PastDue)
+    }
+
+    /// Ended subscriptions never come back and a trial can't start again
+    pub fn can_become(self, next: Self) -> bool {
+        match (self, next) {
+            (Self::Canceled | Self::Expired, _) => self == next,
+            (_, Self::Trialing) => self == Self::Trialing,
+            _ => true,
+        }
+    }
+}
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "subscriptions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub plan_id: uuid::Uuid,
+    pub status: SubscriptionStatus,
+    pub provider: String,
+    pub provider_subscription_id: String,
+    pub current_period_start: DateTimeWithTimeZone,
+    pub current_period_end: DateTimeWithTimeZone,
+    pub trial_end: Option<DateTimeWithTimeZone>,
+    pub cancel_at_period_end: bool,
+    pub canceled_at: Option<DateTimeWithTimeZone>,
+    pub last_event_date: DateTimeWithTimeZone,
+    pub creation_date: DateTimeWithTimeZone,
+    pub update_date: DateTimeWithTimeZone,
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+    #[sea_orm(
+        belongs_to = "super::plan::Entity",
+        from = "Column::PlanId",
+        to = "super::plan::Column::Id"
+    )]
+    Plan,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl Related<super::plan::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Plan.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
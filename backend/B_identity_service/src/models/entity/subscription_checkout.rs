@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+
+use super::payment::PaymentStatus;
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "subscription_checkouts")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub plan_id: uuid::Uuid,
+    pub provider: String,
+    pub provider_session_id: String,
+    pub checkout_url: String,
+    pub status: PaymentStatus, // succeeded once the subscription is created at the provider
+    pub creation_date: DateTimeWithTimeZone,
+    pub update_date: DateTimeWithTimeZone,
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+    #[sea_orm(
+        belongs_to = "super::plan::Entity",
+        from = "Column::PlanId",
+        to = "super::plan::Column::Id"
+    )]
+    Plan,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl Related<super::plan::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Plan.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -23,6 +23,8 @@ pub struct Model {
     pub id: uuid::Uuid,
     #[sea_orm(default_expr = "uuid::Uuid::new_v4()")]
     pub version: uuid::Uuid,
+    #[sea_orm(default_expr = "uuid::Uuid::new_v4()")]
+    pub entitlements_version: uuid::Uuid, // courses in access tokens, see `EntitlementRepository::bump_entitlements_version`
     pub email: String,
     pub username: String,
     pub password_hash: Password,
@@ -32,7 +34,10 @@ pub struct Model {
     pub birth_date: chrono::NaiveDate,
     pub sex: UserSex,
     #[sea_orm(default_value = true)]
-    pub is_active: bool
+    pub is_active: bool,
+    pub email_verified_at: Option<DateTimeWithTimeZone>,
+    pub deletion_scheduled_at: Option<DateTimeWithTimeZone>,
+    pub deletion_date: Option<DateTimeWithTimeZone>,
 }
 
 #[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use sea_orm::entity::prelude::*;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, EnumIter, DeriveActiveEnum, PartialEq, Eq)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "UserTokenKind")]
+pub enum UserTokenKind {
+    #[sea_orm(string_value = "PasswordReset")]
+    PasswordReset,
+    #[sea_orm(string_value = "EmailVerification")]
+    EmailVerification,
+    #[sea_orm(string_value = "EmailChange")]
+    EmailChange,
+}
+
+#[derive(DeriveEntityModel, Debug, Clone, Serialize, Deserialize)]
+#[sea_orm(table_name = "user_tokens")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub kind: UserTokenKind,
+    pub token_hash: String, // sha256 hex, the token itself is only sent by email
+    pub payload: Option<String>, // new address for `EmailChange`
+    pub creation_date: DateTimeWithTimeZone,
+    pub expiration_date: DateTimeWithTimeZone,
+    pub use_date: Option<DateTimeWithTimeZone>,
+}
+
+#[derive(Clone, Copy, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
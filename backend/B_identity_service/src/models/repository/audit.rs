@@ -0,0 +1,66 @@
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+
+use crate::{error::{LocalErr, LocalResult, MapErrPrint}, models::entity::audit_log::{self, AuditAction}};
+
+
+#[derive(Clone)]
+pub struct AuditRepository {
+    db: DatabaseConnection
+}
+
+/// A staff action, recorded in the transaction of the action itself so neither is committed without the other
+pub struct AuditEntry {
+    pub actor_id: uuid::Uuid,
+    pub action: AuditAction,
+    pub target_user_id: uuid::Uuid,
+    pub details: serde_json::Value,
+}
+
+impl AuditEntry {
+    pub fn new(actor_id: uuid::Uuid, action: AuditAction, target_user_id: uuid::Uuid) -> Self {
+        Self { actor_id, action, target_user_id, details: serde_json::json!({}) }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+impl AuditRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Entries about the user, newest first
+    pub async fn get_user_events(&self, user_id: uuid::Uuid) -> LocalResult<Vec<audit_log::Model>> {
+        audit_log::Entity::find()
+            .filter(audit_log::Column::TargetUserId.eq(user_id))
+            .order_by_desc(audit_log::Column::CreationDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// For actions that change nothing (views), there is no transaction to join
+    pub async fn record_read(&self, entry: AuditEntry) -> LocalResult<()> {
+        Self::record(&self.db, entry).await
+    }
+
+    pub async fn record<C: ConnectionTrait>(db: &C, entry: AuditEntry) -> LocalResult<()> {
+        let action: &str = entry.action.into();
+
+        audit_log::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            actor_id: Set(Some(entry.actor_id)),
+            action: Set(action.to_string()),
+            target_user_id: Set(Some(entry.target_user_id)),
+            details: Set(entry.details),
+            creation_date: Set(chrono::Utc::now().into()),
+        }
+        .insert(db)
+        .await
+        .map_err_print(LocalErr::from)
+        .map(|_| ())
+    }
+}
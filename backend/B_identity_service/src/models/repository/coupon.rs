@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait, TransactionTrait, sea_query::Expr};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, models::entity::{coupon, coupon_redemption, coupon_target::{self, CouponTargetType}}};
+
+
+#[derive(Clone)]
+pub struct CouponRepository {
+    db: DatabaseConnection
+}
+
+/// Active and not expired
+fn check_usable(coupon: &coupon::Model) -> LocalResult<()> {
+    if !coupon.is_active {
+        return Err(LocalErr::new(LocalErrKind::UnknownCoupon, StatusCode::NOT_FOUND))
+    }
+    if coupon.expiration_date.is_some_and(|d| d.to_utc() <= Utc::now()) {
+        return Err(LocalErr::new(LocalErrKind::CouponExpired, StatusCode::BAD_REQUEST))
+    }
+    Ok(())
+}
+
+impl CouponRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Codes are matched case-insensitively
+    pub async fn get_coupon_by_code(&self, code: &str) -> LocalResult<Option<coupon::Model>> {
+        coupon::Entity::find()
+            .filter(coupon::Column::Code.eq(code.to_uppercase()))
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Newest first, with their targets and how many times they are used
+    pub async fn get_coupons(&self) -> LocalResult<Vec<(coupon::Model, Vec<coupon_target::Model>, i64)>> {
+        let coupons = coupon::Entity::find()
+            .find_with_related(coupon_target::Entity)
+            .order_by_desc(coupon::Column::CreationDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        let redemptions: HashMap<uuid::Uuid, i64> = coupon_redemption::Entity::find()
+            .select_only()
+            .column(coupon_redemption::Column::CouponId)
+            .column_as(coupon_redemption::Column::Id.count(), "count")
+            .filter(coupon_redemption::Column::ReleaseDate.is_null())
+            .group_by(coupon_redemption::Column::CouponId)
+            .into_tuple::<(uuid::Uuid, i64)>()
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?
+            .into_iter()
+            .collect();
+
+        Ok(coupons.into_iter()
+            .map(|(c, targets)| {
+                let count = redemptions.get(&c.id).copied().unwrap_or(0);
+                (c, targets, count)
+            })
+            .collect())
+    }
+
+    pub async fn create_coupon(&self, coupon: coupon::ActiveModel, targets: &[(CouponTargetType, uuid::Uuid)]) -> LocalResult<coupon::Model> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let coupon = coupon.insert(&txn).await.map_err_print(LocalErr::from)?;
+        if !targets.is_empty() {
+            coupon_target::Entity::insert_many(targets.iter().map(|(target_type, target_id)| coupon_target::ActiveModel {
+                coupon_id: Set(coupon.id),
+                target_type: Set(*target_type),
+                target_id: Set(*target_id),
+            }))
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+        }
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(coupon)
+    }
+
+    /// The coupon can't be used anymore, past redemptions are kept. Returns whether it was active
+    pub async fn deactivate_coupon(&self, coupon_id: uuid::Uuid) -> LocalResult<bool> {
+        coupon::Entity::update_many()
+            .col_expr(coupon::Column::IsActive, Expr::value(false))
+            .filter(coupon::Column::Id.eq(coupon_id))
+            .filter(coupon::Column::IsActive.eq(true))
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|r| r.rows_affected > 0)
+    }
+
+    /// Checks that the user can use the coupon on the course or plan, returning it with the discount on `amount`.
+    /// The use of the coupon by `replaced_session`, a checkout of the user about to be replaced, doesn't count.
+    /// The limits are checked again when the redemption is saved (see `reserve`)
+    pub async fn quote(&self, code: &str, user_id: uuid::Uuid, (target_type, target_id): (CouponTargetType, uuid::Uuid), amount: i64, currency: &str, replaced_session: Option<&str>) -> LocalResult<(coupon::Model, i64)> {
+        let coupon = self.get_coupon_by_code(code)
+            .await?
+            .ok_or(LocalErr::new(LocalErrKind::UnknownCoupon, StatusCode::NOT_FOUND))?;
+        check_usable(&coupon)?;
+
+        let targets = coupon_target::Entity::find()
+            .filter(coupon_target::Column::CouponId.eq(coupon.id))
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+        if !targets.is_empty() && !targets.iter().any(|t| t.target_type == target_type && t.target_id == target_id) {
+            return Err(LocalErr::new(LocalErrKind::CouponNotApplicable, StatusCode::BAD_REQUEST)
+                .with_msg("the coupon is restricted to other courses or plans"))
+        }
+
+        let discount = coupon.discount(amount, currency)
+            .ok_or(LocalErr::new(LocalErrKind::CouponNotApplicable, StatusCode::BAD_REQUEST)
+                .with_msg(format!("the coupon only applies to prices in {}", coupon.currency.as_deref().unwrap_or_default().to_uppercase())))?;
+
+        Self::check_limits(&self.db, &coupon, user_id, replaced_session).await?;
+        Ok((coupon, discount))
+    }
+
+    /// The coupon row is locked until the end of the transaction, so concurrent checkouts can't go over its limits
+    pub async fn redeem<C: ConnectionTrait>(db: &C, redemption: coupon_redemption::Model) -> LocalResult<()> {
+        let coupon = coupon::Entity::find_by_id(redemption.coupon_id)
+            .lock_exclusive()
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)?
+            .ok_or(LocalErr::new(LocalErrKind::UnknownCoupon, StatusCode::NOT_FOUND))?;
+        check_usable(&coupon)?;
+        Self::check_limits(db, &coupon, redemption.user_id, None).await?;
+
+        redemption.into_active_model()
+            .reset_all()
+            .insert(db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    /// Saves the use of the coupon before its checkout session is opened, so no session is opened with a coupon
+    /// that went over its limits in the meantime. It is then tied to the session (see `attach`), or given back
+    /// with `release_reservation` if the session couldn't be opened
+    pub async fn reserve(&self, redemption: coupon_redemption::Model) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+        Self::redeem(&txn, redemption).await?;
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Ties a reserved use to the checkout session opened with it, and to the order of a course
+    pub async fn attach<C: ConnectionTrait>(db: &C, redemption_id: uuid::Uuid, order_id: Option<uuid::Uuid>, provider: &str, session_id: &str) -> LocalResult<()> {
+        coupon_redemption::Entity::update_many()
+            .col_expr(coupon_redemption::Column::OrderId, Expr::value(order_id))
+            .col_expr(coupon_redemption::Column::Provider, Expr::value(provider))
+            .col_expr(coupon_redemption::Column::ProviderSessionId, Expr::value(session_id))
+            .filter(coupon_redemption::Column::Id.eq(redemption_id))
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    pub async fn release_reservation(&self, redemption_id: uuid::Uuid) -> LocalResult<()> {
+        coupon_redemption::Entity::update_many()
+            .col_expr(coupon_redemption::Column::ReleaseDate, Expr::value(Utc::now()))
+            .filter(coupon_redemption::Column::Id.eq(redemption_id))
+            .filter(coupon_redemption::Column::ReleaseDate.is_null())
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    /// A no-op if no coupon was used with the checkout session
+    pub async fn release<C: ConnectionTrait>(db: &C, provider: &str, session_id: &str) -> LocalResult<()> {
+        coupon_redemption::Entity::update_many()
+            .col_expr(coupon_redemption::Column::ReleaseDate, Expr::value(Utc::now()))
+            .filter(coupon_redemption::Column::Provider.eq(provider))
+            .filter(coupon_redemption::Column::ProviderSessionId.eq(session_id))
+            .filter(coupon_redemption::Column::ReleaseDate.is_null())
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    async fn check_limits<C: ConnectionTrait>(db: &C, coupon: &coupon::Model, user_id: uuid::Uuid, except_session: Option<&str>) -> LocalResult<()> {
+        let redemptions = || coupon_redemption::Entity::find()
+            .filter(coupon_redemption::Column::CouponId.eq(coupon.id))
+            .filter(coupon_redemption::Column::ReleaseDate.is_null())
+            .apply_if(except_session, |query, session_id| query.filter(Condition::any()
+                .add(coupon_redemption::Column::ProviderSessionId.is_null())
+                .add(coupon_redemption::Column::ProviderSessionId.ne(session_id))));
+
+        if let Some(max) = coupon.max_redemptions {
+            let total = redemptions().count(db).await.map_err_print(LocalErr::from)?;
+            if total >= max as u64 {
+                return Err(LocalErr::new(LocalErrKind::CouponExhausted, StatusCode::CONFLICT))
+            }
+        }
+
+        let used = redemptions()
+            .filter(coupon_redemption::Column::UserId.eq(user_id))
+            .count(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+        if used >= coupon.max_redemptions_per_user as u64 {
+            return Err(LocalErr::new(LocalErrKind::CouponExhausted, StatusCode::CONFLICT)
+                .with_msg("the coupon was already used the maximum number of times on this account"))
+        }
+        Ok(())
+    }
+}
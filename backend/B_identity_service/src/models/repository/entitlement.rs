@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait, sea_query::{Expr, OnConflict}};
+
+use crate::{config::CONFIG, error::{LocalErr, LocalResult, MapErrPrint}, events::{EntitlementChanged, IdentityEvent}, models::{entity::{entitlement::{self, EntitlementSource}, user}, repository::{audit::{AuditEntry, AuditRepository}, outbox::OutboxRepository}}};
+
+
+#[derive(Clone)]
+pub struct EntitlementRepository {
+    db: DatabaseConnection
+}
+
+impl EntitlementRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Entitlements that aren't revoked, oldest first
+    pub async fn get_user_entitlements(&self, user_id: uuid::Uuid) -> LocalResult<Vec<entitlement::Model>> {
+        entitlement::Entity::find()
+            .filter(entitlement::Column::UserId.eq(user_id))
+            .filter(entitlement::Column::RevocationDate.is_null())
+            .order_by_asc(entitlement::Column::CreationDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Every course the user can access
+    pub async fn get_user_courses(&self, user_id: uuid::Uuid) -> LocalResult<Vec<uuid::Uuid>> {
+        entitlement::Entity::find()
+            .select_only()
+            .column(entitlement::Column::CourseId)
+            .distinct()
+            .filter(entitlement::Column::UserId.eq(user_id))
+            .filter(entitlement::Column::RevocationDate.is_null())
+            .into_tuple()
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// The `(user_id, course_id)` pairs with access
+    pub async fn check_access(&self, pairs: &[(uuid::Uuid, uuid::Uuid)]) -> LocalResult<HashSet<(uuid::Uuid, uuid::Uuid)>> {
+        if pairs.is_empty() {
+            return Ok(HashSet::new())
+        }
+
+        let users: HashSet<_> = pairs.iter().map(|(u, _)| *u).collect();
+        let courses: HashSet<_> = pairs.iter().map(|(_, c)| *c).collect();
+
+        let granted: Vec<(uuid::Uuid, uuid::Uuid)> = entitlement::Entity::find()
+            .select_only()
+            .column(entitlement::Column::UserId)
+            .column(entitlement::Column::CourseId)
+            .filter(entitlement::Column::UserId.is_in(users))
+            .filter(entitlement::Column::CourseId.is_in(courses))
+            .filter(entitlement::Column::RevocationDate.is_null())
+            .into_tuple()
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        let requested: HashSet<_> = pairs.iter().copied().collect();
+        Ok(granted.into_iter().filter(|p| requested.contains(p)).collect())
+    }
+
+    /// Staff grant, recorded in the audit log by the same transaction
+    pub async fn grant_course(&self, user_id: uuid::Uuid, course_id: uuid::Uuid, audit: AuditEntry) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+        Self::grant(&txn, user_id, course_id, EntitlementSource::Grant, None, Some(audit.actor_id)).await?;
+        AuditRepository::record(&txn, audit).await?;
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Revokes the staff grant only, returns whether there was one (`audit` is only recorded if so)
+    pub async fn revoke_grant(&self, user_id: uuid::Uuid, course_id: uuid::Uuid, audit: AuditEntry) -> LocalResult<bool> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+        let revoked = Self::revoke(&txn, user_id, course_id, EntitlementSource::Grant).await?;
+        if revoked {
+            AuditRepository::record(&txn, audit).await?;
+        }
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(revoked)
+    }
+
+    /// Creates the entitlement, or restores it if it was revoked, queuing `entitlement.changed` (see `bump_entitlements_version`)
+    pub async fn grant<C: ConnectionTrait>(db: &C, user_id: uuid::Uuid, course_id: uuid::Uuid, source: EntitlementSource, order_id: Option<uuid::Uuid>, granted_by: Option<uuid::Uuid>) -> LocalResult<()> {
+        let entitlement = entitlement::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            user_id: Set(user_id),
+            course_id: Set(course_id),
+            source: Set(source),
+            order_id: Set(order_id),
+            granted_by: Set(granted_by),
+            creation_date: Set(Utc::now().into()),
+            revocation_date: Set(None),
+        };
+
+        entitlement::Entity::insert(entitlement)
+            .on_conflict(
+                OnConflict::columns([entitlement::Column::UserId, entitlement::Column::CourseId, entitlement::Column::Source])
+                    .update_columns([entitlement::Column::OrderId, entitlement::Column::GrantedBy, entitlement::Column::CreationDate, entitlement::Column::RevocationDate])
+                    .to_owned()
+            )
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        Self::bump_entitlements_version(db, user_id).await?;
+        OutboxRepository::add_event(db, IdentityEvent::EntitlementChanged(EntitlementChanged { user_id, course_id, has_access: true })).await
+    }
+
+    /// Queues `entitlement.changed` if there was an entitlement from `source`, returning whether there was one
+    pub async fn revoke<C: ConnectionTrait>(db: &C, user_id: uuid::Uuid, course_id: uuid::Uuid, source: EntitlementSource) -> LocalResult<bool> {
+        let result = entitlement::Entity::update_many()
+            .col_expr(entitlement::Column::RevocationDate, Expr::value(Utc::now()))
+            .filter(entitlement::Column::UserId.eq(user_id))
+            .filter(entitlement::Column::CourseId.eq(course_id))
+            .filter(entitlement::Column::Source.eq(source))
+            .filter(entitlement::Column::RevocationDate.is_null())
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        if result.rows_affected == 0 {
+            return Ok(false)
+        }
+
+        // access through another source is kept
+        let remaining = entitlement::Entity::find()
+            .filter(entitlement::Column::UserId.eq(user_id))
+            .filter(entitlement::Column::CourseId.eq(course_id))
+            .filter(entitlement::Column::RevocationDate.is_null())
+            .count(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        if remaining == 0 {
+            Self::bump_entitlements_version(db, user_id).await?;
+        }
+        OutboxRepository::add_event(db, IdentityEvent::EntitlementChanged(EntitlementChanged { user_id, course_id, has_access: remaining > 0 })).await?;
+        Ok(true)
+    }
+
+    /// With `JWT_ACCESS_ENTITLEMENTS`, access tokens carry the courses: they stop being accepted, and refreshing gets the new ones
+    /// (the user stays logged in). Other instances (and this one) forget the cached versions when `entitlement.changed` is published (see `events::invalidation`)
+    async fn bump_entitlements_version<C: ConnectionTrait>(db: &C, user_id: uuid::Uuid) -> LocalResult<()> {
+        if !CONFIG.jwt_access_entitlements {
+            return Ok(())
+        }
+
+        user::Entity::update_many()
+            .col_expr(user::Column::EntitlementsVersion, Expr::value(uuid::Uuid::new_v4()))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+}
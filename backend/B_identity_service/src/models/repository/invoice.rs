@@ -0,0 +1,303 @@
+use axum::http::StatusCode;
+use chrono::{Datelike, Utc};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, TransactionTrait, sea_query::{Expr, OnConflict}};
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, models::{entity::{course_price, invoice::{self, InvoiceLine}, invoice_series, order, plan, refund, subscription, user}, repository::payment_webhook::PaymentWebhookRepository}, payments::{ProviderCharge, ProviderRefund}};
+
+
+#[derive(Clone)]
+pub struct InvoiceRepository {
+    db: DatabaseConnection
+}
+
+/// What was charged (or refunded, for credit notes), to be numbered
+struct NewInvoice {
+    user_id: uuid::Uuid,
+    order_id: Option<uuid::Uuid>,
+    subscription_id: Option<uuid::Uuid>,
+    provider: String,
+    provider_charge_id: String,
+    buyer_name: String,
+    buyer_email: String,
+    lines: Vec<InvoiceLine>,
+    currency: String,
+    tax_rate: i32,
+    total_amount: i64, // taxes included
+    credited_invoice_id: Option<uuid::Uuid>,
+    refund_id: Option<uuid::Uuid>,
+}
+
+/// Part of `total` (taxes included) that is taxes, rounded to the nearest minor unit (away from zero for credit notes)
+fn tax_part(total: i64, rate: i32) -> i64 {
+    let divisor = 10_000 + rate as i64;
+    let untaxed = (total.abs() * 10_000 + divisor / 2) / divisor;
+    total.signum() * (total.abs() - untaxed)
+}
+
+async fn buyer<C: ConnectionTrait>(db: &C, user_id: uuid::Uuid) -> LocalResult<user::Model> {
+    user::Entity::find_by_id(user_id)
+        .one(db)
+        .await
+        .map_err_print(LocalErr::from)?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))
+}
+
+impl InvoiceRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Newest first
+    pub async fn get_user_invoices(&self, user_id: uuid::Uuid) -> LocalResult<Vec<invoice::Model>> {
+        invoice::Entity::find()
+            .filter(invoice::Column::UserId.eq(user_id))
+            .order_by_desc(invoice::Column::IssueDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    pub async fn get_user_invoice(&self, user_id: uuid::Uuid, invoice_id: uuid::Uuid) -> LocalResult<Option<invoice::Model>> {
+        invoice::Entity::find_by_id(invoice_id)
+            .filter(invoice::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Invoices the payment of a subscription, marking the webhook event that reported it
+    /// as processed in the same transaction. A charge already invoiced is skipped
+    pub async fn issue_subscription_invoice(&self, event_id: &str, provider: &str, charge: &ProviderCharge) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let (subscription, plan) = subscription::Entity::find()
+            .find_also_related(plan::Entity)
+            .filter(subscription::Column::Provider.eq(provider))
+            .filter(subscription::Column::ProviderSubscriptionId.eq(charge.subscription_id.clone()))
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?
+            .and_then(|(s, p)| Some((s, p?)))
+            // the provider retries the event once the subscription is known
+            .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND).with_msg("no subscription for this charge"))?;
+
+        let buyer = buyer(&txn, subscription.user_id).await?;
+        Self::issue(&txn, NewInvoice {
+            user_id: subscription.user_id,
+            order_id: None,
+            subscription_id: Some(subscription.id),
+            provider: provider.to_string(),
+            provider_charge_id: charge.id.clone(),
+            buyer_name: buyer.username,
+            buyer_email: buyer.email,
+            lines: vec![InvoiceLine {
+                description: format!("{} subscription", plan.name),
+                quantity: 1,
+                unit_amount: charge.amount,
+                amount: charge.amount,
+            }],
+            currency: charge.currency.clone(),
+            tax_rate: CONFIG.invoice_tax_rate,
+            total_amount: charge.amount,
+            credited_invoice_id: None,
+            refund_id: None,
+        }).await?;
+
+        PaymentWebhookRepository::mark_processed(&txn, event_id).await?;
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Invoices a course order paid through the checkout session `charge_id`, the coupon shows as a line of its own
+    pub async fn issue_order_invoice<C: ConnectionTrait>(db: &C, order: &order::Model, provider: &str, charge_id: &str) -> LocalResult<()> {
+        let title = course_price::Entity::find_by_id(order.course_id)
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)?
+            .map(|p| p.title)
+            .unwrap_or_else(|| format!("Course {}", order.course_id));
+
+        let price = order.amount + order.discount_amount;
+        let mut lines = vec![InvoiceLine { description: title, quantity: 1, unit_amount: price, amount: price }];
+        if order.discount_amount > 0 {
+            lines.push(InvoiceLine { description: "Discount".to_string(), quantity: 1, unit_amount: -order.discount_amount, amount: -order.discount_amount });
+        }
+
+        let buyer = buyer(db, order.user_id).await?;
+        Self::issue(db, NewInvoice {
+            user_id: order.user_id,
+            order_id: Some(order.id),
+            subscription_id: None,
+            provider: provider.to_string(),
+            provider_charge_id: charge_id.to_string(),
+            buyer_name: buyer.username,
+            buyer_email: buyer.email,
+            lines,
+            currency: order.currency.clone(),
+            tax_rate: CONFIG.invoice_tax_rate,
+            total_amount: order.amount,
+            credited_invoice_id: None,
+            refund_id: None,
+        }).await
+    }
+
+    /// Credits the invoice of the order for the refund, at the tax rate and to the buyer it was issued with.
+    /// Nothing is issued if the order wasn't invoiced, or if the refund already was credited
+    pub async fn issue_credit_note<C: ConnectionTrait>(db: &C, refund: &refund::Model) -> LocalResult<()> {
+        let credited = invoice::Entity::find()
+            .filter(invoice::Column::OrderId.eq(refund.order_id))
+            .filter(invoice::Column::CreditedInvoiceId.is_null())
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+        let Some(credited) = credited else {
+            return Ok(())
+        };
+
+        Self::credit(db, &credited, &refund.provider, refund.id.to_string(), refund.amount, Some(refund.id)).await
+    }
+
+    /// Credits the invoice of a subscription charge refunded at the provider (there is no refund row for those),
+    /// e.g. a duplicate subscription ended right away. Nothing is issued if the charge wasn't invoiced
+    pub async fn issue_charge_credit_note<C: ConnectionTrait>(db: &C, provider: &str, reported: &ProviderRefund) -> LocalResult<()> {
+        let Some(charge_id) = &reported.charge_id else {
+            return Ok(())
+        };
+        let credited = invoice::Entity::find()
+            .filter(invoice::Column::Provider.eq(provider))
+            .filter(invoice::Column::ProviderChargeId.eq(charge_id))
+            .filter(invoice::Column::CreditedInvoiceId.is_null())
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+        let Some(credited) = credited else {
+            return Ok(())
+        };
+
+        Self::credit(db, &credited, provider, reported.id.clone(), reported.amount, None).await
+    }
+
+    /// Credit note of `amount` on the invoice, `charge_id` tells refunds apart so each is credited once
+    async fn credit<C: ConnectionTrait>(db: &C, credited: &invoice::Model, provider: &str, charge_id: String, amount: i64, refund_id: Option<uuid::Uuid>) -> LocalResult<()> {
+        Self::issue(db, NewInvoice {
+            user_id: credited.user_id,
+            order_id: credited.order_id,
+            subscription_id: credited.subscription_id,
+            provider: provider.to_string(),
+            provider_charge_id: charge_id,
+            buyer_name: credited.buyer_name.clone(),
+            buyer_email: credited.buyer_email.clone(),
+            lines: vec![InvoiceLine {
+                description: format!("Refund of invoice {}", credited.invoice_number()),
+                quantity: 1,
+                unit_amount: -amount,
+                amount: -amount,
+            }],
+            currency: credited.currency.clone(),
+            tax_rate: credited.tax_rate,
+            total_amount: -amount,
+            credited_invoice_id: Some(credited.id),
+            refund_id,
+        }).await
+    }
+
+    /// Takes the next number of the series of the year. The series row stays locked until the end of the
+    /// transaction, so concurrent invoices wait for it, and a rolled back invoice gives its number back
+    async fn issue<C: ConnectionTrait>(db: &C, new: NewInvoice) -> LocalResult<()> {
+        let already_issued = invoice::Entity::find()
+            .filter(invoice::Column::Provider.eq(&new.provider))
+            .filter(invoice::Column::ProviderChargeId.eq(&new.provider_charge_id))
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)?
+            .is_some();
+        if already_issued {
+            return Ok(())
+        }
+
+        let now = Utc::now();
+        let series = match new.credited_invoice_id {
+            Some(_) => format!("{}-CN-{}", CONFIG.invoice_series_prefix, now.year()),
+            None => format!("{}-{}", CONFIG.invoice_series_prefix, now.year()),
+        };
+        invoice_series::Entity::insert(invoice_series::ActiveModel {
+            code: Set(series.clone()),
+            next_number: Set(1),
+        })
+        .on_conflict(OnConflict::column(invoice_series::Column::Code).do_nothing().to_owned())
+        .exec_without_returning(db)
+        .await
+        .map_err_print(LocalErr::from)?;
+
+        let number = invoice_series::Entity::update_many()
+            .col_expr(invoice_series::Column::NextNumber, Expr::col(invoice_series::Column::NextNumber).add(1))
+            .filter(invoice_series::Column::Code.eq(&series))
+            .exec_with_returning(db)
+            .await
+            .map_err_print(LocalErr::from)?
+            .into_iter()
+            .next()
+            .map(|s| s.next_number - 1)
+            .ok_or(LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+        let tax_amount = tax_part(new.total_amount, new.tax_rate);
+        invoice::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            series: Set(series),
+            number: Set(number),
+            user_id: Set(new.user_id),
+            order_id: Set(new.order_id),
+            subscription_id: Set(new.subscription_id),
+            provider: Set(new.provider),
+            provider_charge_id: Set(new.provider_charge_id),
+            seller_name: Set(CONFIG.seller_name.clone()),
+            seller_address: Set(CONFIG.seller_address.clone()),
+            seller_vat_id: Set(CONFIG.seller_vat_id.clone()),
+            buyer_name: Set(new.buyer_name),
+            buyer_email: Set(new.buyer_email),
+            lines: Set(serde_json::to_value(&new.lines).unwrap_or_default()),
+            currency: Set(new.currency),
+            tax_rate: Set(new.tax_rate),
+            subtotal_amount: Set(new.total_amount - tax_amount),
+            tax_amount: Set(tax_amount),
+            total_amount: Set(new.total_amount),
+            issue_date: Set(now.into()),
+            credited_invoice_id: Set(new.credited_invoice_id),
+            refund_id: Set(new.refund_id),
+        }
+        .insert(db)
+        .await
+        .map_err_print(LocalErr::from)
+        .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taxes_are_taken_out_of_the_total() {
+        assert_eq!(tax_part(12_000, 2_000), 2_000);
+        assert_eq!(tax_part(4_999, 2_000), 833); // 4166 without taxes
+        assert_eq!(tax_part(1_055, 550), 55);
+    }
+
+    #[test]
+    fn taxes_are_rounded_to_the_nearest_minor_unit() {
+        assert_eq!(tax_part(1, 2_000), 0);
+        assert_eq!(tax_part(999, 2_000), 166); // 832.5 without taxes, rounded up
+    }
+
+    #[test]
+    fn credit_notes_mirror_the_taxes_of_invoices() {
+        for total in [1, 999, 4_999, 12_000] {
+            assert_eq!(tax_part(-total, 2_000), -tax_part(total, 2_000));
+        }
+    }
+
+    #[test]
+    fn nothing_is_taxed_without_a_rate() {
+        assert_eq!(tax_part(4_999, 0), 0);
+        assert_eq!(tax_part(0, 2_000), 0);
+    }
+}
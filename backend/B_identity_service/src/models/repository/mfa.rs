@@ -0,0 +1,170 @@
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait, sea_query::{Expr, OnConflict}};
+
+use crate::{error::{LocalErr, LocalResult, MapErrPrint}, models::entity::{mfa_recovery_code, user_mfa}, utils::{token, totp}};
+
+
+#[derive(Clone)]
+pub struct MfaRepository {
+    db: DatabaseConnection
+}
+
+impl MfaRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn get_mfa(&self, user_id: uuid::Uuid) -> LocalResult<Option<user_mfa::Model>> {
+        user_mfa::Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await
+            .map_err_print(|e| e.into())
+    }
+
+    /// Confirmed 2FA, the only kind enforced on login
+    pub async fn get_enabled_mfa(&self, user_id: uuid::Uuid) -> LocalResult<Option<user_mfa::Model>> {
+        Ok(self.get_mfa(user_id).await?.filter(|m| m.confirmation_date.is_some()))
+    }
+
+    /// Stores a new unconfirmed secret, replacing a previous unfinished enrollment
+    pub async fn set_pending_secret(&self, user_id: uuid::Uuid, secret: String) -> LocalResult<()> {
+        let model = user_mfa::ActiveModel {
+            user_id: Set(user_id),
+            secret: Set(secret),
+            creation_date: Set(Utc::now().into()),
+            confirmation_date: Set(None),
+            last_used_step: Set(None),
+            pending_login_id: Set(None),
+        };
+
+        let on_conflict = OnConflict::column(user_mfa::Column::UserId)
+            .update_columns([user_mfa::Column::Secret, user_mfa::Column::CreationDate, user_mfa::Column::ConfirmationDate, user_mfa::Column::LastUsedStep])
+            .to_owned();
+
+        user_mfa::Entity::insert(model)
+            .on_conflict(on_conflict)
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    /// Confirms the enrollment and replaces the recovery codes, returning the plain ones
+    pub async fn enable(&self, user_id: uuid::Uuid, step: i64) -> LocalResult<Vec<String>> {
+        let codes = totp::generate_recovery_codes();
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        user_mfa::ActiveModel {
+            user_id: Set(user_id),
+            confirmation_date: Set(Some(Utc::now().into())),
+            last_used_step: Set(Some(step)),
+            ..Default::default()
+        }.update(&txn).await.map_err_print(LocalErr::from)?;
+
+        mfa_recovery_code::Entity::delete_many()
+            .filter(mfa_recovery_code::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        let models = codes.iter().map(|code| mfa_recovery_code::ActiveModel {
+            user_id: Set(user_id),
+            code_hash: Set(token::hash_token(&totp::normalize_recovery_code(code))),
+            ..Default::default()
+        });
+        mfa_recovery_code::Entity::insert_many(models)
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(codes)
+    }
+
+    pub async fn disable(&self, user_id: uuid::Uuid) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        mfa_recovery_code::Entity::delete_many()
+            .filter(mfa_recovery_code::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        user_mfa::Entity::delete_by_id(user_id)
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Records `step` as used, false if it (or a later one) already was
+    pub async fn use_step(&self, user_id: uuid::Uuid, step: i64) -> LocalResult<bool> {
+        let condition = Condition::all()
+            .add(user_mfa::Column::UserId.eq(user_id))
+            .add(Condition::any()
+                .add(user_mfa::Column::LastUsedStep.is_null())
+                .add(user_mfa::Column::LastUsedStep.lt(step)));
+
+        user_mfa::Entity::update_many()
+            .col_expr(user_mfa::Column::LastUsedStep, Expr::value(step))
+            .filter(condition)
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|r| r.rows_affected == 1)
+    }
+
+    /// New pending login for the user, replacing the previous one. Returns the id to put in the mfa token
+    pub async fn start_login(&self, user_id: uuid::Uuid) -> LocalResult<uuid::Uuid> {
+        let login_id = uuid::Uuid::new_v4();
+
+        user_mfa::Entity::update_many()
+            .col_expr(user_mfa::Column::PendingLoginId, Expr::value(login_id))
+            .filter(user_mfa::Column::UserId.eq(user_id))
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        Ok(login_id)
+    }
+
+    /// Ends the pending login, false if it was already finished or replaced by a newer one
+    pub async fn finish_login(&self, user_id: uuid::Uuid, login_id: uuid::Uuid) -> LocalResult<bool> {
+        user_mfa::Entity::update_many()
+            .col_expr(user_mfa::Column::PendingLoginId, Expr::value(Option::<uuid::Uuid>::None))
+            .filter(user_mfa::Column::UserId.eq(user_id))
+            .filter(user_mfa::Column::PendingLoginId.eq(login_id))
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|r| r.rows_affected == 1)
+    }
+
+    /// Marks the recovery code as used, false if it doesn't exist or was already used
+    pub async fn use_recovery_code(&self, user_id: uuid::Uuid, code: &str) -> LocalResult<bool> {
+        let condition = Condition::all()
+            .add(mfa_recovery_code::Column::UserId.eq(user_id))
+            .add(mfa_recovery_code::Column::CodeHash.eq(token::hash_token(&totp::normalize_recovery_code(code))))
+            .add(mfa_recovery_code::Column::UseDate.is_null());
+
+        mfa_recovery_code::Entity::update_many()
+            .col_expr(mfa_recovery_code::Column::UseDate, Expr::current_timestamp().into())
+            .filter(condition)
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|r| r.rows_affected > 0)
+    }
+
+    /// Accepts either a TOTP code or an unused recovery code
+    pub async fn check_code(&self, mfa: &user_mfa::Model, code: &str) -> LocalResult<bool> {
+        if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+            return match totp::verify_code(&mfa.secret, code)? {
+                Some(step) => self.use_step(mfa.user_id, step).await,
+                None => Ok(false)
+            }
+        }
+        self.use_recovery_code(mfa.user_id, code).await
+    }
+}
@@ -1 +1,15 @@
-pub mod user;
\ No newline at end of file
+pub mod user;
+pub mod session;
+pub mod user_token;
+pub mod mfa;
+pub mod role;
+pub mod audit;
+pub mod outbox;
+pub mod processed_message;
+pub mod payment;
+pub mod payment_webhook;
+pub mod subscription;
+pub mod entitlement;
+pub mod coupon;
+pub mod refund;
+pub mod invoice;
\ No newline at end of file
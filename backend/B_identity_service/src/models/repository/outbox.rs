@@ -0,0 +1,73 @@
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, DatabaseTransaction, EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait, sea_query::{Expr, LockBehavior, LockType}};
+
+use crate::{error::{LocalErr, LocalResult, MapErrPrint}, events::IdentityEvent, models::entity::outbox_event};
+
+
+/// Transactional outbox: events are added inside the transaction of the change they
+/// describe and relayed to the broker by `EventPublisher`
+#[derive(Clone)]
+pub struct OutboxRepository {
+    db: DatabaseConnection
+}
+
+impl OutboxRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn add_event<C: ConnectionTrait>(db: &C, event: IdentityEvent) -> LocalResult<()> {
+        outbox_event::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            event_type: Set(event.event_type().to_string()),
+            schema_version: Set(event.schema_version()),
+            payload: Set(event.data()),
+            creation_date: Set(Utc::now().into()),
+            publication_date: Set(None),
+            attempts: Set(0),
+            last_error: Set(None),
+        }
+        .insert(db)
+        .await
+        .map_err_print(LocalErr::from)
+        .map(|_| ())
+    }
+
+    /// Oldest unpublished events, locked until the returned transaction ends
+    /// (other instances skip them instead of publishing them twice)
+    pub async fn lock_pending(&self, limit: u64) -> LocalResult<(DatabaseTransaction, Vec<outbox_event::Model>)> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let events = outbox_event::Entity::find()
+            .filter(outbox_event::Column::PublicationDate.is_null())
+            .order_by_asc(outbox_event::Column::CreationDate)
+            .limit(limit)
+            .lock_with_behavior(LockType::Update, LockBehavior::SkipLocked)
+            .all(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        Ok((txn, events))
+    }
+
+    pub async fn mark_published(txn: &DatabaseTransaction, id: uuid::Uuid) -> LocalResult<()> {
+        outbox_event::Entity::update_many()
+            .col_expr(outbox_event::Column::PublicationDate, Expr::current_timestamp().into())
+            .filter(outbox_event::Column::Id.eq(id))
+            .exec(txn)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    pub async fn mark_failed(txn: &DatabaseTransaction, id: uuid::Uuid, error: String) -> LocalResult<()> {
+        outbox_event::Entity::update_many()
+            .col_expr(outbox_event::Column::Attempts, Expr::col(outbox_event::Column::Attempts).add(1))
+            .col_expr(outbox_event::Column::LastError, Expr::value(error))
+            .filter(outbox_event::Column::Id.eq(id))
+            .exec(txn)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,322 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, SqlErr, TransactionTrait, sea_query::{Expr, OnConflict}};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, events::{IdentityEvent, OrderPaid}, models::{entity::{coupon_redemption, course_price, entitlement::EntitlementSource, order::{self, OrderStatus}, payment::{self, PaymentStatus}}, repository::{coupon::CouponRepository, entitlement::EntitlementRepository, invoice::InvoiceRepository, outbox::OutboxRepository, payment_webhook::PaymentWebhookRepository, subscription::CHECKOUT_LIFETIME_HOURS}}, payments::{CheckoutSession, CheckoutStatus}};
+
+
+#[derive(Clone)]
+pub struct PaymentRepository {
+    db: DatabaseConnection
+}
+
+impl PaymentRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn get_course_price(&self, course_id: uuid::Uuid) -> LocalResult<Option<course_price::Model>> {
+        course_price::Entity::find_by_id(course_id)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    pub async fn set_course_price(&self, course_id: uuid::Uuid, title: &str, amount: i64, currency: &str) -> LocalResult<()> {
+        let price = course_price::ActiveModel {
+            course_id: Set(course_id),
+            title: Set(title.to_string()),
+            amount: Set(amount),
+            currency: Set(currency.to_string()),
+            update_date: Set(Utc::now().into()),
+        };
+
+        course_price::Entity::insert(price)
+            .on_conflict(
+                OnConflict::column(course_price::Column::CourseId)
+                    .update_columns([course_price::Column::Title, course_price::Column::Amount, course_price::Column::Currency, course_price::Column::UpdateDate])
+                    .to_owned()
+            )
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    /// The course can't be bought anymore, past orders are kept
+    pub async fn remove_course_price(&self, course_id: uuid::Uuid) -> LocalResult<()> {
+        course_price::Entity::delete_by_id(course_id)
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    pub async fn has_paid_order(&self, user_id: uuid::Uuid, course_id: uuid::Uuid) -> LocalResult<bool> {
+        Self::paid_order_exists(&self.db, user_id, course_id).await
+    }
+
+    async fn paid_order_exists<C: ConnectionTrait>(db: &C, user_id: uuid::Uuid, course_id: uuid::Uuid) -> LocalResult<bool> {
+        order::Entity::find()
+            .filter(order::Column::UserId.eq(user_id))
+            .filter(order::Column::CourseId.eq(course_id))
+            .filter(order::Column::Status.eq(OrderStatus::Paid))
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|o| o.is_some())
+    }
+
+    /// The pending order of the user for the course (there is at most one) and its payment, whatever its state
+    pub async fn get_pending_order(&self, user_id: uuid::Uuid, course_id: uuid::Uuid) -> LocalResult<Option<(order::Model, Option<payment::Model>)>> {
+        order::Entity::find()
+            .filter(order::Column::UserId.eq(user_id))
+            .filter(order::Column::CourseId.eq(course_id))
+            .filter(order::Column::Status.eq(OrderStatus::Pending))
+            .find_also_related(payment::Entity)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Whether the checkout session of a pending order can be handed out again: it is still pending here,
+    /// younger than a session lives, and not lost by the provider (`session_lost`, the fake one after a restart)
+    pub fn is_reusable(payment: &payment::Model, session_lost: bool, now: DateTime<Utc>) -> bool {
+        payment.status == PaymentStatus::Pending
+            && !session_lost
+            && payment.creation_date.to_utc() + Duration::hours(CHECKOUT_LIFETIME_HOURS) > now
+    }
+
+    /// Cancels a pending order whose checkout was given up, expiring its payment and giving its coupon back,
+    /// so another checkout can be opened for the course. If the session still gets paid, the order becomes `RefundDue`
+    pub async fn abandon_order(&self, order_id: uuid::Uuid) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let order = order::Entity::find_by_id(order_id)
+            .filter(order::Column::Status.eq(OrderStatus::Pending))
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+        // settled in the meantime
+        if order.is_none() {
+            return Ok(())
+        }
+
+        let now = Utc::now();
+        order::Entity::update_many()
+            .col_expr(order::Column::Status, Expr::value(OrderStatus::Canceled))
+            .col_expr(order::Column::UpdateDate, Expr::value(now))
+            .filter(order::Column::Id.eq(order_id))
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        let payments = payment::Entity::update_many()
+            .col_expr(payment::Column::Status, Expr::value(PaymentStatus::Expired))
+            .col_expr(payment::Column::UpdateDate, Expr::value(now))
+            .filter(payment::Column::OrderId.eq(order_id))
+            .filter(payment::Column::Status.eq(PaymentStatus::Pending))
+            .exec_with_returning(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+        for payment in payments {
+            CouponRepository::release(&txn, &payment.provider, &payment.provider_session_id).await?;
+        }
+
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Saves the order with the checkout session opened for it, tying it to the use of the coupon reserved for it if any.
+    /// Fails with `CheckoutPending` if the user opened another checkout for the course in the meantime
+    pub async fn create_order(&self, order: order::ActiveModel, payment: payment::ActiveModel, redemption_id: Option<uuid::Uuid>) -> LocalResult<order::Model> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let order = order.insert(&txn).await.map_err_print(|e| match e.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(_)) => LocalErr::new(LocalErrKind::CheckoutPending, StatusCode::CONFLICT)
+                .with_msg("a checkout is already open for this course"),
+            _ => e.into(),
+        })?;
+        let payment = payment.insert(&txn).await.map_err_print(LocalErr::from)?;
+        if let Some(redemption_id) = redemption_id {
+            CouponRepository::attach(&txn, redemption_id, Some(order.id), &payment.provider, &payment.provider_session_id).await?;
+        }
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(order)
+    }
+
+    /// Saves an order the coupon pays entirely, it is paid right away without going through the provider
+    pub async fn create_free_order(&self, order: order::ActiveModel, redemption: coupon_redemption::Model) -> LocalResult<order::Model> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let order = order.insert(&txn).await.map_err_print(LocalErr::from)?;
+        CouponRepository::redeem(&txn, redemption).await?;
+        Self::settle_order(&txn, &order).await?;
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(order)
+    }
+
+    pub async fn get_user_order(&self, user_id: uuid::Uuid, order_id: uuid::Uuid) -> LocalResult<Option<order::Model>> {
+        order::Entity::find_by_id(order_id)
+            .filter(order::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Newest first
+    pub async fn get_user_orders(&self, user_id: uuid::Uuid) -> LocalResult<Vec<order::Model>> {
+        order::Entity::find()
+            .filter(order::Column::UserId.eq(user_id))
+            .order_by_desc(order::Column::CreationDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Moves the payment of the checkout session and its order to the state of the session (a paid order
+    /// grants the course and is invoiced, a canceled or `RefundDue` one gives back its coupon), marking the webhook event that reported it as processed in the same transaction.
+    /// Only pending rows (or an abandoned checkout that still got paid) are touched, so applying a session twice is harmless
+    pub async fn apply_checkout(&self, event_id: &str, provider: &str, session: &CheckoutSession) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let payment = payment::Entity::find()
+            .filter(payment::Column::Provider.eq(provider))
+            .filter(payment::Column::ProviderSessionId.eq(&session.id))
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        // not opened by this service (e.g. a payment link of the provider's dashboard): acknowledged, nothing to apply
+        let Some(payment) = payment else {
+            PaymentWebhookRepository::mark_processed(&txn, event_id).await?;
+            return txn.commit().await.map_err_print(LocalErr::from)
+        };
+
+        let statuses = match session.status {
+            CheckoutStatus::Open => None,
+            CheckoutStatus::Paid => Some((PaymentStatus::Succeeded, OrderStatus::Paid)),
+            CheckoutStatus::Failed => Some((PaymentStatus::Failed, OrderStatus::Canceled)),
+            CheckoutStatus::Expired => Some((PaymentStatus::Expired, OrderStatus::Canceled)),
+        };
+
+        // an abandoned checkout (see `abandon_order`) may still have been paid
+        let applicable = match payment.status {
+            PaymentStatus::Pending => true,
+            PaymentStatus::Expired => session.status == CheckoutStatus::Paid,
+            _ => false,
+        };
+        if let Some((payment_status, order_status)) = statuses.filter(|_| applicable) {
+            let now = Utc::now();
+
+            payment::Entity::update_many()
+                .col_expr(payment::Column::Status, Expr::value(payment_status))
+                .col_expr(payment::Column::ProviderPaymentId, Expr::value(session.payment_id.clone()))
+                .col_expr(payment::Column::UpdateDate, Expr::value(now))
+                .filter(payment::Column::Id.eq(payment.id))
+                .exec(&txn)
+                .await
+                .map_err_print(LocalErr::from)?;
+
+            let order = order::Entity::find_by_id(payment.order_id)
+                .lock_exclusive()
+                .one(&txn)
+                .await
+                .map_err_print(LocalErr::from)?
+                .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+            // a course is bought once: paying it twice (or paying a canceled order) is kept as money to give back
+            let order_status = match (order.status, order_status) {
+                (OrderStatus::Pending, OrderStatus::Paid) if Self::paid_order_exists(&txn, order.user_id, order.course_id).await? => Some(OrderStatus::RefundDue),
+                (OrderStatus::Pending, status) => Some(status),
+                (OrderStatus::Canceled, OrderStatus::Paid) => Some(OrderStatus::RefundDue),
+                _ => None,
+            };
+
+            if let Some(order_status) = order_status {
+                let order = order::ActiveModel {
+                    id: Set(order.id),
+                    status: Set(order_status),
+                    update_date: Set(now.into()),
+                    ..Default::default()
+                }
+                .update(&txn)
+                .await
+                .map_err_print(LocalErr::from)?;
+
+                match order.status {
+                    OrderStatus::Paid => Self::settle_order(&txn, &order).await?,
+                    // the coupon can be used again
+                    _ => CouponRepository::release(&txn, provider, &session.id).await?,
+                }
+                if payment_status == PaymentStatus::Succeeded {
+                    InvoiceRepository::issue_order_invoice(&txn, &order, provider, &session.id).await?;
+                }
+            }
+        }
+
+        PaymentWebhookRepository::mark_processed(&txn, event_id).await?;
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Grants the course of the paid order and queues `order.paid`
+    async fn settle_order<C: ConnectionTrait>(db: &C, order: &order::Model) -> LocalResult<()> {
+        EntitlementRepository::grant(db, order.user_id, order.course_id, EntitlementSource::Purchase, Some(order.id), None).await?;
+        OutboxRepository::add_event(db, IdentityEvent::OrderPaid(OrderPaid {
+            order_id: order.id,
+            user_id: order.user_id,
+            course_id: order.course_id,
+            amount: order.amount,
+            currency: order.currency.clone(),
+            payment_date: order.update_date.to_utc(),
+        })).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(status: PaymentStatus, created: DateTime<Utc>) -> payment::Model {
+        payment::Model {
+            id: uuid::Uuid::new_v4(),
+            order_id: uuid::Uuid::new_v4(),
+            provider: "fake".to_string(),
+            provider_session_id: "fake_cs_1".to_string(),
+            provider_payment_id: None,
+            checkout_url: "http://localhost/fake_cs_1".to_string(),
+            amount: 4_999,
+            currency: "eur".to_string(),
+            status,
+            creation_date: created.into(),
+            update_date: created.into(),
+        }
+    }
+
+    #[test]
+    fn recent_pending_checkouts_are_reused() {
+        let now = Utc::now();
+        assert!(PaymentRepository::is_reusable(&payment(PaymentStatus::Pending, now), false, now));
+        assert!(PaymentRepository::is_reusable(&payment(PaymentStatus::Pending, now - Duration::hours(CHECKOUT_LIFETIME_HOURS - 1)), false, now));
+    }
+
+    #[test]
+    fn checkouts_older_than_a_session_are_abandoned() {
+        let now = Utc::now();
+        assert!(!PaymentRepository::is_reusable(&payment(PaymentStatus::Pending, now - Duration::hours(CHECKOUT_LIFETIME_HOURS)), false, now));
+        assert!(!PaymentRepository::is_reusable(&payment(PaymentStatus::Pending, now - Duration::days(7)), false, now));
+    }
+
+    #[test]
+    fn lost_or_closed_checkouts_are_abandoned() {
+        let now = Utc::now();
+        assert!(!PaymentRepository::is_reusable(&payment(PaymentStatus::Pending, now), true, now));
+        for status in [PaymentStatus::Succeeded, PaymentStatus::Failed, PaymentStatus::Expired] {
+            assert!(!PaymentRepository::is_reusable(&payment(status, now), false, now));
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, sea_query::{Expr, OnConflict}};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, models::entity::payment_webhook_event::{self, WebhookEventStatus}};
+
+
+#[derive(Clone)]
+pub struct PaymentWebhookRepository {
+    db: DatabaseConnection
+}
+
+impl PaymentWebhookRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Stores the event unless its id is already known, returning the stored row either way
+    pub async fn record_event(&self, id: &str, provider: &str, event_type: &str, payload: serde_json::Value) -> LocalResult<payment_webhook_event::Model> {
+        let event = payment_webhook_event::ActiveModel {
+            id: Set(id.to_string()),
+            provider: Set(provider.to_string()),
+            event_type: Set(event_type.to_string()),
+            payload: Set(payload),
+            status: Set(WebhookEventStatus::Received),
+            attempts: Set(0),
+            last_error: Set(None),
+            receipt_date: Set(Utc::now().into()),
+            processing_date: Set(None),
+        };
+
+        payment_webhook_event::Entity::insert(event)
+            .on_conflict(OnConflict::column(payment_webhook_event::Column::Id).do_nothing().to_owned())
+            .do_nothing()
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        self.get_event(id)
+            .await?
+            .ok_or(LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))
+    }
+
+    pub async fn get_event(&self, id: &str) -> LocalResult<Option<payment_webhook_event::Model>> {
+        payment_webhook_event::Entity::find_by_id(id)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Newest first, with the total count
+    pub async fn list_events(&self, status: Option<WebhookEventStatus>, page: u64, per_page: u64) -> LocalResult<(Vec<payment_webhook_event::Model>, u64)> {
+        let mut filters = Condition::all();
+        if let Some(status) = status {
+            filters = filters.add(payment_webhook_event::Column::Status.eq(status));
+        }
+
+        let paginator = payment_webhook_event::Entity::find()
+            .filter(filters)
+            .order_by_desc(payment_webhook_event::Column::ReceiptDate)
+            .paginate(&self.db, per_page);
+
+        let total = paginator.num_items().await.map_err_print(LocalErr::from)?;
+        let events = paginator.fetch_page(page).await.map_err_print(LocalErr::from)?;
+        Ok((events, total))
+    }
+
+    /// Called in the transaction applying the event
+    pub async fn mark_processed<C: ConnectionTrait>(db: &C, id: &str) -> LocalResult<()> {
+        payment_webhook_event::Entity::update_many()
+            .col_expr(payment_webhook_event::Column::Status, Expr::value(WebhookEventStatus::Processed))
+            .col_expr(payment_webhook_event::Column::Attempts, Expr::col(payment_webhook_event::Column::Attempts).add(1))
+            .col_expr(payment_webhook_event::Column::LastError, Expr::value(Option::<String>::None))
+            .col_expr(payment_webhook_event::Column::ProcessingDate, Expr::value(Utc::now()))
+            .filter(payment_webhook_event::Column::Id.eq(id))
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    /// Events about things we don't track (e.g. other Stripe objects) are acknowledged as is
+    pub async fn mark_ignored(&self, id: &str) -> LocalResult<()> {
+        Self::mark_processed(&self.db, id).await
+    }
+
+    pub async fn mark_failed(&self, id: &str, error: &str) -> LocalResult<()> {
+        payment_webhook_event::Entity::update_many()
+            .col_expr(payment_webhook_event::Column::Status, Expr::value(WebhookEventStatus::Failed))
+            .col_expr(payment_webhook_event::Column::Attempts, Expr::col(payment_webhook_event::Column::Attempts).add(1))
+            .col_expr(payment_webhook_event::Column::LastError, Expr::value(error))
+            .filter(payment_webhook_event::Column::Id.eq(id))
+            .filter(payment_webhook_event::Column::Status.ne(WebhookEventStatus::Processed))
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,40 @@
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, DatabaseConnection, EntityTrait, sea_query::OnConflict};
+
+use crate::{error::{LocalErr, LocalResult, MapErrPrint}, models::entity::processed_message};
+
+
+#[derive(Clone)]
+pub struct ProcessedMessageRepository {
+    db: DatabaseConnection
+}
+
+impl ProcessedMessageRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn is_processed(&self, id: uuid::Uuid) -> LocalResult<bool> {
+        processed_message::Entity::find_by_id(id)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|m| m.is_some())
+    }
+
+    pub async fn mark_processed(&self, id: uuid::Uuid, command_type: &str) -> LocalResult<()> {
+        let message = processed_message::ActiveModel {
+            id: Set(id),
+            command_type: Set(command_type.to_string()),
+            processing_date: Set(Utc::now().into()),
+        };
+
+        processed_message::Entity::insert(message)
+            .on_conflict(OnConflict::column(processed_message::Column::Id).do_nothing().to_owned())
+            .do_nothing()
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+}
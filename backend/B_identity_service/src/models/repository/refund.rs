@@ -0,0 +1,328 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait, sea_query::Expr};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, events::{IdentityEvent, OrderRefunded}, models::{entity::{audit_log::AuditAction, entitlement::EntitlementSource, order::{self, OrderStatus}, payment::{self, PaymentStatus}, refund::{self, RefundStatus}}, repository::{audit::{AuditEntry, AuditRepository}, entitlement::EntitlementRepository, invoice::InvoiceRepository, outbox::OutboxRepository, payment_webhook::PaymentWebhookRepository}}, payments::{DisputeStatus, ProviderDispute, ProviderRefund}};
+
+
+#[derive(Clone)]
+pub struct RefundRepository {
+    db: DatabaseConnection
+}
+
+fn not_refundable(msg: &str) -> LocalErr {
+    LocalErr::new(LocalErrKind::OrderNotRefundable, StatusCode::CONFLICT).with_msg(msg)
+}
+
+impl RefundRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn get_refund(&self, refund_id: uuid::Uuid) -> LocalResult<Option<refund::Model>> {
+        refund::Entity::find_by_id(refund_id)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Newest first
+    pub async fn get_user_refunds(&self, user_id: uuid::Uuid) -> LocalResult<Vec<refund::Model>> {
+        refund::Entity::find()
+            .filter(refund::Column::UserId.eq(user_id))
+            .order_by_desc(refund::Column::CreationDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Newest first
+    pub async fn get_order_refunds(&self, order_id: uuid::Uuid) -> LocalResult<Vec<refund::Model>> {
+        refund::Entity::find()
+            .filter(refund::Column::OrderId.eq(order_id))
+            .order_by_desc(refund::Column::CreationDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// The payment that paid the order, none for orders a coupon paid entirely
+    pub async fn get_order_payment(&self, order_id: uuid::Uuid) -> LocalResult<Option<payment::Model>> {
+        payment::Entity::find()
+            .filter(payment::Column::OrderId.eq(order_id))
+            .filter(payment::Column::Status.eq(PaymentStatus::Succeeded))
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Saves a pending refund of `amount` (by default, what's left to refund) before the provider is asked for it.
+    /// The order row is locked and pending refunds count as refunded, so concurrent requests can't refund more than was paid.
+    /// Staff refunds are `audited` in the same transaction
+    pub async fn create_refund(&self, order_id: uuid::Uuid, amount: Option<i64>, reason: Option<String>, requested_by: uuid::Uuid, audited: bool) -> LocalResult<(refund::Model, payment::Model)> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let order = order::Entity::find_by_id(order_id)
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?
+            .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+        if !matches!(order.status, OrderStatus::Paid | OrderStatus::RefundDue) {
+            return Err(not_refundable("only paid orders can be refunded"))
+        }
+
+        let payment = payment::Entity::find()
+            .filter(payment::Column::OrderId.eq(order.id))
+            .filter(payment::Column::Status.eq(PaymentStatus::Succeeded))
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?
+            .ok_or_else(|| not_refundable("nothing was paid for this order"))?;
+
+        let refunds = refund::Entity::find()
+            .filter(refund::Column::OrderId.eq(order.id))
+            .all(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+        let remaining = order.amount - refunds.iter().filter(|r| r.status.holds_amount()).map(|r| r.amount).sum::<i64>();
+        if remaining <= 0 {
+            return Err(not_refundable("the order is already refunded"))
+        }
+
+        let amount = amount.unwrap_or(remaining);
+        if amount <= 0 || amount > remaining {
+            return Err(LocalErr::new(LocalErrKind::InvalidRefundAmount, StatusCode::BAD_REQUEST)
+                .with_msg(format!("at most {} can be refunded", remaining)))
+        }
+
+        let now = Utc::now();
+        let refund = refund::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            order_id: Set(order.id),
+            payment_id: Set(payment.id),
+            user_id: Set(order.user_id),
+            amount: Set(amount),
+            currency: Set(order.currency),
+            reason: Set(reason),
+            status: Set(RefundStatus::Pending),
+            provider: Set(payment.provider.clone()),
+            provider_refund_id: Set(None),
+            requested_by: Set(Some(requested_by)),
+            creation_date: Set(now.into()),
+            update_date: Set(now.into()),
+        }
+        .insert(&txn)
+        .await
+        .map_err_print(LocalErr::from)?;
+
+        if audited {
+            let details = serde_json::json!({ "order_id": refund.order_id, "refund_id": refund.id, "amount": refund.amount });
+            AuditRepository::record(&txn, AuditEntry::new(requested_by, AuditAction::RefundIssued, refund.user_id).with_details(details)).await?;
+        }
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok((refund, payment))
+    }
+
+    /// The provider refused the refund, its amount can be refunded again
+    pub async fn fail_refund(&self, refund_id: uuid::Uuid) -> LocalResult<()> {
+        refund::Entity::update_many()
+            .col_expr(refund::Column::Status, Expr::value(RefundStatus::Failed))
+            .col_expr(refund::Column::UpdateDate, Expr::value(Utc::now()))
+            .filter(refund::Column::Id.eq(refund_id))
+            .filter(refund::Column::Status.eq(RefundStatus::Pending))
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+
+    /// Moves the refund to the state reported by the provider, refunds made at the provider are recorded too.
+    /// A succeeded refund is added to the order, which revokes the course once refunded in full (one of a subscription charge credits its invoice).
+    /// `event_id` is the webhook event marked as processed in the same transaction, if any
+    pub async fn apply_refund(&self, event_id: Option<&str>, provider: &str, reported: &ProviderRefund) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let by_id = match reported.refund_id {
+            Some(refund_id) => refund::Column::Id.eq(refund_id),
+            None => refund::Column::ProviderRefundId.eq(&reported.id),
+        };
+        let found = refund::Entity::find()
+            .filter(refund::Column::Provider.eq(provider))
+            .filter(by_id)
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        // none for refunds of subscription payments, only course orders are tracked
+        let refund = match found {
+            Some(refund) => Some(refund),
+            None => Self::record_provider_refund(&txn, provider, reported).await?,
+        };
+
+        // a subscription charge refunded at the provider is only credited on its invoice
+        if refund.is_none() && reported.status == RefundStatus::Succeeded {
+            InvoiceRepository::issue_charge_credit_note(&txn, provider, reported).await?;
+        }
+
+        if let Some(refund) = refund.filter(|r| r.status == RefundStatus::Pending) {
+            let now = Utc::now();
+            refund::Entity::update_many()
+                .col_expr(refund::Column::Status, Expr::value(reported.status))
+                .col_expr(refund::Column::ProviderRefundId, Expr::value(reported.id.clone()))
+                .col_expr(refund::Column::UpdateDate, Expr::value(now))
+                .filter(refund::Column::Id.eq(refund.id))
+                .exec(&txn)
+                .await
+                .map_err_print(LocalErr::from)?;
+
+            if reported.status == RefundStatus::Succeeded {
+                Self::settle_refund(&txn, &refund).await?;
+            }
+        }
+
+        if let Some(event_id) = event_id {
+            PaymentWebhookRepository::mark_processed(&txn, event_id).await?;
+        }
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// A dispute takes the course away while it is open, and gives it back if it is won.
+    /// `event_id` is the webhook event marked as processed in the same transaction
+    pub async fn apply_dispute(&self, event_id: &str, provider: &str, dispute: &ProviderDispute) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let payment = payment::Entity::find()
+            .filter(payment::Column::Provider.eq(provider))
+            .filter(payment::Column::ProviderPaymentId.eq(dispute.payment_id.clone()))
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        // a subscription payment (only course orders have one): there is no course to take back
+        let Some(payment) = payment else {
+            PaymentWebhookRepository::mark_processed(&txn, event_id).await?;
+            return txn.commit().await.map_err_print(LocalErr::from)
+        };
+
+        let order = order::Entity::find_by_id(payment.order_id)
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?
+            .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+        let status = match (order.status, dispute.status) {
+            (OrderStatus::Paid, DisputeStatus::Open) => {
+                EntitlementRepository::revoke(&txn, order.user_id, order.course_id, EntitlementSource::Purchase).await?;
+                Some(OrderStatus::Disputed)
+            },
+            (OrderStatus::Disputed, DisputeStatus::Won) => {
+                EntitlementRepository::grant(&txn, order.user_id, order.course_id, EntitlementSource::Purchase, Some(order.id), None).await?;
+                Some(OrderStatus::Paid)
+            },
+            // a lost dispute keeps the order disputed, the money is gone
+            _ => None,
+        };
+
+        if let Some(status) = status {
+            order::Entity::update_many()
+                .col_expr(order::Column::Status, Expr::value(status))
+                .col_expr(order::Column::UpdateDate, Expr::value(Utc::now()))
+                .filter(order::Column::Id.eq(order.id))
+                .exec(&txn)
+                .await
+                .map_err_print(LocalErr::from)?;
+        }
+
+        PaymentWebhookRepository::mark_processed(&txn, event_id).await?;
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Pending refund for a refund made at the provider, about one of our payments (`None` if it isn't about a course order)
+    async fn record_provider_refund<C: ConnectionTrait>(db: &C, provider: &str, reported: &ProviderRefund) -> LocalResult<Option<refund::Model>> {
+        let payment = payment::Entity::find()
+            .filter(payment::Column::Provider.eq(provider))
+            .filter(payment::Column::ProviderPaymentId.eq(reported.payment_id.clone()))
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+        let Some(payment) = payment else {
+            return Ok(None)
+        };
+
+        let order = order::Entity::find_by_id(payment.order_id)
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)?
+            .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+        let now = Utc::now();
+        refund::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            order_id: Set(order.id),
+            payment_id: Set(payment.id),
+            user_id: Set(order.user_id),
+            amount: Set(reported.amount),
+            currency: Set(payment.currency),
+            reason: Set(None),
+            status: Set(RefundStatus::Pending),
+            provider: Set(provider.to_string()),
+            provider_refund_id: Set(Some(reported.id.clone())),
+            requested_by: Set(None),
+            creation_date: Set(now.into()),
+            update_date: Set(now.into()),
+        }
+        .insert(db)
+        .await
+        .map_err_print(LocalErr::from)
+        .map(Some)
+    }
+
+    /// Adds the refund to its order, a fully refunded order loses the course. Credits its invoice and queues `order.refunded`
+    async fn settle_refund<C: ConnectionTrait>(db: &C, refund: &refund::Model) -> LocalResult<()> {
+        let order = order::Entity::find_by_id(refund.order_id)
+            .lock_exclusive()
+            .one(db)
+            .await
+            .map_err_print(LocalErr::from)?
+            .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+        let refunded_amount = order.refunded_amount + refund.amount;
+        let fully_refunded = refunded_amount >= order.amount;
+        let status = match fully_refunded {
+            true => OrderStatus::Refunded,
+            false => order.status,
+        };
+
+        let now = Utc::now();
+        order::Entity::update_many()
+            .col_expr(order::Column::RefundedAmount, Expr::value(refunded_amount))
+            .col_expr(order::Column::Status, Expr::value(status))
+            .col_expr(order::Column::UpdateDate, Expr::value(now))
+            .filter(order::Column::Id.eq(order.id))
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        // a `RefundDue` order never granted the course, the order that did keeps it
+        if fully_refunded && order.status != OrderStatus::RefundDue {
+            EntitlementRepository::revoke(db, order.user_id, order.course_id, EntitlementSource::Purchase).await?;
+        }
+        InvoiceRepository::issue_credit_note(db, refund).await?;
+
+        OutboxRepository::add_event(db, IdentityEvent::OrderRefunded(OrderRefunded {
+            order_id: order.id,
+            refund_id: refund.id,
+            user_id: order.user_id,
+            course_id: order.course_id,
+            amount: refund.amount,
+            refunded_amount,
+            currency: order.currency,
+            fully_refunded,
+            refund_date: now,
+        })).await
+    }
+}
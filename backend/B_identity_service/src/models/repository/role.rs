@@ -0,0 +1,102 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait, sea_query::{Expr, Query}};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, events::{IdentityEvent, RoleChanged}, models::{entity::{role, role_permission, user, user_role}, repository::{audit::{AuditEntry, AuditRepository}, outbox::OutboxRepository}}};
+
+
+#[derive(Clone)]
+pub struct RoleRepository {
+    db: DatabaseConnection
+}
+
+impl RoleRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn get_user_roles(&self, user_id: uuid::Uuid) -> LocalResult<Vec<String>> {
+        user_role::Entity::find()
+            .filter(user_role::Column::UserId.eq(user_id))
+            .select_only()
+            .column(user_role::Column::RoleName)
+            .order_by_asc(user_role::Column::RoleName)
+            .into_tuple::<String>()
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Whether any of the user roles grants `permission`
+    pub async fn has_permission(&self, user_id: uuid::Uuid, permission: &str) -> LocalResult<bool> {
+        let user_roles = Query::select()
+            .column(user_role::Column::RoleName)
+            .from(user_role::Entity)
+            .and_where(user_role::Column::UserId.eq(user_id))
+            .to_owned();
+
+        role_permission::Entity::find()
+            .filter(role_permission::Column::PermissionName.eq(permission))
+            .filter(role_permission::Column::RoleName.in_subquery(user_roles))
+            .count(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|count| count > 0)
+    }
+
+    /// Replaces the user roles and bumps their version, so tokens carrying the old
+    /// roles stop being accepted, queuing `user.role_changed`. `UserRepository::forget_version` must be called after.
+    /// Staff changes pass their `audit` entry, recorded in the same transaction
+    pub async fn set_user_roles(&self, user_id: uuid::Uuid, roles: &[String], audit: Option<AuditEntry>) -> LocalResult<()> {
+        let known = role::Entity::find()
+            .filter(role::Column::Name.is_in(roles))
+            .count(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        if known as usize != roles.len() {
+            return Err(LocalErr::new(LocalErrKind::UnknownRole, StatusCode::BAD_REQUEST))
+        }
+
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        user_role::Entity::delete_many()
+            .filter(user_role::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        Self::insert_roles(&txn, user_id, roles).await?;
+        OutboxRepository::add_event(&txn, IdentityEvent::RoleChanged(RoleChanged { user_id, roles: roles.to_vec() })).await?;
+
+        user::Entity::update_many()
+            .col_expr(user::Column::Version, Expr::value(uuid::Uuid::new_v4()))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        if let Some(audit) = audit {
+            AuditRepository::record(&txn, audit).await?;
+        }
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    pub async fn insert_roles<C: ConnectionTrait>(db: &C, user_id: uuid::Uuid, roles: &[String]) -> LocalResult<()> {
+        if roles.is_empty() {
+            return Ok(())
+        }
+
+        let rows = roles.iter().map(|r| user_role::ActiveModel {
+            user_id: Set(user_id),
+            role_name: Set(r.clone()),
+            creation_date: Set(Utc::now().into()),
+        });
+
+        user_role::Entity::insert_many(rows)
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,137 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait, sea_query::Expr};
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, models::entity::session};
+
+
+#[derive(Clone)]
+pub struct SessionRepository {
+    db: DatabaseConnection
+}
+
+impl SessionRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn create_session(&self, user_id: uuid::Uuid) -> LocalResult<session::Model> {
+        let id = uuid::Uuid::new_v4();
+        Self::insert_session(&self.db, id, id, user_id).await
+    }
+
+    /// Replaces the session `id` with a new one of the same family.
+    /// 
+    /// Presenting a session that was already rotated means the refresh token was
+    /// reused (most likely stolen), so the whole family is revoked.
+    pub async fn rotate_session(&self, id: uuid::Uuid, user_id: uuid::Uuid) -> LocalResult<session::Model> {
+        let invalid = || LocalErr::new(LocalErrKind::InvalidRefreshToken, StatusCode::UNAUTHORIZED);
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let current = session::Entity::find_by_id(id)
+            .filter(session::Column::UserId.eq(user_id))
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?
+            .ok_or_else(invalid)?;
+
+        if current.replaced_by.is_some() {
+            eprintln!("refresh token reuse detected (user {}, family {})", user_id, current.family_id);
+
+            let family = Condition::all().add(session::Column::FamilyId.eq(current.family_id));
+            Self::revoke_where(&txn, family).await?;
+            txn.commit().await.map_err_print(LocalErr::from)?;
+            return Err(invalid());
+        }
+
+        if current.revocation_date.is_some() || current.expiration_date < Utc::now() {
+            return Err(invalid());
+        }
+
+        let new = Self::insert_session(&txn, uuid::Uuid::new_v4(), current.family_id, user_id).await?;
+
+        let mut current: session::ActiveModel = current.into();
+        current.revocation_date = Set(Some(Utc::now().into()));
+        current.replaced_by = Set(Some(new.id));
+        current.update(&txn).await.map_err_print(LocalErr::from)?;
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(new)
+    }
+
+    /// Revokes every session of the family `id` belongs to (a single device)
+    pub async fn revoke_session(&self, id: uuid::Uuid) -> LocalResult<()> {
+        let session = session::Entity::find_by_id(id)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        match session {
+            Some(s) => Self::revoke_where(&self.db, Condition::all().add(session::Column::FamilyId.eq(s.family_id))).await,
+            None => Ok(())
+        }
+    }
+
+    pub async fn revoke_user_sessions(&self, user_id: uuid::Uuid) -> LocalResult<()> {
+        Self::revoke_all(&self.db, user_id).await
+    }
+
+    pub async fn revoke_all<C: ConnectionTrait>(db: &C, user_id: uuid::Uuid) -> LocalResult<()> {
+        Self::revoke_where(db, Condition::all().add(session::Column::UserId.eq(user_id))).await
+    }
+
+    pub async fn get_user_sessions(&self, user_id: uuid::Uuid) -> LocalResult<Vec<session::Model>> {
+        session::Entity::find()
+            .filter(session::Column::UserId.eq(user_id))
+            .order_by_desc(session::Column::CreationDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Whether the device `id` belongs to is still logged in: a rotated session
+    /// stays valid as long as some session of its family isn't revoked or expired
+    pub async fn is_family_active(&self, id: uuid::Uuid) -> LocalResult<bool> {
+        let Some(session) = session::Entity::find_by_id(id)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)? else { return Ok(false) };
+
+        let live = session::Entity::find()
+            .filter(session::Column::FamilyId.eq(session.family_id))
+            .filter(session::Column::RevocationDate.is_null())
+            .filter(session::Column::ExpirationDate.gt(Utc::now()))
+            .count(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        Ok(live > 0)
+    }
+
+    async fn insert_session<C: ConnectionTrait>(db: &C, id: uuid::Uuid, family_id: uuid::Uuid, user_id: uuid::Uuid) -> LocalResult<session::Model> {
+        let session = session::ActiveModel {
+            id: Set(id),
+            family_id: Set(family_id),
+            user_id: Set(user_id),
+            expiration_date: Set((Utc::now() + CONFIG.jwt_refresh_exp_time).into()),
+            ..Default::default()
+        };
+
+        session.insert(db).await.map_err_print(|e| e.into())
+    }
+
+    async fn revoke_where<C: ConnectionTrait>(db: &C, filters: Condition) -> LocalResult<()> {
+        let condition = Condition::all()
+            .add(session::Column::RevocationDate.is_null())
+            .add(filters);
+
+        session::Entity::update_many()
+            .col_expr(session::Column::RevocationDate, Expr::current_timestamp().into())
+            .filter(condition)
+            .exec(db)
+            .await
+            .map_err_print(|e| e.into())
+            .map(|_| ())
+    }
+}
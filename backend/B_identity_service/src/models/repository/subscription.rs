@@ -0,0 +1,245 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, SqlErr, TransactionTrait, sea_query::Expr};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, events::{IdentityEvent, SubscriptionChanged}, models::{entity::{payment::PaymentStatus, plan, subscription::{self, SubscriptionStatus}, subscription_checkout}, repository::{coupon::CouponRepository, outbox::OutboxRepository, payment_webhook::PaymentWebhookRepository}}, payments::ProviderSubscription};
+
+/// Pending checkouts (and course orders) older than this are given up (Stripe sessions expire after a day, fake ones don't survive a restart)
+pub(crate) const CHECKOUT_LIFETIME_HOURS: i64 = 24;
+
+
+#[derive(Clone)]
+pub struct SubscriptionRepository {
+    db: DatabaseConnection
+}
+
+impl SubscriptionRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Cheapest first
+    pub async fn get_active_plans(&self) -> LocalResult<Vec<plan::Model>> {
+        plan::Entity::find()
+            .filter(plan::Column::IsActive.eq(true))
+            .order_by_asc(plan::Column::Amount)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    pub async fn get_active_plan(&self, code: &str) -> LocalResult<Option<plan::Model>> {
+        plan::Entity::find()
+            .filter(plan::Column::Code.eq(code))
+            .filter(plan::Column::IsActive.eq(true))
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Newest first
+    pub async fn get_user_subscriptions(&self, user_id: uuid::Uuid) -> LocalResult<Vec<(subscription::Model, plan::Model)>> {
+        subscription::Entity::find()
+            .find_also_related(plan::Entity)
+            .filter(subscription::Column::UserId.eq(user_id))
+            .order_by_desc(subscription::Column::CreationDate)
+            .all(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|rows| rows.into_iter().filter_map(|(s, p)| Some((s, p?))).collect())
+    }
+
+    /// The one that hasn't ended or else the latest one
+    pub async fn get_current_subscription(&self, user_id: uuid::Uuid) -> LocalResult<Option<(subscription::Model, plan::Model)>> {
+        let mut subscriptions = self.get_user_subscriptions(user_id).await?;
+        let current = subscriptions.iter()
+            .position(|(s, _)| s.status.is_live())
+            .unwrap_or(0);
+
+        Ok((current < subscriptions.len()).then(|| subscriptions.swap_remove(current)))
+    }
+
+    /// The checkout session the user opened and hasn't paid or given up yet
+    pub async fn get_pending_checkout(&self, user_id: uuid::Uuid) -> LocalResult<Option<subscription_checkout::Model>> {
+        subscription_checkout::Entity::find()
+            .filter(subscription_checkout::Column::UserId.eq(user_id))
+            .filter(subscription_checkout::Column::Status.eq(PaymentStatus::Pending))
+            .filter(subscription_checkout::Column::CreationDate.gt(Utc::now() - Duration::hours(CHECKOUT_LIFETIME_HOURS)))
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Saves the checkout session opened for the user, tying it to the use of the coupon reserved for it if any.
+    /// Fails with `CheckoutPending` if the user opened another one in the meantime
+    pub async fn create_checkout(&self, checkout: subscription_checkout::Model, redemption_id: Option<uuid::Uuid>) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        // given up, they don't hold the place of the new one
+        subscription_checkout::Entity::update_many()
+            .col_expr(subscription_checkout::Column::Status, Expr::value(PaymentStatus::Expired))
+            .col_expr(subscription_checkout::Column::UpdateDate, Expr::value(Utc::now()))
+            .filter(subscription_checkout::Column::UserId.eq(checkout.user_id))
+            .filter(subscription_checkout::Column::Status.eq(PaymentStatus::Pending))
+            .filter(subscription_checkout::Column::CreationDate.lte(Utc::now() - Duration::hours(CHECKOUT_LIFETIME_HOURS)))
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        if let Some(redemption_id) = redemption_id {
+            CouponRepository::attach(&txn, redemption_id, None, &checkout.provider, &checkout.provider_session_id).await?;
+        }
+        checkout.into_active_model().reset_all().insert(&txn).await.map_err_print(|e| match e.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(_)) => LocalErr::new(LocalErrKind::CheckoutPending, StatusCode::CONFLICT)
+                .with_msg("a subscription checkout is already open"),
+            _ => e.into(),
+        })?;
+
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Gives up a checkout session that was expired at the provider, so it gives back its coupon right away
+    pub async fn expire_checkout(&self, checkout: &subscription_checkout::Model) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+        Self::close(&txn, &checkout.provider, &checkout.provider_session_id, PaymentStatus::Expired).await?;
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    /// Moves the checkout session to the `status` reported by the provider, one that wasn't paid gives back its coupon.
+    /// `event_id` is the webhook event marked as processed in the same transaction
+    pub async fn close_checkout(&self, event_id: &str, provider: &str, session_id: &str, status: PaymentStatus) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+        Self::close(&txn, provider, session_id, status).await?;
+        PaymentWebhookRepository::mark_processed(&txn, event_id).await?;
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+
+    async fn close<C: ConnectionTrait>(db: &C, provider: &str, session_id: &str, status: PaymentStatus) -> LocalResult<()> {
+        subscription_checkout::Entity::update_many()
+            .col_expr(subscription_checkout::Column::Status, Expr::value(status))
+            .col_expr(subscription_checkout::Column::UpdateDate, Expr::value(Utc::now()))
+            .filter(subscription_checkout::Column::Provider.eq(provider))
+            .filter(subscription_checkout::Column::ProviderSessionId.eq(session_id))
+            .filter(subscription_checkout::Column::Status.is_in([PaymentStatus::Pending, PaymentStatus::Expired]))
+            .exec(db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        match status {
+            PaymentStatus::Succeeded => Ok(()),
+            _ => CouponRepository::release(db, provider, session_id).await,
+        }
+    }
+
+    /// Whether the user of the reported subscription already has another one that hasn't ended
+    pub async fn has_other_live_subscription(&self, provider: &str, reported: &ProviderSubscription) -> LocalResult<bool> {
+        let Some(user_id) = reported.user_id else {
+            return Ok(false)
+        };
+
+        subscription::Entity::find()
+            .filter(subscription::Column::UserId.eq(user_id))
+            .filter(subscription::Column::Status.is_in([SubscriptionStatus::Trialing, SubscriptionStatus::Active, SubscriptionStatus::PastDue]))
+            .filter(Condition::any()
+                .add(subscription::Column::Provider.ne(provider))
+                .add(subscription::Column::ProviderSubscriptionId.ne(&reported.id)))
+            .count(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|count| count > 0)
+    }
+
+    /// Mirrors what the provider reported, at `event_date` provider time. Reports older than the last applied
+    /// one and transitions out of an ended subscription are ignored (providers don't guarantee the order of events).
+    /// Responses of the provider to our own requests have no `event_date`: they are applied without moving
+    /// `last_event_date`, local time can't be compared with the provider's and the event about the change follows.
+    /// `event_id` is the webhook event marked as processed in the same transaction, if any
+    pub async fn apply_subscription(&self, event_id: Option<&str>, provider: &str, reported: &ProviderSubscription, event_date: Option<DateTime<Utc>>) -> LocalResult<()> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let current = subscription::Entity::find()
+            .filter(subscription::Column::Provider.eq(provider))
+            .filter(subscription::Column::ProviderSubscriptionId.eq(&reported.id))
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        let changed = match (current, reported.status) {
+            // not paid yet
+            (None, None) => None,
+            (None, Some(status)) => {
+                let (Some(user_id), Some(plan_id)) = (reported.user_id, reported.plan_id) else {
+                    return Err(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND).with_msg("subscription without user_id/plan_id metadata"))
+                };
+
+                let subscription = subscription::ActiveModel {
+                    id: Set(uuid::Uuid::new_v4()),
+                    user_id: Set(user_id),
+                    plan_id: Set(plan_id),
+                    status: Set(status),
+                    provider: Set(provider.to_string()),
+                    provider_subscription_id: Set(reported.id.clone()),
+                    current_period_start: Set(reported.current_period_start.into()),
+                    current_period_end: Set(reported.current_period_end.into()),
+                    trial_end: Set(reported.trial_end.map(Into::into)),
+                    cancel_at_period_end: Set(reported.cancel_at_period_end),
+                    canceled_at: Set(reported.canceled_at.map(Into::into)),
+                    last_event_date: Set(event_date.unwrap_or(DateTime::UNIX_EPOCH).into()),
+                    creation_date: Set(Utc::now().into()),
+                    update_date: Set(Utc::now().into()),
+                };
+                Some(subscription.insert(&txn).await.map_err_print(LocalErr::from)?)
+            },
+            (Some(current), status) => {
+                // the provider cancels a subscription whose renewal kept failing, it ended unpaid
+                let status = match status.unwrap_or(current.status) {
+                    SubscriptionStatus::Canceled if current.status == SubscriptionStatus::PastDue && !current.cancel_at_period_end => SubscriptionStatus::Expired,
+                    status => status,
+                };
+
+                if event_date.is_some_and(|d| d < current.last_event_date.to_utc()) || !current.status.can_become(status) {
+                    None
+                } else {
+                    let mut subscription = current.into_active_model();
+                    if let Some(plan_id) = reported.plan_id {
+                        subscription.plan_id = Set(plan_id);
+                    }
+                    subscription.status = Set(status);
+                    subscription.current_period_start = Set(reported.current_period_start.into());
+                    subscription.current_period_end = Set(reported.current_period_end.into());
+                    subscription.trial_end = Set(reported.trial_end.map(Into::into));
+                    subscription.cancel_at_period_end = Set(reported.cancel_at_period_end);
+                    subscription.canceled_at = Set(reported.canceled_at.map(Into::into));
+                    if let Some(event_date) = event_date {
+                        subscription.last_event_date = Set(event_date.into());
+                    }
+                    subscription.update_date = Set(Utc::now().into());
+                    Some(subscription.update(&txn).await.map_err_print(LocalErr::from)?)
+                }
+            },
+        };
+
+        if let Some(subscription) = changed {
+            let plan = plan::Entity::find_by_id(subscription.plan_id)
+                .one(&txn)
+                .await
+                .map_err_print(LocalErr::from)?
+                .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND).with_msg("unknown plan"))?;
+
+            OutboxRepository::add_event(&txn, IdentityEvent::SubscriptionChanged(SubscriptionChanged {
+                subscription_id: subscription.id,
+                user_id: subscription.user_id,
+                plan: plan.code,
+                status: subscription.status,
+                current_period_end: subscription.current_period_end.to_utc(),
+                cancel_at_period_end: subscription.cancel_at_period_end,
+            })).await?;
+        }
+
+        if let Some(event_id) = event_id {
+            PaymentWebhookRepository::mark_processed(&txn, event_id).await?;
+        }
+        txn.commit().await.map_err_print(LocalErr::from)
+    }
+}
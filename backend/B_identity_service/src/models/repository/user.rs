@@ -1,16 +1,24 @@
-use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter};
+use chrono::{NaiveDate, Utc};
+use moka::future::Cache;
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait, ActiveValue::Set, sea_query::{LockBehavior, LockType}};
 
-use crate::{error::{LocalResult, MapErrPrint}, models::entity::user};
+use crate::{config::CONFIG, error::{LocalErr, LocalResult, MapErrPrint}, events::{IdentityEvent, PasswordChanged, UserDeleted, UserRegistered, UserUpdated}, models::{entity::{common::Password, mfa_recovery_code, session, user, user_mfa, user_token}, repository::{audit::{AuditEntry, AuditRepository}, outbox::OutboxRepository, role::RoleRepository, session::SessionRepository}}};
 
 
 #[derive(Clone)]
 pub struct UserRepository {
-    db: DatabaseConnection
+    db: DatabaseConnection,
+    versions: Cache<uuid::Uuid, Option<(uuid::Uuid, uuid::Uuid)>>, // version and entitlements version, None for missing/inactive users
 }
 
 impl UserRepository {
     pub fn new(db: DatabaseConnection) -> Self {
-        Self { db }
+        let versions = Cache::builder()
+            .max_capacity(100_000)
+            .time_to_live(CONFIG.user_cache_exp_time.to_std().unwrap_or_default())
+            .build();
+
+        Self { db, versions }
     }
 
     pub async fn get_user_by(&self, filters: Condition) -> LocalResult<Option<user::Model>> {
@@ -25,7 +33,211 @@ impl UserRepository {
             .map_err_print(|e| e.into())
     }
 
-    pub async fn insert_user(&self, user: user::ActiveModel) -> LocalResult<user::Model> {
-        user.insert(&self.db).await.map_err_print(|e| e.into())
+    /// Any user, active or not (for staff)
+    pub async fn get_any_user(&self, id: uuid::Uuid) -> LocalResult<Option<user::Model>> {
+        user::Entity::find_by_id(id)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+
+    /// Page `page` (from 0) of the users matching `filters`, newest first, and the total count
+    pub async fn list_users(&self, filters: Condition, page: u64, per_page: u64) -> LocalResult<(Vec<user::Model>, u64)> {
+        let paginator = user::Entity::find()
+            .filter(filters)
+            .order_by_desc(user::Column::CreationDate)
+            .paginate(&self.db, per_page);
+
+        let total = paginator.num_items().await.map_err_print(LocalErr::from)?;
+        let users = paginator.fetch_page(page).await.map_err_print(LocalErr::from)?;
+        Ok((users, total))
+    }
+
+    /// Unlike `get_user_by`, inactive users count too (they keep their email/username)
+    pub async fn exists_user(&self, filters: Condition) -> LocalResult<bool> {
+        user::Entity::find()
+            .filter(filters)
+            .count(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|count| count > 0)
+    }
+
+    /// Inserts the user with its first role, queuing `user.registered`
+    pub async fn insert_user(&self, user: user::ActiveModel, role: &str) -> LocalResult<user::Model> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let user = user.insert(&txn).await.map_err_print(LocalErr::from)?;
+        let roles = vec![role.to_string()];
+        RoleRepository::insert_roles(&txn, user.id, &roles).await?;
+
+        OutboxRepository::add_event(&txn, IdentityEvent::UserRegistered(UserRegistered {
+            user_id: user.id,
+            username: user.username.clone(),
+            email: user.email.clone(),
+            roles,
+            creation_date: user.creation_date.to_utc(),
+        })).await?;
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(user)
+    }
+
+    /// Updates the set fields of `user` (its `id` must be set), queuing
+    /// `user.password_changed` and/or `user.updated` when relevant fields change
+    pub async fn update_user(&self, user: user::ActiveModel) -> LocalResult<user::Model> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+        let user = Self::update(&txn, user).await?;
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        self.versions.invalidate(&user.id).await;
+        Ok(user)
+    }
+
+    /// Staff change: `update_user`, optionally revoking every session of the user, with `audit` in the same transaction
+    pub async fn update_user_audited(&self, user: user::ActiveModel, revoke_sessions: bool, audit: AuditEntry) -> LocalResult<user::Model> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+        let user = Self::update(&txn, user).await?;
+        if revoke_sessions {
+            SessionRepository::revoke_all(&txn, user.id).await?;
+        }
+        AuditRepository::record(&txn, audit).await?;
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        self.versions.invalidate(&user.id).await;
+        Ok(user)
+    }
+
+    async fn update<C: ConnectionTrait>(db: &C, user: user::ActiveModel) -> LocalResult<user::Model> {
+        let password_changed = user.password_hash.is_set();
+        let profile_changed = user.email.is_set()
+            || user.username.is_set()
+            || user.avatar.is_set()
+            || user.banner.is_set()
+            || user.is_active.is_set()
+            || user.email_verified_at.is_set();
+
+        let user = user.update(db).await.map_err_print(LocalErr::from)?;
+
+        if password_changed {
+            OutboxRepository::add_event(db, IdentityEvent::PasswordChanged(PasswordChanged {
+                user_id: user.id,
+                change_date: Utc::now(),
+            })).await?;
+        }
+        if profile_changed {
+            OutboxRepository::add_event(db, IdentityEvent::UserUpdated(UserUpdated {
+                user_id: user.id,
+                username: user.username.clone(),
+                email: user.email.clone(),
+                email_verified: user.email_verified_at.is_some(),
+                avatar: user.avatar.clone(),
+                banner: user.banner.clone(),
+                is_active: user.is_active,
+            })).await?;
+        }
+        Ok(user)
+    }
+
+    /// Anonymises (see `anonymize`) up to `limit` users whose deletion grace period is over, in one transaction,
+    /// and returns them as they were. Their rows are locked and skipped by other instances sweeping at the same time,
+    /// so nobody is anonymised twice
+    pub async fn anonymize_due_users(&self, limit: u64) -> LocalResult<Vec<user::Model>> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let users = user::Entity::find()
+            .filter(user::Column::DeletionScheduledAt.lte(Utc::now()))
+            .filter(user::Column::DeletionDate.is_null())
+            .limit(limit)
+            .lock_with_behavior(LockType::Update, LockBehavior::SkipLocked)
+            .all(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        for user in &users {
+            Self::anonymize(&txn, user.id).await?;
+        }
+        txn.commit().await.map_err_print(LocalErr::from)?;
+
+        for user in &users {
+            self.versions.invalidate(&user.id).await;
+        }
+        Ok(users)
     }
-}
\ No newline at end of file
+
+    /// Removes the PII of the user and everything tied to their credentials, keeping the row
+    /// (and its id) so audit entries and other services' references stay consistent.
+    /// `user.deleted` is queued in the same transaction
+    async fn anonymize<C: ConnectionTrait>(db: &C, user_id: uuid::Uuid) -> LocalResult<()> {
+        let now = Utc::now();
+        let short_id = user_id.simple().to_string();
+
+        // setting the password bumps `version`
+        user::ActiveModel {
+            id: Set(user_id),
+            email: Set(format!("deleted-{}@deleted.invalid", short_id)),
+            username: Set(format!("deleted-{}", short_id)),
+            password_hash: Set(Password("!".to_string())), // never a valid bcrypt hash
+            avatar: Set(None),
+            banner: Set(None),
+            birth_date: Set(NaiveDate::from_ymd_opt(1900, 1, 1).unwrap_or_default()),
+            sex: Set(user::UserSex::Other),
+            email_verified_at: Set(None),
+            is_active: Set(false),
+            deletion_date: Set(Some(now.into())),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+        .map_err_print(LocalErr::from)?;
+
+        session::Entity::delete_many().filter(session::Column::UserId.eq(user_id)).exec(db).await.map_err_print(LocalErr::from)?;
+        user_token::Entity::delete_many().filter(user_token::Column::UserId.eq(user_id)).exec(db).await.map_err_print(LocalErr::from)?;
+        user_mfa::Entity::delete_many().filter(user_mfa::Column::UserId.eq(user_id)).exec(db).await.map_err_print(LocalErr::from)?;
+        mfa_recovery_code::Entity::delete_many().filter(mfa_recovery_code::Column::UserId.eq(user_id)).exec(db).await.map_err_print(LocalErr::from)?;
+
+        OutboxRepository::add_event(db, IdentityEvent::UserDeleted(UserDeleted { user_id, deletion_date: now })).await
+    }
+
+    /// Drops the cached version of a user whose version was changed outside `update_user`,
+    /// or by another instance (see `events::invalidation`)
+    pub async fn forget_version(&self, user_id: uuid::Uuid) {
+        self.versions.invalidate(&user_id).await;
+    }
+
+    /// Whether `version` (from a token) is still the current version of an active user
+    pub async fn is_current_version(&self, user_id: uuid::Uuid, version: uuid::Uuid) -> LocalResult<bool> {
+        Ok(self.current_versions(user_id).await?.is_some_and(|(current, _)| current == version))
+    }
+
+    /// Like `is_current_version`, the courses of an access token must also be the current ones (if it has some)
+    pub async fn is_current_access(&self, user_id: uuid::Uuid, version: uuid::Uuid, entitlements_version: Option<uuid::Uuid>) -> LocalResult<bool> {
+        Ok(self.current_versions(user_id).await?.is_some_and(|(current, current_entitlements)| {
+            current == version && entitlements_version.is_none_or(|v| v == current_entitlements)
+        }))
+    }
+
+    /// The entitlements version of an active user, to issue access tokens with the courses
+    pub async fn get_entitlements_version(&self, user_id: uuid::Uuid) -> LocalResult<Option<uuid::Uuid>> {
+        Ok(self.current_versions(user_id).await?.map(|(_, entitlements_version)| entitlements_version))
+    }
+
+    async fn current_versions(&self, user_id: uuid::Uuid) -> LocalResult<Option<(uuid::Uuid, uuid::Uuid)>> {
+        if let Some(current) = self.versions.get(&user_id).await {
+            return Ok(current);
+        }
+
+        let current = user::Entity::find_by_id(user_id)
+            .filter(user::Column::IsActive.eq(true))
+            .select_only()
+            .column(user::Column::Version)
+            .column(user::Column::EntitlementsVersion)
+            .into_tuple::<(uuid::Uuid, uuid::Uuid)>()
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        self.versions.insert(user_id, current).await;
+        Ok(current)
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::{Duration, Utc};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait, prelude::DateTimeWithTimeZone, sea_query::Expr};
+
+use crate::{error::{LocalErr, LocalResult, MapErrPrint}, models::entity::user_token::{self, UserTokenKind}, utils::token};
+
+
+#[derive(Clone)]
+pub struct UserTokenRepository {
+    db: DatabaseConnection
+}
+
+impl UserTokenRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Creates a new token of `kind` for the user, discarding any previous unused one.
+    /// Returns the plain token, which is never stored.
+    pub async fn create_token(&self, user_id: uuid::Uuid, kind: UserTokenKind, payload: Option<String>, valid_for: Duration) -> LocalResult<String> {
+        let (plain, hash) = token::generate_token();
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let previous = Condition::all()
+            .add(user_token::Column::UserId.eq(user_id))
+            .add(user_token::Column::Kind.eq(kind))
+            .add(user_token::Column::UseDate.is_null());
+
+        user_token::Entity::update_many()
+            .col_expr(user_token::Column::UseDate, Expr::current_timestamp().into())
+            .filter(previous)
+            .exec(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        let model = user_token::ActiveModel {
+            user_id: Set(user_id),
+            kind: Set(kind),
+            token_hash: Set(hash),
+            payload: Set(payload),
+            expiration_date: Set((Utc::now() + valid_for).into()),
+            ..Default::default()
+        };
+        model.insert(&txn).await.map_err_print(LocalErr::from)?;
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(plain)
+    }
+
+    /// Marks the token as used and returns it, or `None` if it is unknown, used or expired
+    pub async fn consume_token(&self, plain: &str, kind: UserTokenKind) -> LocalResult<Option<user_token::Model>> {
+        self.consume(plain, kind, Condition::all()).await
+    }
+
+    /// `consume_token` for a token sent to a signed-in user: one of another user is left untouched
+    pub async fn consume_user_token(&self, plain: &str, kind: UserTokenKind, user_id: uuid::Uuid) -> LocalResult<Option<user_token::Model>> {
+        self.consume(plain, kind, Condition::all().add(user_token::Column::UserId.eq(user_id))).await
+    }
+
+    async fn consume(&self, plain: &str, kind: UserTokenKind, filters: Condition) -> LocalResult<Option<user_token::Model>> {
+        let txn = self.db.begin().await.map_err_print(LocalErr::from)?;
+
+        let condition = Condition::all()
+            .add(user_token::Column::TokenHash.eq(token::hash_token(plain)))
+            .add(user_token::Column::Kind.eq(kind))
+            .add(user_token::Column::UseDate.is_null())
+            .add(user_token::Column::ExpirationDate.gt(Utc::now()))
+            .add(filters);
+
+        let found = user_token::Entity::find()
+            .filter(condition)
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        let Some(found) = found else {
+            return Ok(None)
+        };
+
+        let mut used: user_token::ActiveModel = found.into();
+        used.use_date = Set(Some(Utc::now().into()));
+        let used = used.update(&txn).await.map_err_print(LocalErr::from)?;
+
+        txn.commit().await.map_err_print(LocalErr::from)?;
+        Ok(Some(used))
+    }
+
+    /// When the last token of `kind` was sent to the user, used or not
+    pub async fn last_creation_date(&self, user_id: uuid::Uuid, kind: UserTokenKind) -> LocalResult<Option<DateTimeWithTimeZone>> {
+        let condition = Condition::all()
+            .add(user_token::Column::UserId.eq(user_id))
+            .add(user_token::Column::Kind.eq(kind));
+
+        user_token::Entity::find()
+            .filter(condition)
+            .order_by_desc(user_token::Column::CreationDate)
+            .select_only()
+            .column(user_token::Column::CreationDate)
+            .into_tuple()
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+    }
+}
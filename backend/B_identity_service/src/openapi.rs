@@ -8,7 +8,75 @@ use utoipa::OpenApi;
         crate::routes::endpoints::auth::register,
         crate::routes::endpoints::auth::login,
         crate::routes::endpoints::auth::get_user_profile,
+        crate::routes::endpoints::auth::update_user_profile,
         crate::routes::endpoints::auth::refresh_access_token,
+        crate::routes::endpoints::auth::logout,
+        crate::routes::endpoints::auth::logout_all,
+        crate::routes::endpoints::auth::forgot_password,
+        crate::routes::endpoints::auth::reset_password,
+        crate::routes::endpoints::auth::verify_email,
+        crate::routes::endpoints::auth::resend_verification_email,
+        crate::routes::endpoints::auth::change_password,
+        crate::routes::endpoints::auth::change_email,
+        crate::routes::endpoints::auth::confirm_email_change,
+        crate::routes::endpoints::auth::upload_avatar,
+        crate::routes::endpoints::auth::upload_banner,
+        crate::routes::endpoints::auth::delete_avatar,
+        crate::routes::endpoints::auth::delete_banner,
+        crate::routes::endpoints::auth::delete_account,
+        crate::routes::endpoints::auth::restore_account,
+        crate::routes::endpoints::auth::export_user_data,
+        crate::routes::endpoints::mfa::enroll_mfa,
+        crate::routes::endpoints::mfa::confirm_mfa,
+        crate::routes::endpoints::mfa::disable_mfa,
+        crate::routes::endpoints::mfa::verify_mfa,
+        crate::routes::endpoints::users::get_public_profile,
+        crate::routes::endpoints::well_known::get_jwks,
+        crate::routes::endpoints::internal::introspect,
+        crate::routes::endpoints::internal::get_user_subscription,
+        crate::routes::endpoints::internal::get_user_entitlements,
+        crate::routes::endpoints::internal::check_access,
+        crate::routes::endpoints::admin::list_users,
+        crate::routes::endpoints::admin::get_user,
+        crate::routes::endpoints::admin::deactivate_user,
+        crate::routes::endpoints::admin::reactivate_user,
+        crate::routes::endpoints::admin::force_logout,
+        crate::routes::endpoints::admin::force_password_reset,
+        crate::routes::endpoints::admin::set_user_roles,
+        crate::routes::endpoints::admin::list_user_entitlements,
+        crate::routes::endpoints::admin::grant_entitlement,
+        crate::routes::endpoints::admin::revoke_entitlement,
+        crate::routes::endpoints::admin::list_coupons,
+        crate::routes::endpoints::admin::create_coupon,
+        crate::routes::endpoints::admin::deactivate_coupon,
+        crate::routes::endpoints::admin::list_order_refunds,
+        crate::routes::endpoints::admin::refund_order,
+        crate::routes::endpoints::admin::list_webhook_events,
+        crate::routes::endpoints::admin::retry_webhook_event,
+        crate::routes::endpoints::payments::create_checkout,
+        crate::routes::endpoints::payments::list_orders,
+        crate::routes::endpoints::payments::get_order,
+        crate::routes::endpoints::payments::refund_own_order,
+        crate::routes::endpoints::payments::list_refunds,
+        crate::routes::endpoints::payments::list_invoices,
+        crate::routes::endpoints::payments::get_invoice,
+        crate::routes::endpoints::payments::get_invoice_html,
+        crate::routes::endpoints::payments::get_invoice_pdf,
+        crate::routes::endpoints::payments::payment_webhook,
+        crate::routes::endpoints::payments::fake_checkout_page,
+        crate::routes::endpoints::payments::fake_pay,
+        crate::routes::endpoints::payments::fake_cancel,
+        crate::routes::endpoints::payments::fake_renew_subscription,
+        crate::routes::endpoints::payments::fake_fail_subscription,
+        crate::routes::endpoints::payments::fake_open_dispute,
+        crate::routes::endpoints::payments::fake_win_dispute,
+        crate::routes::endpoints::payments::fake_lose_dispute,
+        crate::routes::endpoints::subscriptions::list_plans,
+        crate::routes::endpoints::subscriptions::subscribe,
+        crate::routes::endpoints::subscriptions::get_subscription,
+        crate::routes::endpoints::subscriptions::cancel_subscription,
+        crate::routes::endpoints::subscriptions::resume_subscription,
+        crate::routes::endpoints::subscriptions::change_plan,
     )
 )]
 pub struct ApiDocs;
\ No newline at end of file
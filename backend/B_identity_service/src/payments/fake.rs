@@ -0,0 +1,482 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Months, Utc};
+use serde_json::json;
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult}, models::entity::{plan::BillingInterval, refund::RefundStatus, subscription::SubscriptionStatus}, payments::{Checkout, CheckoutSession, CheckoutStatus, DisputeStatus, PaymentProvider, ProviderRefund, ProviderSubscription, Refund, SubscriptionCheckout, SubscriptionUpdate, UpdatedSubscription, proration}};
+
+/// Checkout pages served by this service at `/api/payments/fake`, for development without network.
+/// Sessions only live in memory: they are lost on restart and not shared between instances
+#[derive(Default)]
+pub struct FakePaymentProvider {
+    sessions: Mutex<HashMap<String, FakeSession>>,
+    subscriptions: Mutex<HashMap<String, FakeSubscription>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeSession {
+    pub id: String,
+    pub amount: i64,
+    pub currency: String,
+    pub description: String,
+    pub success_url: String,
+    pub cancel_url: String,
+    pub status: CheckoutStatus,
+    pub payment_id: Option<String>,
+    pub refunded: i64, // refunds always succeed right away
+    pub subscription: Option<FakeSubscriptionCheckout>, // subscription mode
+    pub subscription_id: Option<String>, // set once paid in subscription mode
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeSubscriptionCheckout {
+    pub user_id: uuid::Uuid,
+    pub plan_id: uuid::Uuid,
+    pub amount: i64, // per interval, the session amount is the first payment
+    pub interval: BillingInterval,
+    pub trial_days: u32,
+}
+
+/// Renewed on demand at `/api/payments/fake/subscriptions/{id}/renew`, no time passes on its own
+#[derive(Debug, Clone)]
+pub struct FakeSubscription {
+    pub id: String,
+    pub user_id: uuid::Uuid,
+    pub plan_id: uuid::Uuid,
+    pub amount: i64, // per interval
+    pub currency: String,
+    pub interval: BillingInterval,
+    pub status: SubscriptionStatus,
+    pub current_period_start: DateTime<Utc>,
+    pub current_period_end: DateTime<Utc>,
+    pub trial_end: Option<DateTime<Utc>>,
+    pub cancel_at_period_end: bool,
+    pub canceled_at: Option<DateTime<Utc>>,
+}
+
+impl From<&FakeSession> for CheckoutSession {
+    fn from(session: &FakeSession) -> Self {
+        Self {
+            id: session.id.clone(),
+            url: format!("{}/api/payments/fake/{}", CONFIG.public_url, session.id),
+            status: session.status,
+            payment_id: session.payment_id.clone(),
+        }
+    }
+}
+
+impl From<&FakeSubscription> for ProviderSubscription {
+    fn from(subscription: &FakeSubscription) -> Self {
+        Self {
+            id: subscription.id.clone(),
+            user_id: Some(subscription.user_id),
+            plan_id: Some(subscription.plan_id),
+            status: Some(subscription.status),
+            current_period_start: subscription.current_period_start,
+            current_period_end: subscription.current_period_end,
+            trial_end: subscription.trial_end,
+            cancel_at_period_end: subscription.cancel_at_period_end,
+            canceled_at: subscription.canceled_at,
+        }
+    }
+}
+
+fn add_interval(date: DateTime<Utc>, interval: BillingInterval) -> DateTime<Utc> {
+    date.checked_add_months(Months::new(interval.months())).unwrap_or(date)
+}
+
+fn fake_event(event_type: &str, object: serde_json::Value) -> serde_json::Value {
+    json!({
+        "id": format!("fake_evt_{}", uuid::Uuid::new_v4().simple()),
+        "object": "event",
+        "type": event_type,
+        "created": Utc::now().timestamp(),
+        "data": { "object": object }
+    })
+}
+
+impl FakePaymentProvider {
+    pub fn get_session(&self, session_id: &str) -> Option<FakeSession> {
+        self.sessions.lock().unwrap().get(session_id).cloned()
+    }
+
+    /// Completes an open session, returning it
+    pub fn pay(&self, session_id: &str) -> Option<FakeSession> {
+        self.close(session_id, CheckoutStatus::Paid)
+    }
+
+    /// Expires an open session, returning it
+    pub fn cancel(&self, session_id: &str) -> Option<FakeSession> {
+        self.close(session_id, CheckoutStatus::Expired)
+    }
+
+    /// Stripe-shaped `checkout.session.*` event about the session, to be signed and delivered
+    /// to the webhook like a real one
+    pub fn event(&self, session: &FakeSession) -> serde_json::Value {
+        let (event_type, status, payment_status) = match session.status {
+            CheckoutStatus::Open => ("checkout.session.created", "open", "unpaid"),
+            CheckoutStatus::Paid => ("checkout.session.completed", "complete", "paid"),
+            CheckoutStatus::Failed => ("checkout.session.async_payment_failed", "complete", "unpaid"),
+            CheckoutStatus::Expired => ("checkout.session.expired", "expired", "unpaid"),
+        };
+
+        fake_event(event_type, json!({
+            "id": session.id,
+            "object": "checkout.session",
+            "mode": if session.subscription.is_some() { "subscription" } else { "payment" },
+            "status": status,
+            "payment_status": payment_status,
+            "payment_intent": session.payment_id,
+            "subscription": session.subscription_id,
+            "amount_total": session.amount,
+            "currency": session.currency,
+        }))
+    }
+
+    /// Stripe-shaped `charge.dispute.*` event about the payment of a paid session, `None` if there is no such payment
+    pub fn dispute_event(&self, payment_id: &str, status: DisputeStatus) -> Option<serde_json::Value> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.values().find(|s| s.payment_id.as_deref() == Some(payment_id))?;
+
+        let (event_type, status) = match status {
+            DisputeStatus::Open => ("charge.dispute.created", "needs_response"),
+            DisputeStatus::Won => ("charge.dispute.closed", "won"),
+            DisputeStatus::Lost => ("charge.dispute.closed", "lost"),
+        };
+        Some(fake_event(event_type, json!({
+            "id": format!("fake_dp_{}", session.id.trim_start_matches("fake_cs_")), // one dispute per payment
+            "object": "dispute",
+            "status": status,
+            "payment_intent": payment_id,
+            "amount": session.amount,
+            "currency": session.currency,
+        })))
+    }
+
+    pub fn get_subscription(&self, subscription_id: &str) -> Option<FakeSubscription> {
+        self.subscriptions.lock().unwrap().get(subscription_id).cloned()
+    }
+
+    /// Ends the current period: a new one is paid, or the subscription ends if it was canceled at period end
+    pub fn renew(&self, subscription_id: &str) -> Option<FakeSubscription> {
+        self.update(subscription_id, |s| {
+            let now = Utc::now();
+            if s.cancel_at_period_end {
+                s.status = SubscriptionStatus::Canceled;
+                s.canceled_at = Some(now);
+                return
+            }
+
+            s.status = SubscriptionStatus::Active;
+            s.trial_end = s.trial_end.filter(|t| *t > now);
+            s.current_period_start = s.current_period_end;
+            s.current_period_end = add_interval(s.current_period_end, s.interval);
+        })
+    }
+
+    /// The renewal payment fails, a second failure ends the subscription (retries exhausted)
+    pub fn fail(&self, subscription_id: &str) -> Option<FakeSubscription> {
+        self.update(subscription_id, |s| match s.status {
+            SubscriptionStatus::PastDue => {
+                s.status = SubscriptionStatus::Canceled;
+                s.canceled_at = Some(Utc::now());
+            },
+            _ => s.status = SubscriptionStatus::PastDue,
+        })
+    }
+
+    /// Stripe-shaped `customer.subscription.*` event about the subscription
+    pub fn subscription_event(&self, subscription: &FakeSubscription, created: bool) -> serde_json::Value {
+        let (event_type, status) = match subscription.status {
+            _ if created => ("customer.subscription.created", subscription.status),
+            SubscriptionStatus::Canceled | SubscriptionStatus::Expired => ("customer.subscription.deleted", subscription.status),
+            status => ("customer.subscription.updated", status),
+        };
+        let status = match status {
+            SubscriptionStatus::Trialing => "trialing",
+            SubscriptionStatus::Active => "active",
+            SubscriptionStatus::PastDue => "past_due",
+            SubscriptionStatus::Canceled => "canceled",
+            SubscriptionStatus::Expired => "incomplete_expired",
+        };
+
+        fake_event(event_type, json!({
+            "id": subscription.id,
+            "object": "subscription",
+            "status": status,
+            "metadata": {
+                "user_id": subscription.user_id.to_string(),
+                "plan_id": subscription.plan_id.to_string(),
+            },
+            "current_period_start": subscription.current_period_start.timestamp(),
+            "current_period_end": subscription.current_period_end.timestamp(),
+            "trial_end": subscription.trial_end.map(|t| t.timestamp()),
+            "cancel_at_period_end": subscription.cancel_at_period_end,
+            "canceled_at": subscription.canceled_at.map(|t| t.timestamp()),
+            "items": { "data": [{ "id": format!("{}_item", subscription.id) }] },
+        }))
+    }
+
+    /// Stripe-shaped `invoice.paid` event, for a payment of `amount` on the subscription
+    pub fn invoice_event(&self, subscription: &FakeSubscription, amount: i64) -> serde_json::Value {
+        fake_event("invoice.paid", json!({
+            "id": format!("fake_in_{}", uuid::Uuid::new_v4().simple()),
+            "object": "invoice",
+            "subscription": subscription.id,
+            "amount_paid": amount,
+            "currency": subscription.currency,
+            "status": "paid",
+        }))
+    }
+
+    /// Only subscriptions that haven't ended can change
+    fn update(&self, subscription_id: &str, change: impl FnOnce(&mut FakeSubscription)) -> Option<FakeSubscription> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let subscription = subscriptions.get_mut(subscription_id).filter(|s| s.status.is_live())?;
+
+        change(subscription);
+        Some(subscription.clone())
+    }
+
+    fn close(&self, session_id: &str, status: CheckoutStatus) -> Option<FakeSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(session_id).filter(|s| s.status == CheckoutStatus::Open)?;
+
+        session.status = status;
+        if status != CheckoutStatus::Paid {
+            return Some(session.clone())
+        }
+
+        match &session.subscription {
+            Some(checkout) => {
+                let now = Utc::now();
+                let trial_end = (checkout.trial_days > 0).then(|| now + Duration::days(checkout.trial_days.into()));
+                let subscription = FakeSubscription {
+                    id: format!("fake_sub_{}", uuid::Uuid::new_v4().simple()),
+                    user_id: checkout.user_id,
+                    plan_id: checkout.plan_id,
+                    amount: checkout.amount,
+                    currency: session.currency.clone(),
+                    interval: checkout.interval,
+                    status: if trial_end.is_some() { SubscriptionStatus::Trialing } else { SubscriptionStatus::Active },
+                    current_period_start: now,
+                    current_period_end: trial_end.unwrap_or_else(|| add_interval(now, checkout.interval)),
+                    trial_end,
+                    cancel_at_period_end: false,
+                    canceled_at: None,
+                };
+
+                session.subscription_id = Some(subscription.id.clone());
+                self.subscriptions.lock().unwrap().insert(subscription.id.clone(), subscription);
+            },
+            None => session.payment_id = Some(format!("fake_pi_{}", uuid::Uuid::new_v4().simple())),
+        }
+        Some(session.clone())
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for FakePaymentProvider {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn create_checkout_session(&self, checkout: Checkout<'_>) -> LocalResult<CheckoutSession> {
+        let session = FakeSession {
+            id: format!("fake_cs_{}", uuid::Uuid::new_v4().simple()),
+            amount: checkout.amount,
+            currency: checkout.currency.to_string(),
+            description: checkout.description.to_string(),
+            success_url: checkout.success_url,
+            cancel_url: checkout.cancel_url,
+            status: CheckoutStatus::Open,
+            payment_id: None,
+            refunded: 0,
+            subscription: None,
+            subscription_id: None,
+        };
+
+        let checkout_session = CheckoutSession::from(&session);
+        self.sessions.lock().unwrap().insert(session.id.clone(), session);
+        Ok(checkout_session)
+    }
+
+    async fn create_subscription_checkout(&self, checkout: SubscriptionCheckout<'_>) -> LocalResult<CheckoutSession> {
+        let session = FakeSession {
+            id: format!("fake_cs_{}", uuid::Uuid::new_v4().simple()),
+            amount: checkout.amount - checkout.discount, // first payment
+            currency: checkout.currency.to_string(),
+            description: checkout.description.to_string(),
+            success_url: checkout.success_url,
+            cancel_url: checkout.cancel_url,
+            status: CheckoutStatus::Open,
+            payment_id: None,
+            refunded: 0,
+            subscription: Some(FakeSubscriptionCheckout {
+                user_id: checkout.user_id,
+                plan_id: checkout.plan_id,
+                amount: checkout.amount,
+                interval: checkout.interval,
+                trial_days: checkout.trial_days,
+            }),
+            subscription_id: None,
+        };
+
+        let checkout_session = CheckoutSession::from(&session);
+        self.sessions.lock().unwrap().insert(session.id.clone(), session);
+        Ok(checkout_session)
+    }
+
+    async fn expire_checkout_session(&self, session_id: &str) -> LocalResult<()> {
+        match self.get_session(session_id).map(|s| s.status) {
+            Some(CheckoutStatus::Paid) => Err(LocalErr::new(LocalErrKind::PaymentProviderError, StatusCode::BAD_GATEWAY)
+                .with_msg("the session was already paid")),
+            _ => {
+                self.cancel(session_id);
+                Ok(())
+            },
+        }
+    }
+
+    async fn update_subscription(&self, subscription_id: &str, update: SubscriptionUpdate<'_>) -> LocalResult<UpdatedSubscription> {
+        let mut invoiced_amount = 0;
+        let subscription = self.update(subscription_id, |s| match update {
+            SubscriptionUpdate::CancelAtPeriodEnd(cancel) => s.cancel_at_period_end = cancel,
+            SubscriptionUpdate::CancelNow => {
+                s.status = SubscriptionStatus::Canceled;
+                s.canceled_at = Some(Utc::now());
+            },
+            SubscriptionUpdate::ChangePlan { plan_id, amount, interval, .. } => {
+                // the difference is charged right away (nothing during the trial), a new interval starts a new period
+                let now = Utc::now();
+                if s.status != SubscriptionStatus::Trialing {
+                    invoiced_amount = proration(s.amount, s.interval, amount, interval, s.current_period_start, s.current_period_end, now);
+                }
+                if interval != s.interval {
+                    s.current_period_start = now;
+                    s.current_period_end = add_interval(now, interval);
+                }
+                s.plan_id = plan_id;
+                s.amount = amount;
+                s.interval = interval;
+            },
+        });
+
+        subscription
+            .as_ref()
+            .map(|s| UpdatedSubscription { subscription: s.into(), invoiced_amount })
+            .ok_or(LocalErr::new(LocalErrKind::PaymentProviderError, StatusCode::BAD_GATEWAY)
+                .with_msg("unknown subscription (fake subscriptions don't survive a restart)"))
+    }
+
+    async fn create_refund(&self, refund: Refund<'_>) -> LocalResult<ProviderRefund> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.values_mut()
+            .find(|s| s.payment_id.as_deref() == Some(refund.payment_id))
+            .ok_or(LocalErr::new(LocalErrKind::PaymentRefused, StatusCode::BAD_GATEWAY)
+                .with_msg("unknown payment (fake payments don't survive a restart)"))?;
+
+        if session.refunded + refund.amount > session.amount {
+            return Err(LocalErr::new(LocalErrKind::PaymentRefused, StatusCode::BAD_GATEWAY)
+                .with_msg("the refund exceeds what was paid"))
+        }
+        session.refunded += refund.amount;
+
+        Ok(ProviderRefund {
+            id: format!("fake_re_{}", uuid::Uuid::new_v4().simple()),
+            refund_id: Some(refund.refund_id),
+            payment_id: Some(refund.payment_id.to_string()),
+            charge_id: None,
+            amount: refund.amount,
+            status: RefundStatus::Succeeded,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider_with(session_id: &str, amount: i64, subscription: Option<FakeSubscriptionCheckout>) -> FakePaymentProvider {
+        let provider = FakePaymentProvider::default();
+        provider.sessions.lock().unwrap().insert(session_id.to_string(), FakeSession {
+            id: session_id.to_string(),
+            amount,
+            currency: "eur".to_string(),
+            description: "Course".to_string(),
+            success_url: String::new(),
+            cancel_url: String::new(),
+            status: CheckoutStatus::Open,
+            payment_id: None,
+            refunded: 0,
+            subscription,
+            subscription_id: None,
+        });
+        provider
+    }
+
+    #[test]
+    fn sessions_are_closed_once() {
+        let provider = provider_with("cs_1", 1000, None);
+
+        let paid = provider.pay("cs_1").unwrap();
+        assert_eq!(paid.status, CheckoutStatus::Paid);
+        assert!(paid.payment_id.is_some());
+
+        assert!(provider.pay("cs_1").is_none());
+        assert!(provider.cancel("cs_1").is_none());
+        assert!(provider.pay("cs_unknown").is_none());
+    }
+
+    #[test]
+    fn canceled_sessions_are_expired_without_payment() {
+        let provider = provider_with("cs_1", 1000, None);
+
+        let expired = provider.cancel("cs_1").unwrap();
+        assert_eq!(expired.status, CheckoutStatus::Expired);
+        assert!(expired.payment_id.is_none());
+    }
+
+    #[tokio::test]
+    async fn refunds_cannot_exceed_the_payment() {
+        let provider = provider_with("cs_1", 1000, None);
+        let payment_id = provider.pay("cs_1").unwrap().payment_id.unwrap();
+        let refund = |amount| Refund { refund_id: uuid::Uuid::new_v4(), payment_id: &payment_id, amount };
+
+        let first = provider.create_refund(refund(600)).await.unwrap();
+        assert_eq!(first.status, RefundStatus::Succeeded);
+        assert!(provider.create_refund(refund(500)).await.is_err());
+        assert!(provider.create_refund(refund(400)).await.is_ok());
+    }
+
+    #[test]
+    fn subscriptions_end_after_two_failed_renewals() {
+        let checkout = FakeSubscriptionCheckout { user_id: uuid::Uuid::new_v4(), plan_id: uuid::Uuid::new_v4(), amount: 1000, interval: BillingInterval::Month, trial_days: 0 };
+        let provider = provider_with("cs_1", 1000, Some(checkout));
+        let subscription_id = provider.pay("cs_1").unwrap().subscription_id.unwrap();
+
+        assert_eq!(provider.get_subscription(&subscription_id).unwrap().status, SubscriptionStatus::Active);
+        assert_eq!(provider.fail(&subscription_id).unwrap().status, SubscriptionStatus::PastDue);
+        assert_eq!(provider.fail(&subscription_id).unwrap().status, SubscriptionStatus::Canceled);
+        assert!(provider.renew(&subscription_id).is_none());
+    }
+
+    #[test]
+    fn renewals_move_the_period_or_end_canceled_subscriptions() {
+        let checkout = FakeSubscriptionCheckout { user_id: uuid::Uuid::new_v4(), plan_id: uuid::Uuid::new_v4(), amount: 1000, interval: BillingInterval::Month, trial_days: 7 };
+        let provider = provider_with("cs_1", 0, Some(checkout));
+        let subscription = provider.get_subscription(&provider.pay("cs_1").unwrap().subscription_id.unwrap()).unwrap();
+        assert_eq!(subscription.status, SubscriptionStatus::Trialing);
+
+        let renewed = provider.renew(&subscription.id).unwrap();
+        assert_eq!(renewed.status, SubscriptionStatus::Active);
+        assert_eq!(renewed.current_period_start, subscription.current_period_end);
+
+        provider.update(&subscription.id, |s| s.cancel_at_period_end = true);
+        let ended = provider.renew(&subscription.id).unwrap();
+        assert_eq!(ended.status, SubscriptionStatus::Canceled);
+        assert!(ended.canceled_at.is_some());
+    }
+}
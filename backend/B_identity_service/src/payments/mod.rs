@@ -0,0 +1,218 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{error::LocalResult, models::entity::{plan::BillingInterval, refund::RefundStatus, subscription::SubscriptionStatus}};
+
+mod fake;
+mod stripe;
+pub mod webhook;
+
+pub use fake::{FakePaymentProvider, FakeSession, FakeSubscription};
+pub use stripe::StripePaymentProvider;
+
+/// What the buyer is asked to pay
+pub struct Checkout<'a> {
+    pub order_id: uuid::Uuid, // sent as the provider's client reference
+    pub amount: i64, // minor units
+    pub currency: &'a str,
+    pub description: &'a str,
+    pub customer_email: &'a str,
+    pub success_url: String,
+    pub cancel_url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckoutStatus {
+    Open,
+    Paid,
+    Failed, // delayed payment methods (e.g. bank debits) can fail after the checkout
+    Expired,
+}
+
+/// Hosted payment page at the provider
+#[derive(Debug, Clone)]
+pub struct CheckoutSession {
+    pub id: String,
+    pub url: String,
+    pub status: CheckoutStatus,
+    pub payment_id: Option<String>, // set once paid
+}
+
+/// Checkout of the first period of a subscription
+pub struct SubscriptionCheckout<'a> {
+    pub user_id: uuid::Uuid, // both ids are kept in the subscription metadata
+    pub plan_id: uuid::Uuid,
+    pub price_id: Option<&'a str>, // plan price at the provider
+    pub amount: i64, // minor units, per interval
+    pub currency: &'a str,
+    pub interval: BillingInterval,
+    pub trial_days: u32,
+    pub discount: i64, // minor units off the first payment (coupon)
+    pub description: &'a str,
+    pub customer_email: &'a str,
+    pub success_url: String,
+    pub cancel_url: String,
+}
+
+pub enum SubscriptionUpdate<'a> {
+    CancelAtPeriodEnd(bool), // `false` resumes it
+    /// Ends it right away and refunds what was paid for the current period (a second subscription paid by mistake)
+    CancelNow,
+    /// Charges the prorated difference right away
+    ChangePlan {
+        plan_id: uuid::Uuid,
+        price_id: Option<&'a str>,
+        amount: i64, // minor units, per interval
+        interval: BillingInterval,
+    },
+}
+
+/// Subscription as reported by the provider
+#[derive(Debug, Clone)]
+pub struct ProviderSubscription {
+    pub id: String,
+    pub user_id: Option<uuid::Uuid>, // from the metadata
+    pub plan_id: Option<uuid::Uuid>,
+    pub status: Option<SubscriptionStatus>, // `None` until the first payment is made
+    pub current_period_start: DateTime<Utc>,
+    pub current_period_end: DateTime<Utc>,
+    pub trial_end: Option<DateTime<Utc>>,
+    pub cancel_at_period_end: bool,
+    pub canceled_at: Option<DateTime<Utc>>,
+}
+
+/// Subscription as reported after one of our updates
+#[derive(Debug, Clone)]
+pub struct UpdatedSubscription {
+    pub subscription: ProviderSubscription,
+    pub invoiced_amount: i64, // minor units invoiced right away (the prorated difference of a plan change), negative when credited
+}
+
+/// Successful payment of a subscription period (or of a plan change) at the provider
+#[derive(Debug, Clone)]
+pub struct ProviderCharge {
+    pub id: String, // provider invoice
+    pub subscription_id: Option<String>,
+    pub amount: i64, // minor units
+    pub currency: String,
+}
+
+/// Money given back on a paid checkout
+pub struct Refund<'a> {
+    pub refund_id: uuid::Uuid, // kept in the provider's metadata, a retried request doesn't refund twice
+    pub payment_id: &'a str, // provider payment (Stripe payment intent)
+    pub amount: i64, // minor units
+}
+
+/// Refund as reported by the provider
+#[derive(Debug, Clone)]
+pub struct ProviderRefund {
+    pub id: String,
+    pub refund_id: Option<uuid::Uuid>, // from the metadata, none for refunds made at the provider (e.g. its dashboard)
+    pub payment_id: Option<String>,
+    pub charge_id: Option<String>, // provider invoice, from the metadata of the refunds of subscription charges
+    pub amount: i64,
+    pub status: RefundStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeStatus {
+    Open,
+    Won, // the money stays with us
+    Lost,
+}
+
+/// Chargeback opened by the buyer's bank
+#[derive(Debug, Clone)]
+pub struct ProviderDispute {
+    pub payment_id: Option<String>,
+    pub status: DisputeStatus,
+}
+
+/// Amount owed (negative: credited) when switching plans at `now`: the unused part of the current period
+/// is credited and the new plan charged for it. Switching interval starts a new period, charged in full.
+/// Only used by the fake provider, the real ones compute it themselves
+pub fn proration(old_amount: i64, old_interval: BillingInterval, new_amount: i64, new_interval: BillingInterval, period_start: DateTime<Utc>, period_end: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+    let period = (period_end - period_start).num_seconds().max(1) as i128;
+    let remaining = ((period_end - now).num_seconds() as i128).clamp(0, period);
+    // rounded to the nearest minor unit
+    let prorate = |amount: i64| ((amount as i128 * remaining + period / 2) / period) as i64;
+
+    let credit = prorate(old_amount);
+    match old_interval == new_interval {
+        true => prorate(new_amount) - credit,
+        false => new_amount - credit,
+    }
+}
+
+/// Hosted checkout provider, modeled on Stripe Checkout.
+/// Payments are only settled by the provider's webhook events (see `webhook`)
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    /// Stored with each payment, webhook events are matched on it and the session id
+    fn name(&self) -> &'static str;
+    async fn create_checkout_session(&self, checkout: Checkout<'_>) -> LocalResult<CheckoutSession>;
+    async fn create_subscription_checkout(&self, checkout: SubscriptionCheckout<'_>) -> LocalResult<CheckoutSession>;
+    /// The session can't be paid anymore, fails if it already was
+    async fn expire_checkout_session(&self, session_id: &str) -> LocalResult<()>;
+    async fn update_subscription(&self, subscription_id: &str, update: SubscriptionUpdate<'_>) -> LocalResult<UpdatedSubscription>;
+    async fn create_refund(&self, refund: Refund<'_>) -> LocalResult<ProviderRefund>;
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn period() -> (DateTime<Utc>, DateTime<Utc>) {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        (start, start + Duration::days(30))
+    }
+
+    #[test]
+    fn same_interval_charges_the_difference_for_the_rest_of_the_period() {
+        let (start, end) = period();
+        let halfway = start + Duration::days(15);
+
+        assert_eq!(proration(1500, BillingInterval::Month, 3000, BillingInterval::Month, start, end, halfway), 750);
+        // downgrades are credited
+        assert_eq!(proration(3000, BillingInterval::Month, 1500, BillingInterval::Month, start, end, halfway), -750);
+        assert_eq!(proration(1500, BillingInterval::Month, 3000, BillingInterval::Month, start, end, start), 1500);
+        assert_eq!(proration(1500, BillingInterval::Month, 3000, BillingInterval::Month, start, end, end), 0);
+    }
+
+    #[test]
+    fn new_interval_is_charged_in_full_minus_the_unused_part() {
+        let (start, end) = period();
+        let ten_days_left = end - Duration::days(10);
+
+        assert_eq!(proration(1500, BillingInterval::Month, 15000, BillingInterval::Year, start, end, ten_days_left), 15000 - 500);
+    }
+
+    #[test]
+    fn rounds_to_the_nearest_minor_unit() {
+        let (start, end) = period();
+        let third = start + Duration::days(10);
+
+        // 2/3 of 1000 and 2000
+        assert_eq!(proration(1000, BillingInterval::Month, 2000, BillingInterval::Month, start, end, third), 1333 - 667);
+    }
+
+    #[test]
+    fn out_of_period_times_are_clamped() {
+        let (start, end) = period();
+
+        assert_eq!(proration(1500, BillingInterval::Month, 3000, BillingInterval::Month, start, end, start - Duration::days(1)), 1500);
+        assert_eq!(proration(1500, BillingInterval::Month, 3000, BillingInterval::Month, start, end, end + Duration::days(1)), 0);
+        assert_eq!(proration(1500, BillingInterval::Month, 3000, BillingInterval::Month, start, start, start), 0);
+    }
+
+    #[test]
+    fn large_amounts_do_not_overflow() {
+        let (start, end) = period();
+        let halfway = start + Duration::days(15);
+
+        assert_eq!(proration(i64::MAX / 2, BillingInterval::Year, i64::MAX / 2, BillingInterval::Year, start, end, halfway), 0);
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, models::entity::{refund::RefundStatus, subscription::SubscriptionStatus}, payments::{Checkout, CheckoutSession, CheckoutStatus, DisputeStatus, PaymentProvider, ProviderCharge, ProviderDispute, ProviderRefund, ProviderSubscription, Refund, SubscriptionCheckout, SubscriptionUpdate, UpdatedSubscription}};
+
+/// Stripe Checkout and Billing over its HTTP API (form-encoded requests, JSON responses)
+pub struct StripePaymentProvider {
+    client: reqwest::Client,
+    api_url: String,
+    secret_key: String,
+}
+
+/// Also the `data.object` of `checkout.session.*` webhook events
+#[derive(Deserialize)]
+pub(super) struct StripeCheckoutSession {
+    id: String,
+    url: Option<String>, // only while the session is open
+    status: Option<String>, // open | complete | expired
+    payment_status: String, // paid | unpaid | no_payment_required
+    payment_intent: Option<String>,
+}
+
+/// Also the `data.object` of `customer.subscription.*` webhook events
+#[derive(Deserialize)]
+pub(super) struct StripeSubscription {
+    id: String,
+    status: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    current_period_start: Option<i64>, // moved to the items in recent API versions
+    current_period_end: Option<i64>,
+    trial_end: Option<i64>,
+    #[serde(default)]
+    cancel_at_period_end: bool,
+    canceled_at: Option<i64>,
+    items: StripeList<StripeSubscriptionItem>,
+}
+
+/// Response to our updates, which expand `latest_invoice`
+#[derive(Deserialize)]
+struct StripeUpdatedSubscription {
+    #[serde(flatten)]
+    subscription: StripeSubscription,
+    latest_invoice: Option<StripeLatestInvoice>,
+}
+
+#[derive(Deserialize)]
+struct StripeLatestInvoice {
+    id: String,
+    total: i64, // negative when credited
+    amount_paid: i64,
+    payment_intent: Option<String>,
+}
+
+/// The `data.object` of `invoice.*` webhook events
+#[derive(Deserialize)]
+pub(super) struct StripeInvoice {
+    id: String,
+    subscription: Option<String>, // moved to `parent` in recent API versions
+    parent: Option<StripeInvoiceParent>,
+    amount_paid: i64,
+    currency: String,
+}
+
+#[derive(Deserialize)]
+struct StripeInvoiceParent {
+    subscription_details: Option<StripeInvoiceSubscription>,
+}
+
+#[derive(Deserialize)]
+struct StripeInvoiceSubscription {
+    subscription: Option<String>,
+}
+
+/// Also the `data.object` of `refund.*` webhook events
+#[derive(Deserialize)]
+pub(super) struct StripeRefund {
+    id: String,
+    amount: i64,
+    status: String,
+    payment_intent: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+/// The `data.object` of `charge.dispute.*` webhook events
+#[derive(Deserialize)]
+pub(super) struct StripeDispute {
+    status: String,
+    payment_intent: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StripeSubscriptionItem {
+    id: String,
+    current_period_start: Option<i64>,
+    current_period_end: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct StripeList<T> {
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct StripeCoupon {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct StripeErrorResponse {
+    error: StripeError,
+}
+
+#[derive(Deserialize)]
+struct StripeError {
+    message: Option<String>,
+}
+
+fn provider_err() -> LocalErr {
+    LocalErr::new(LocalErrKind::PaymentProviderError, StatusCode::BAD_GATEWAY)
+}
+
+fn timestamp(seconds: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(seconds, 0).unwrap_or_default()
+}
+
+impl From<StripeCheckoutSession> for CheckoutSession {
+    fn from(session: StripeCheckoutSession) -> Self {
+        let status = match (session.status.as_deref(), session.payment_status.as_str()) {
+            (_, "paid" | "no_payment_required") => CheckoutStatus::Paid,
+            (Some("expired"), _) => CheckoutStatus::Expired,
+            _ => CheckoutStatus::Open,
+        };
+
+        Self {
+            id: session.id,
+            url: session.url.unwrap_or_default(),
+            status,
+            payment_id: session.payment_intent,
+        }
+    }
+}
+
+impl From<StripeSubscription> for ProviderSubscription {
+    fn from(subscription: StripeSubscription) -> Self {
+        let status = match subscription.status.as_str() {
+            "trialing" => Some(SubscriptionStatus::Trialing),
+            "active" => Some(SubscriptionStatus::Active),
+            "past_due" | "unpaid" => Some(SubscriptionStatus::PastDue),
+            "canceled" => Some(SubscriptionStatus::Canceled),
+            "incomplete_expired" => Some(SubscriptionStatus::Expired),
+            _ => None, // incomplete | paused
+        };
+
+        let item = subscription.items.data.first();
+        let period_start = subscription.current_period_start.or(item.and_then(|i| i.current_period_start));
+        let period_end = subscription.current_period_end.or(item.and_then(|i| i.current_period_end));
+        let metadata_id = |key: &str| subscription.metadata.get(key).and_then(|id| id.parse().ok());
+
+        Self {
+            user_id: metadata_id("user_id"),
+            plan_id: metadata_id("plan_id"),
+            id: subscription.id,
+            status,
+            current_period_start: period_start.map(timestamp).unwrap_or_else(Utc::now),
+            current_period_end: period_end.map(timestamp).unwrap_or_else(Utc::now),
+            trial_end: subscription.trial_end.map(timestamp),
+            cancel_at_period_end: subscription.cancel_at_period_end,
+            canceled_at: subscription.canceled_at.map(timestamp),
+        }
+    }
+}
+
+impl From<StripeInvoice> for ProviderCharge {
+    fn from(invoice: StripeInvoice) -> Self {
+        let parent_subscription = invoice.parent
+            .and_then(|p| p.subscription_details)
+            .and_then(|d| d.subscription);
+
+        Self {
+            id: invoice.id,
+            subscription_id: invoice.subscription.or(parent_subscription),
+            amount: invoice.amount_paid,
+            currency: invoice.currency,
+        }
+    }
+}
+
+impl From<StripeRefund> for ProviderRefund {
+    fn from(refund: StripeRefund) -> Self {
+        let status = match refund.status.as_str() {
+            "succeeded" => RefundStatus::Succeeded,
+            "failed" => RefundStatus::Failed,
+            "canceled" => RefundStatus::Canceled,
+            _ => RefundStatus::Pending, // pending | requires_action
+        };
+
+        Self {
+            refund_id: refund.metadata.get("refund_id").and_then(|id| id.parse().ok()),
+            id: refund.id,
+            payment_id: refund.payment_intent,
+            charge_id: refund.metadata.get("invoice_id").cloned(),
+            amount: refund.amount,
+            status,
+        }
+    }
+}
+
+impl From<StripeDispute> for ProviderDispute {
+    fn from(dispute: StripeDispute) -> Self {
+        let status = match dispute.status.as_str() {
+            "won" => DisputeStatus::Won,
+            "lost" => DisputeStatus::Lost,
+            _ => DisputeStatus::Open, // warning_* | needs_response | under_review
+        };
+
+        Self {
+            payment_id: dispute.payment_intent,
+            status,
+        }
+    }
+}
+
+impl StripePaymentProvider {
+    pub fn new(api_url: &str, secret_key: Option<&str>) -> anyhow::Result<Self> {
+        let secret_key = secret_key.ok_or_else(|| anyhow::anyhow!("`STRIPE_SECRET_KEY` is required by the stripe payment provider"))?;
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()?;
+
+        Ok(Self {
+            client,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            secret_key: secret_key.to_string(),
+        })
+    }
+
+    async fn send<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> LocalResult<T> {
+        let response = request.send().await.map_err_print(|_| provider_err())?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let message = response.json::<StripeErrorResponse>()
+                .await
+                .ok()
+                .and_then(|e| e.error.message)
+                .unwrap_or_else(|| status.to_string());
+            eprintln!("stripe request failed: {}", message);
+
+            // refused, unlike an outage (5xx) or a request it may still be handling (409 for a retried idempotency key)
+            let refused = status.is_client_error() && !matches!(status, reqwest::StatusCode::CONFLICT | reqwest::StatusCode::TOO_MANY_REQUESTS);
+            return Err(match refused {
+                true => LocalErr::new(LocalErrKind::PaymentRefused, StatusCode::BAD_GATEWAY),
+                false => provider_err(),
+            })
+        }
+
+        response.json::<T>()
+            .await
+            .map_err_print(|_| provider_err())
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, form: &[(&str, &str)]) -> LocalResult<T> {
+        Self::send(self.client
+            .post(format!("{}{}", self.api_url, path))
+            .bearer_auth(&self.secret_key)
+            .form(form)
+        ).await
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for StripePaymentProvider {
+    fn name(&self) -> &'static str {
+        "stripe"
+    }
+
+    async fn create_checkout_session(&self, checkout: Checkout<'_>) -> LocalResult<CheckoutSession> {
+        let order_id = checkout.order_id.to_string();
+        let amount = checkout.amount.to_string();
+        let form = [
+            ("mode", "payment"),
+            ("client_reference_id", &order_id),
+            ("metadata[order_id]", &order_id),
+            ("customer_email", checkout.customer_email),
+            ("success_url", &checkout.success_url),
+            ("cancel_url", &checkout.cancel_url),
+            ("line_items[0][quantity]", "1"),
+            ("line_items[0][price_data][currency]", checkout.currency),
+            ("line_items[0][price_data][unit_amount]", &amount),
+            ("line_items[0][price_data][product_data][name]", checkout.description),
+        ];
+
+        Self::send::<StripeCheckoutSession>(self.client
+            .post(format!("{}/v1/checkout/sessions", self.api_url))
+            .bearer_auth(&self.secret_key)
+            .header("Idempotency-Key", &order_id) // a retried request doesn't open a second session
+            .form(&form)
+        ).await.map(CheckoutSession::from)
+    }
+
+    async fn create_subscription_checkout(&self, checkout: SubscriptionCheckout<'_>) -> LocalResult<CheckoutSession> {
+        let price_id = checkout.price_id
+            .ok_or(provider_err().with_msg("the plan has no stripe price"))?;
+        let user_id = checkout.user_id.to_string();
+        let plan_id = checkout.plan_id.to_string();
+        let trial_days = checkout.trial_days.to_string();
+
+        // coupons of this service are mirrored by a single-use Stripe coupon for the first invoice
+        let coupon_id = match checkout.discount {
+            0 => None,
+            discount => {
+                let amount_off = discount.to_string();
+                let coupon = self.post::<StripeCoupon>("/v1/coupons", &[
+                    ("amount_off", &amount_off),
+                    ("currency", checkout.currency),
+                    ("duration", "once"),
+                    ("max_redemptions", "1"),
+                ]).await?;
+                Some(coupon.id)
+            },
+        };
+
+        let mut form = vec![
+            ("mode", "subscription"),
+            ("client_reference_id", &user_id),
+            ("customer_email", checkout.customer_email),
+            ("success_url", &checkout.success_url),
+            ("cancel_url", &checkout.cancel_url),
+            ("line_items[0][quantity]", "1"),
+            ("line_items[0][price]", price_id),
+            ("subscription_data[metadata][user_id]", &user_id),
+            ("subscription_data[metadata][plan_id]", &plan_id),
+        ];
+        if checkout.trial_days > 0 {
+            form.push(("subscription_data[trial_period_days]", &trial_days));
+        }
+        if let Some(coupon_id) = &coupon_id {
+            form.push(("discounts[0][coupon]", coupon_id));
+        }
+
+        self.post::<StripeCheckoutSession>("/v1/checkout/sessions", &form)
+            .await
+            .map(CheckoutSession::from)
+    }
+
+    async fn expire_checkout_session(&self, session_id: &str) -> LocalResult<()> {
+        let path = format!("/v1/checkout/sessions/{}/expire", session_id);
+        if self.post::<StripeCheckoutSession>(&path, &[]).await.is_ok() {
+            return Ok(())
+        }
+
+        // only open sessions can be expired, one that expired on its own is fine
+        let session = Self::send::<StripeCheckoutSession>(self.client
+            .get(format!("{}/v1/checkout/sessions/{}", self.api_url, session_id))
+            .bearer_auth(&self.secret_key)
+        ).await?;
+        match session.status.as_deref() {
+            Some("expired") => Ok(()),
+            _ => Err(provider_err().with_msg("the session was already paid")),
+        }
+    }
+
+    async fn update_subscription(&self, subscription_id: &str, update: SubscriptionUpdate<'_>) -> LocalResult<UpdatedSubscription> {
+        let path = format!("/v1/subscriptions/{}", subscription_id);
+
+        let (subscription, invoiced_amount) = match update {
+            SubscriptionUpdate::CancelAtPeriodEnd(cancel) => {
+                let cancel = if cancel { "true" } else { "false" };
+                (self.post::<StripeSubscription>(&path, &[("cancel_at_period_end", cancel)]).await?, 0)
+            },
+            SubscriptionUpdate::CancelNow => {
+                let canceled = Self::send::<StripeUpdatedSubscription>(self.client
+                    .delete(format!("{}{}", self.api_url, path))
+                    .bearer_auth(&self.secret_key)
+                    .query(&[("expand[]", "latest_invoice")])
+                ).await?;
+
+                // a trial paid nothing
+                if let Some(invoice) = canceled.latest_invoice.as_ref().filter(|i| i.amount_paid > 0) {
+                    let payment_intent = invoice.payment_intent.as_deref()
+                        .ok_or(provider_err().with_msg("the paid invoice has no payment intent"))?;
+                    let amount = invoice.amount_paid.to_string();
+                    Self::send::<StripeRefund>(self.client
+                        .post(format!("{}/v1/refunds", self.api_url))
+                        .bearer_auth(&self.secret_key)
+                        .header("Idempotency-Key", format!("cancel-{}", invoice.id))
+                        // the refund is credited on the invoice of the charge (see `InvoiceRepository::issue_charge_credit_note`)
+                        .form(&[("payment_intent", payment_intent), ("amount", &amount), ("metadata[invoice_id]", &invoice.id)])
+                    ).await?;
+                }
+                (canceled.subscription, 0)
+            },
+            SubscriptionUpdate::ChangePlan { plan_id, price_id, .. } => {
+                let price_id = price_id.ok_or(provider_err().with_msg("the plan has no stripe price"))?;
+                let current = Self::send::<StripeSubscription>(self.client
+                    .get(format!("{}{}", self.api_url, path))
+                    .bearer_auth(&self.secret_key)
+                ).await?;
+                let item_id = current.items.data.first()
+                    .map(|i| i.id.clone())
+                    .ok_or(provider_err().with_msg("the subscription has no item"))?;
+
+                // the prorated difference is invoiced right away, as the latest invoice
+                let plan_id = plan_id.to_string();
+                let updated = self.post::<StripeUpdatedSubscription>(&path, &[
+                    ("items[0][id]", &item_id),
+                    ("items[0][price]", price_id),
+                    ("proration_behavior", "always_invoice"),
+                    ("metadata[plan_id]", &plan_id),
+                    ("expand[]", "latest_invoice"),
+                ]).await?;
+                let invoiced_amount = updated.latest_invoice.as_ref().map_or(0, |i| i.total);
+                (updated.subscription, invoiced_amount)
+            },
+        };
+
+        Ok(UpdatedSubscription { subscription: subscription.into(), invoiced_amount })
+    }
+
+    async fn create_refund(&self, refund: Refund<'_>) -> LocalResult<ProviderRefund> {
+        let refund_id = refund.refund_id.to_string();
+        let amount = refund.amount.to_string();
+        let form = [
+            ("payment_intent", refund.payment_id),
+            ("amount", &amount),
+            ("metadata[refund_id]", &refund_id),
+        ];
+
+        Self::send::<StripeRefund>(self.client
+            .post(format!("{}/v1/refunds", self.api_url))
+            .bearer_auth(&self.secret_key)
+            .header("Idempotency-Key", &refund_id)
+            .form(&form)
+        ).await.map(ProviderRefund::from)
+    }
+}
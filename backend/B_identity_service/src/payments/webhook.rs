@@ -0,0 +1,220 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, models::entity::{payment::PaymentStatus, payment_webhook_event::{self, WebhookEventStatus}}, payments::{CheckoutSession, CheckoutStatus, ProviderCharge, ProviderDispute, ProviderRefund, ProviderSubscription, SubscriptionUpdate, stripe::{StripeCheckoutSession, StripeDispute, StripeInvoice, StripeRefund, StripeSubscription}}, state::AppState};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `t=<unix time>,v1=<hex HMAC-SHA256 of "<t>.<body>">`, with one `v1` per active secret
+pub const SIGNATURE_HEADER: &str = "Stripe-Signature";
+
+#[derive(Deserialize)]
+struct WebhookEvent {
+    id: String,
+    #[serde(rename = "type")]
+    event_type: String,
+}
+
+fn invalid_signature() -> LocalErr {
+    LocalErr::new(LocalErrKind::InvalidWebhookSignature, StatusCode::BAD_REQUEST)
+}
+
+fn mac(secret: &str, timestamp: i64, payload: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+    mac
+}
+
+/// Value of `SIGNATURE_HEADER` for the payload
+pub fn sign(payload: &[u8], secret: &str, timestamp: i64) -> String {
+    let signature = mac(secret, timestamp, payload).finalize().into_bytes();
+    format!("t={},v1={}", timestamp, hex::encode(signature))
+}
+
+/// The timestamp is signed too, so an old captured request can't be replayed past `PAYMENT_WEBHOOK_TOLERANCE_SECONDS`
+pub fn verify_signature(payload: &[u8], header: &str, secret: &str) -> LocalResult<()> {
+    verify_signature_at(payload, header, secret, CONFIG.payment_webhook_tolerance.num_seconds(), Utc::now().timestamp())
+}
+
+fn verify_signature_at(payload: &[u8], header: &str, secret: &str, tolerance: i64, now: i64) -> LocalResult<()> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<i64>().ok(),
+            Some(("v1", s)) => signatures.extend(hex::decode(s).ok()),
+            _ => {} // other schemes
+        }
+    }
+
+    let timestamp = timestamp.ok_or_else(invalid_signature)?;
+    if (now - timestamp).abs() > tolerance {
+        return Err(invalid_signature().with_msg("timestamp outside of the tolerance"))
+    }
+
+    // `verify_slice` compares in constant time
+    match signatures.iter().any(|s| mac(secret, timestamp, payload).verify_slice(s).is_ok()) {
+        true => Ok(()),
+        false => Err(invalid_signature()),
+    }
+}
+
+/// Handles a delivery of the provider. An event id already processed is acknowledged without
+/// being applied again, a failed one is applied again (providers retry until they get a 2xx)
+pub async fn receive(state: &AppState, payload: &[u8], signature: &str) -> LocalResult<()> {
+    verify_signature(payload, signature, &CONFIG.payment_webhook_secret)?;
+
+    let body: serde_json::Value = serde_json::from_slice(payload)
+        .map_err_print(|e| LocalErr::new(LocalErrKind::JsonRejection, StatusCode::BAD_REQUEST).with_msg(e.to_string()))?;
+    let event: WebhookEvent = serde_json::from_value(body.clone())
+        .map_err_print(|e| LocalErr::new(LocalErrKind::JsonRejection, StatusCode::BAD_REQUEST).with_msg(e.to_string()))?;
+
+    let record = state.payment_webhooks_service
+        .record_event(&event.id, state.payment_provider.name(), &event.event_type, body)
+        .await?;
+
+    match record.status {
+        WebhookEventStatus::Processed => Ok(()),
+        _ => process(state, &record).await,
+    }
+}
+
+/// Applies a stored event. A failure is saved on the event, which can be retried by staff
+pub async fn process(state: &AppState, event: &payment_webhook_event::Model) -> LocalResult<()> {
+    match apply(state, event).await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let error = e.msg.clone().unwrap_or_else(|| <&str>::from(e.error).to_string());
+            state.payment_webhooks_service.mark_failed(&event.id, &error).await?;
+            Err(LocalErr::new(LocalErrKind::WebhookProcessingFailed, StatusCode::INTERNAL_SERVER_ERROR).with_msg(error))
+        }
+    }
+}
+
+async fn apply(state: &AppState, event: &payment_webhook_event::Model) -> LocalResult<()> {
+    let object = event.payload.pointer("/data/object").cloned().unwrap_or_default();
+    let invalid_object = |e: serde_json::Error| LocalErr::new(LocalErrKind::JsonRejection, StatusCode::BAD_REQUEST).with_msg(e.to_string());
+
+    match event.event_type.as_str() {
+        "customer.subscription.created" | "customer.subscription.updated" | "customer.subscription.deleted" => {
+            let mut subscription: ProviderSubscription = serde_json::from_value::<StripeSubscription>(object)
+                .map_err_print(invalid_object)?
+                .into();
+            let created = event.payload.get("created")
+                .and_then(|c| c.as_i64())
+                .and_then(|c| DateTime::from_timestamp(c, 0));
+
+            // a second subscription paid while the first one was live (e.g. a checkout that outlived its replacement)
+            // is ended and refunded at the provider, and recorded as canceled
+            if subscription.status.is_some_and(|s| s.is_live()) && state.subscriptions_service.has_other_live_subscription(&event.provider, &subscription).await? {
+                subscription = state.payment_provider.update_subscription(&subscription.id, SubscriptionUpdate::CancelNow).await?.subscription;
+            }
+
+            state.subscriptions_service.apply_subscription(Some(&event.id), &event.provider, &subscription, created).await
+        },
+        // subscription checkouts are followed by `customer.subscription.created`
+        "checkout.session.completed" | "checkout.session.async_payment_succeeded" | "checkout.session.expired" | "checkout.session.async_payment_failed"
+            if object.get("mode").and_then(|m| m.as_str()) != Some("subscription") =>
+        {
+            let mut session: CheckoutSession = serde_json::from_value::<StripeCheckoutSession>(object)
+                .map_err_print(invalid_object)?
+                .into();
+            if event.event_type == "checkout.session.async_payment_failed" {
+                session.status = CheckoutStatus::Failed;
+            }
+
+            state.payments_service.apply_checkout(&event.id, &event.provider, &session).await
+        },
+        // a subscription checkout that didn't go through gives back its coupon
+        "checkout.session.completed" | "checkout.session.async_payment_succeeded" | "checkout.session.expired" | "checkout.session.async_payment_failed" => {
+            let session_id = object.get("id").and_then(|i| i.as_str()).unwrap_or_default();
+            let status = match event.event_type.as_str() {
+                "checkout.session.expired" => PaymentStatus::Expired,
+                "checkout.session.async_payment_failed" => PaymentStatus::Failed,
+                _ => PaymentStatus::Succeeded,
+            };
+            state.subscriptions_service.close_checkout(&event.id, &event.provider, session_id, status).await
+        },
+        "invoice.paid" => {
+            let charge: ProviderCharge = serde_json::from_value::<StripeInvoice>(object)
+                .map_err_print(invalid_object)?
+                .into();
+            match charge.amount {
+                0 => state.payment_webhooks_service.mark_ignored(&event.id).await, // trials
+                _ => state.invoices_service.issue_subscription_invoice(&event.id, &event.provider, &charge).await,
+            }
+        },
+        "refund.created" | "refund.updated" | "refund.failed" => {
+            let refund: ProviderRefund = serde_json::from_value::<StripeRefund>(object)
+                .map_err_print(invalid_object)?
+                .into();
+            state.refunds_service.apply_refund(Some(&event.id), &event.provider, &refund).await
+        },
+        "charge.dispute.created" | "charge.dispute.closed" => {
+            let dispute: ProviderDispute = serde_json::from_value::<StripeDispute>(object)
+                .map_err_print(invalid_object)?
+                .into();
+            state.refunds_service.apply_dispute(&event.id, &event.provider, &dispute).await
+        },
+        // nothing to do, but the event is known to be handled
+        _ => state.payment_webhooks_service.mark_ignored(&event.id).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec_test";
+    const PAYLOAD: &[u8] = br#"{"id":"evt_1","type":"checkout.session.completed"}"#;
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn accepts_signatures_within_the_tolerance() {
+        let header = sign(PAYLOAD, SECRET, NOW - 299);
+        assert!(verify_signature_at(PAYLOAD, &header, SECRET, 300, NOW).is_ok());
+
+        // clocks can be a little ahead too
+        let header = sign(PAYLOAD, SECRET, NOW + 10);
+        assert!(verify_signature_at(PAYLOAD, &header, SECRET, 300, NOW).is_ok());
+    }
+
+    #[test]
+    fn rejects_replayed_signatures() {
+        let header = sign(PAYLOAD, SECRET, NOW - 301);
+        assert!(verify_signature_at(PAYLOAD, &header, SECRET, 300, NOW).is_err());
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let header = sign(PAYLOAD, SECRET, NOW);
+        let tampered = br#"{"id":"evt_2","type":"checkout.session.completed"}"#;
+        assert!(verify_signature_at(tampered, &header, SECRET, 300, NOW).is_err());
+        assert!(verify_signature_at(PAYLOAD, &header, "whsec_other", 300, NOW).is_err());
+
+        // the timestamp is part of the signature
+        let moved = header.replace(&format!("t={}", NOW), &format!("t={}", NOW + 1));
+        assert!(verify_signature_at(PAYLOAD, &moved, SECRET, 300, NOW).is_err());
+    }
+
+    #[test]
+    fn accepts_any_of_several_signatures() {
+        let valid = sign(PAYLOAD, SECRET, NOW);
+        let other = sign(PAYLOAD, "whsec_old", NOW);
+        let v1 = |header: &str| header.split_once(",v1=").unwrap().1.to_string();
+        let header = format!("t={},v1={},v0=ignored,v1={}", NOW, v1(&other), v1(&valid));
+        assert!(verify_signature_at(PAYLOAD, &header, SECRET, 300, NOW).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        for header in ["", "t=abc,v1=00", &format!("v1={}", "00".repeat(32)), &format!("t={}", NOW)] {
+            assert!(verify_signature_at(PAYLOAD, header, SECRET, 300, NOW).is_err());
+        }
+    }
+}
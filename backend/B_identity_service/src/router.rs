@@ -1,13 +1,22 @@
 use axum::{Router, routing::get};
+use tower_http::services::ServeDir;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::{openapi::ApiDocs, routes::endpoints::auth::auth_routes, state::AppState};
+use crate::{config::CONFIG, openapi::ApiDocs, routes::endpoints::{admin::admin_routes, auth::auth_routes, internal::internal_routes, mfa::mfa_routes, payments::payments_routes, subscriptions::subscriptions_routes, users::users_routes, well_known::well_known_routes}, state::AppState};
 
 pub fn api_routes() -> Router<AppState> {
     Router::new()
         .route("/api/ping", get(async || { "pong" }))
         .nest("/api/auth", auth_routes())
+        .nest("/api/auth/mfa", mfa_routes())
+        .nest("/api/users", users_routes())
+        .nest("/api/admin", admin_routes())
+        .nest("/api/internal", internal_routes())
+        .nest("/api/payments", payments_routes())
+        .nest("/api/subscriptions", subscriptions_routes())
+        .nest("/.well-known", well_known_routes())
+        .nest_service("/media", ServeDir::new(&CONFIG.storage_local_dir))
 }
 
 pub fn swagger_routes() -> Router<AppState> {
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{models::entity::{payment_webhook_event::{self, WebhookEventStatus}, user}, routes::dto::common::StringWithLimit};
+
+
+pub const DEFAULT_PAGE_SIZE: u64 = 20;
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+/// Every filter is optional, `email` and `username` match case-insensitive substrings
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListUsersQuery {
+    pub email: Option<String>,
+    pub username: Option<String>,
+    pub created_from: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_to: Option<chrono::DateTime<chrono::Utc>>,
+    pub active: Option<bool>,
+    pub page: Option<u64>, // from 0
+    pub per_page: Option<u64>, // `DEFAULT_PAGE_SIZE` by default, at most `MAX_PAGE_SIZE`
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminUserResponse {
+    pub id: uuid::Uuid,
+    pub email: String,
+    pub username: String,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+    pub is_active: bool,
+    pub email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<user::Model> for AdminUserResponse {
+    fn from(user: user::Model) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            username: user.username,
+            creation_date: user.creation_date.to_utc(),
+            is_active: user.is_active,
+            email_verified_at: user.email_verified_at.map(|d| d.to_utc()),
+        }
+    }
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminUserListResponse {
+    pub users: Vec<AdminUserResponse>,
+    pub page: u64,
+    pub per_page: u64,
+    pub total: u64,
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminUserDetailsResponse {
+    #[serde(flatten)]
+    pub user: AdminUserResponse,
+    pub roles: Vec<String>,
+    pub mfa_enabled: bool,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetUserRolesRequestBody {
+    pub roles: Vec<StringWithLimit<50>>, // replaces the current ones
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct UserRolesResponse {
+    pub roles: Vec<String>,
+}
+
+
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListWebhookEventsQuery {
+    pub status: Option<WebhookEventStatus>,
+    pub page: Option<u64>, // from 0
+    pub per_page: Option<u64>,
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookEventResponse {
+    pub id: String,
+    pub provider: String,
+    pub event_type: String,
+    pub status: WebhookEventStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub payload: serde_json::Value,
+    pub receipt_date: chrono::DateTime<chrono::Utc>,
+    pub processing_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<payment_webhook_event::Model> for WebhookEventResponse {
+    fn from(event: payment_webhook_event::Model) -> Self {
+        Self {
+            id: event.id,
+            provider: event.provider,
+            event_type: event.event_type,
+            status: event.status,
+            attempts: event.attempts,
+            last_error: event.last_error,
+            payload: event.payload,
+            receipt_date: event.receipt_date.to_utc(),
+            processing_date: event.processing_date.map(|d| d.to_utc()),
+        }
+    }
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookEventListResponse {
+    pub events: Vec<WebhookEventResponse>,
+    pub page: u64,
+    pub per_page: u64,
+    pub total: u64,
+}
+
+
+/// Staff refunds aren't limited to the refund window
+#[derive(Deserialize, ToSchema)]
+pub struct CreateRefundRequestBody {
+    pub amount: Option<i64>, // minor units, what is left of the order by default
+    pub reason: Option<StringWithLimit<500>>,
+}
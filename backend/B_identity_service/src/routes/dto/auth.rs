@@ -2,7 +2,7 @@ use sea_orm::ActiveValue::Set;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use crate::{error::LocalErr, models::entity::{common::Password, user}, routes::dto::common::StringWithLimit};
+use crate::{error::LocalErr, models::entity::{audit_log, common::Password, session, user}, routes::dto::{common::StringWithLimit, payments::{InvoiceResponse, OrderResponse, RefundResponse}, subscriptions::SubscriptionResponse, entitlements::EntitlementResponse}};
 
 #[derive(Deserialize, ToSchema)]
 pub struct RegisterRequestBody {
@@ -45,7 +45,177 @@ pub struct UserRequestsResponse {
 }
 
 
+/// Returned by login instead of the tokens when the user has 2FA enabled
+#[derive(Serialize, ToSchema)]
+pub struct MfaPendingResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String, // exchanged at `/api/auth/mfa/verify`
+}
+
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    LoggedIn(UserRequestsResponse),
+    MfaRequired(MfaPendingResponse),
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct UserProfileResponse {
+    pub id: uuid::Uuid,
+    pub username: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub avatar: Option<String>,
+    pub banner: Option<String>,
+    pub birth_date: chrono::NaiveDate,
+    pub sex: user::UserSex,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+    pub deletion_scheduled_at: Option<chrono::DateTime<chrono::Utc>>, // until then `/api/auth/user/restore` cancels it
+}
+
+impl From<user::Model> for UserProfileResponse {
+    fn from(user: user::Model) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            email_verified: user.email_verified_at.is_some(),
+            avatar: user.avatar,
+            banner: user.banner,
+            birth_date: user.birth_date,
+            sex: user.sex,
+            creation_date: user.creation_date.to_utc(),
+            deletion_scheduled_at: user.deletion_scheduled_at.map(|d| d.to_utc()),
+        }
+    }
+}
+
+
+/// Only the fields present are updated
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateUserRequestBody {
+    pub username: Option<StringWithLimit<50>>,
+    pub birth_date: Option<chrono::NaiveDate>,
+    pub sex: Option<user::UserSex>,
+}
+
+
 #[derive(Serialize, ToSchema)]
 pub struct RefreshAccessTokenResponse {
     pub token: String
-}
\ No newline at end of file
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct ForgotPasswordRequestBody {
+    pub email: StringWithLimit<100>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetPasswordRequestBody {
+    pub token: StringWithLimit<64>,
+    pub password: StringWithLimit<100>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct VerifyEmailRequestBody {
+    pub token: StringWithLimit<64>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResendVerificationEmailRequestBody {
+    pub email: StringWithLimit<100>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePasswordRequestBody {
+    pub current_password: StringWithLimit<100>,
+    pub new_password: StringWithLimit<100>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangeEmailRequestBody {
+    pub new_email: StringWithLimit<100>,
+    pub password: StringWithLimit<100>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct ConfirmEmailChangeRequestBody {
+    pub token: StringWithLimit<64>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeleteAccountRequestBody {
+    pub password: StringWithLimit<100>,
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct AccountDeletionResponse {
+    pub deletion_scheduled_at: chrono::DateTime<chrono::Utc>,
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct ExportedSession {
+    pub id: uuid::Uuid,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+    pub expiration_date: chrono::DateTime<chrono::Utc>,
+    pub revocation_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<session::Model> for ExportedSession {
+    fn from(session: session::Model) -> Self {
+        Self {
+            id: session.id,
+            creation_date: session.creation_date.to_utc(),
+            expiration_date: session.expiration_date.to_utc(),
+            revocation_date: session.revocation_date.map(|d| d.to_utc()),
+        }
+    }
+}
+
+
+/// Staff actions on the account, without who took them
+#[derive(Serialize, ToSchema)]
+pub struct ExportedAuditEvent {
+    pub action: String,
+    pub details: serde_json::Value,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<audit_log::Model> for ExportedAuditEvent {
+    fn from(event: audit_log::Model) -> Self {
+        Self {
+            action: event.action,
+            details: event.details,
+            creation_date: event.creation_date.to_utc(),
+        }
+    }
+}
+
+
+/// Everything stored about the user
+#[derive(Serialize, ToSchema)]
+pub struct UserExportResponse {
+    pub generation_date: chrono::DateTime<chrono::Utc>,
+    pub profile: UserProfileResponse,
+    pub roles: Vec<String>,
+    pub mfa_enabled: bool,
+    pub sessions: Vec<ExportedSession>,
+    pub audit_events: Vec<ExportedAuditEvent>,
+    pub orders: Vec<OrderResponse>,
+    pub refunds: Vec<RefundResponse>,
+    pub invoices: Vec<InvoiceResponse>,
+    pub subscriptions: Vec<SubscriptionResponse>,
+    pub entitlements: Vec<EntitlementResponse>,
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{models::entity::{coupon::{self, DiscountType}, coupon_target::{self, CouponTargetType}}, routes::dto::common::StringWithLimit};
+
+
+/// Without `courses` and `plans` the coupon applies to every course and plan
+#[derive(Deserialize, ToSchema)]
+pub struct CreateCouponRequestBody {
+    pub code: StringWithLimit<50>, // letters, digits, `-` and `_`, stored uppercase
+    pub discount_type: DiscountType,
+    pub discount_value: i64, // percent (1 to 100), or minor units
+    pub currency: Option<StringWithLimit<3>>, // required by fixed discounts
+    pub expiration_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub max_redemptions: Option<i32>,
+    pub max_redemptions_per_user: Option<i32>, // 1 by default
+    #[serde(default)]
+    pub courses: Vec<uuid::Uuid>,
+    #[serde(default)]
+    pub plans: Vec<StringWithLimit<50>>, // codes
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct CouponResponse {
+    pub id: uuid::Uuid,
+    pub code: String,
+    pub discount_type: DiscountType,
+    pub discount_value: i64,
+    pub currency: Option<String>,
+    pub expiration_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub max_redemptions: Option<i32>,
+    pub max_redemptions_per_user: i32,
+    pub is_active: bool,
+    pub courses: Vec<uuid::Uuid>,
+    pub plans: Vec<uuid::Uuid>, // ids
+    pub redemptions: i64, // checkouts that didn't go through don't count
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+}
+
+impl CouponResponse {
+    pub fn new(coupon: coupon::Model, targets: &[coupon_target::Model], redemptions: i64) -> Self {
+        let targets_of = |target_type| targets.iter()
+            .filter(|t| t.target_type == target_type)
+            .map(|t| t.target_id)
+            .collect();
+
+        Self {
+            id: coupon.id,
+            code: coupon.code,
+            discount_type: coupon.discount_type,
+            discount_value: coupon.discount_value,
+            currency: coupon.currency,
+            expiration_date: coupon.expiration_date.map(|d| d.to_utc()),
+            max_redemptions: coupon.max_redemptions,
+            max_redemptions_per_user: coupon.max_redemptions_per_user,
+            is_active: coupon.is_active,
+            courses: targets_of(CouponTargetType::Course),
+            plans: targets_of(CouponTargetType::Plan),
+            redemptions,
+            creation_date: coupon.creation_date.to_utc(),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::entity::entitlement::{self, EntitlementSource};
+
+
+/// At most this many pairs per bulk check
+pub const MAX_ACCESS_CHECKS: usize = 500;
+
+#[derive(Serialize, ToSchema)]
+pub struct EntitlementResponse {
+    pub course_id: uuid::Uuid,
+    pub source: EntitlementSource,
+    pub order_id: Option<uuid::Uuid>,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<entitlement::Model> for EntitlementResponse {
+    fn from(entitlement: entitlement::Model) -> Self {
+        Self {
+            course_id: entitlement.course_id,
+            source: entitlement.source,
+            order_id: entitlement.order_id,
+            creation_date: entitlement.creation_date.to_utc(),
+        }
+    }
+}
+
+
+/// A course can appear more than once, once per source
+#[derive(Serialize, ToSchema)]
+pub struct UserEntitlementsResponse {
+    pub user_id: uuid::Uuid,
+    pub entitlements: Vec<EntitlementResponse>,
+}
+
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy)]
+pub struct AccessCheck {
+    pub user_id: uuid::Uuid,
+    pub course_id: uuid::Uuid,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CheckAccessRequestBody {
+    pub checks: Vec<AccessCheck>, // at most `MAX_ACCESS_CHECKS`
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AccessCheckResult {
+    #[serde(flatten)]
+    pub check: AccessCheck,
+    pub has_access: bool,
+}
+
+/// One result per check, in the same order
+#[derive(Serialize, ToSchema)]
+pub struct CheckAccessResponse {
+    pub results: Vec<AccessCheckResult>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct GrantEntitlementRequestBody {
+    pub course_id: uuid::Uuid,
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{models::entity::subscription::SubscriptionStatus, routes::dto::common::StringWithLimit};
+
+
+/// RFC 7662 introspection request. Only access tokens can be introspected, so `token_type_hint` is ignored
+#[derive(Deserialize, ToSchema)]
+pub struct IntrospectRequestBody {
+    pub token: StringWithLimit<4096>,
+}
+
+
+/// RFC 7662 introspection response, an inactive token only has `active: false`
+#[derive(Serialize, ToSchema, Default)]
+pub struct IntrospectResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>, // backend that asked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<uuid::Uuid>, // user id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>, // space separated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<uuid::Uuid>, // session id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+}
+
+impl IntrospectResponse {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+}
+
+
+/// `active` is what access decisions should use: a past due subscription keeps access while the provider retries
+#[derive(Serialize, ToSchema)]
+pub struct UserSubscriptionResponse {
+    pub user_id: uuid::Uuid,
+    pub active: bool,
+    pub status: Option<SubscriptionStatus>, // `None` if the user never subscribed
+    pub plan: Option<String>, // code
+    pub current_period_end: Option<chrono::DateTime<chrono::Utc>>,
+    pub cancel_at_period_end: bool,
+}
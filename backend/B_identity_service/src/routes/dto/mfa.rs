@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::routes::dto::common::StringWithLimit;
+
+
+#[derive(Serialize, ToSchema)]
+pub struct MfaEnrollmentResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub qr_code: String, // base64 png of `otpauth_uri`
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct MfaCodeRequestBody {
+    pub code: StringWithLimit<20>,
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct MfaRecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct DisableMfaRequestBody {
+    pub password: StringWithLimit<100>,
+    pub code: StringWithLimit<20>, // TOTP or recovery code
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct MfaVerifyRequestBody {
+    pub mfa_token: StringWithLimit<1000>,
+    pub code: StringWithLimit<20>, // TOTP or recovery code
+}
\ No newline at end of file
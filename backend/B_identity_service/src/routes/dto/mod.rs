@@ -1,2 +1,10 @@
 pub mod auth;
-pub mod common;
\ No newline at end of file
+pub mod common;
+pub mod users;
+pub mod mfa;
+pub mod internal;
+pub mod admin;
+pub mod payments;
+pub mod subscriptions;
+pub mod entitlements;
+pub mod coupons;
\ No newline at end of file
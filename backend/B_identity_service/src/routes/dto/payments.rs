@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{models::entity::{invoice::{self, InvoiceLine}, order::{self, OrderStatus}, refund::{self, RefundStatus}}, routes::dto::common::StringWithLimit};
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct CheckoutRequestBody {
+    pub course_id: uuid::Uuid,
+    pub coupon: Option<StringWithLimit<50>>, // code
+}
+
+
+/// The buyer is sent to `checkout_url`, then back to the frontend at `/orders/<order_id>`.
+/// When a coupon covers the whole price the order is already paid and `checkout_url` is that frontend page
+#[derive(Serialize, ToSchema)]
+pub struct CheckoutResponse {
+    pub order_id: uuid::Uuid,
+    pub checkout_url: String,
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct OrderResponse {
+    pub id: uuid::Uuid,
+    pub course_id: uuid::Uuid,
+    pub amount: i64, // minor units, what is charged
+    pub discount_amount: i64,
+    pub refunded_amount: i64,
+    pub currency: String,
+    pub status: OrderStatus,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+    pub update_date: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<order::Model> for OrderResponse {
+    fn from(order: order::Model) -> Self {
+        Self {
+            id: order.id,
+            course_id: order.course_id,
+            amount: order.amount,
+            discount_amount: order.discount_amount,
+            refunded_amount: order.refunded_amount,
+            currency: order.currency,
+            status: order.status,
+            creation_date: order.creation_date.to_utc(),
+            update_date: order.update_date.to_utc(),
+        }
+    }
+}
+
+
+
+/// Refunds what is left of the order, within `REFUND_WINDOW_DAYS` of the payment
+#[derive(Deserialize, ToSchema)]
+pub struct RefundRequestBody {
+    pub reason: Option<StringWithLimit<500>>,
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct RefundResponse {
+    pub id: uuid::Uuid,
+    pub order_id: uuid::Uuid,
+    pub amount: i64, // minor units
+    pub currency: String,
+    pub reason: Option<String>,
+    pub status: RefundStatus,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+    pub update_date: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<refund::Model> for RefundResponse {
+    fn from(refund: refund::Model) -> Self {
+        Self {
+            id: refund.id,
+            order_id: refund.order_id,
+            amount: refund.amount,
+            currency: refund.currency,
+            reason: refund.reason,
+            status: refund.status,
+            creation_date: refund.creation_date.to_utc(),
+            update_date: refund.update_date.to_utc(),
+        }
+    }
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct InvoiceResponse {
+    pub id: uuid::Uuid,
+    pub number: String,
+    pub order_id: Option<uuid::Uuid>,
+    pub subscription_id: Option<uuid::Uuid>,
+    pub seller_name: String,
+    pub seller_address: String,
+    pub seller_vat_id: Option<String>,
+    pub buyer_name: String,
+    pub buyer_email: String,
+    pub lines: Vec<InvoiceLine>,
+    pub currency: String,
+    pub tax_rate: i32, // basis points
+    pub subtotal_amount: i64, // minor units, without taxes
+    pub tax_amount: i64,
+    pub total_amount: i64,
+    pub issue_date: chrono::DateTime<chrono::Utc>,
+    pub credited_invoice_id: Option<uuid::Uuid>, // credit notes
+    pub refund_id: Option<uuid::Uuid>,
+}
+
+impl From<invoice::Model> for InvoiceResponse {
+    fn from(invoice: invoice::Model) -> Self {
+        Self {
+            id: invoice.id,
+            number: invoice.invoice_number(),
+            lines: invoice.lines(),
+            order_id: invoice.order_id,
+            subscription_id: invoice.subscription_id,
+            seller_name: invoice.seller_name,
+            seller_address: invoice.seller_address,
+            seller_vat_id: invoice.seller_vat_id,
+            buyer_name: invoice.buyer_name,
+            buyer_email: invoice.buyer_email,
+            currency: invoice.currency,
+            tax_rate: invoice.tax_rate,
+            subtotal_amount: invoice.subtotal_amount,
+            tax_amount: invoice.tax_amount,
+            total_amount: invoice.total_amount,
+            issue_date: invoice.issue_date.to_utc(),
+            credited_invoice_id: invoice.credited_invoice_id,
+            refund_id: invoice.refund_id,
+        }
+    }
+}
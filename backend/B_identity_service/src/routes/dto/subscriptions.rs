@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{models::entity::{plan::{self, BillingInterval}, subscription::{self, SubscriptionStatus}}, routes::dto::common::StringWithLimit};
+
+
+#[derive(Serialize, ToSchema)]
+pub struct PlanResponse {
+    pub code: String,
+    pub name: String,
+    pub amount: i64, // minor units, per interval
+    pub currency: String,
+    pub billing_interval: BillingInterval,
+    pub trial_days: i32,
+}
+
+impl From<plan::Model> for PlanResponse {
+    fn from(plan: plan::Model) -> Self {
+        Self {
+            code: plan.code,
+            name: plan.name,
+            amount: plan.amount,
+            currency: plan.currency,
+            billing_interval: plan.billing_interval,
+            trial_days: plan.trial_days,
+        }
+    }
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct SubscribeRequestBody {
+    pub plan: StringWithLimit<50>, // code
+    pub coupon: Option<StringWithLimit<50>>, // code, taken off the first payment instead of the trial
+}
+
+
+/// The subscription shows up once the provider reports the checkout as paid
+#[derive(Serialize, ToSchema)]
+pub struct SubscriptionCheckoutResponse {
+    pub checkout_url: String,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePlanRequestBody {
+    pub plan: StringWithLimit<50>, // code
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct SubscriptionResponse {
+    pub id: uuid::Uuid,
+    pub plan: PlanResponse,
+    pub status: SubscriptionStatus,
+    pub current_period_start: chrono::DateTime<chrono::Utc>,
+    pub current_period_end: chrono::DateTime<chrono::Utc>,
+    pub trial_end: Option<chrono::DateTime<chrono::Utc>>,
+    pub cancel_at_period_end: bool,
+    pub canceled_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<(subscription::Model, plan::Model)> for SubscriptionResponse {
+    fn from((subscription, plan): (subscription::Model, plan::Model)) -> Self {
+        Self {
+            id: subscription.id,
+            plan: plan.into(),
+            status: subscription.status,
+            current_period_start: subscription.current_period_start.to_utc(),
+            current_period_end: subscription.current_period_end.to_utc(),
+            trial_end: subscription.trial_end.map(|d| d.to_utc()),
+            cancel_at_period_end: subscription.cancel_at_period_end,
+            canceled_at: subscription.canceled_at.map(|d| d.to_utc()),
+            creation_date: subscription.creation_date.to_utc(),
+        }
+    }
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct ChangePlanResponse {
+    #[serde(flatten)]
+    pub subscription: SubscriptionResponse,
+    pub proration_amount: i64, // charged now in minor units, negative if credited
+}
@@ -0,0 +1,24 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::models::entity::user;
+
+/// What anyone can see of a user (e.g. instructor pages)
+#[derive(Serialize, ToSchema)]
+pub struct PublicUserProfileResponse {
+    pub username: String,
+    pub avatar: Option<String>,
+    pub banner: Option<String>,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<user::Model> for PublicUserProfileResponse {
+    fn from(user: user::Model) -> Self {
+        Self {
+            username: user.username,
+            avatar: user.avatar,
+            banner: user.banner,
+            creation_date: user.creation_date.to_utc(),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,410 @@
+use axum::{Router, extract::State, http::StatusCode, routing::{delete, get, post, put}};
+use sea_orm::{ActiveValue::Set, ColumnTrait, Condition, sea_query::{Expr, Func, LikeExpr}};
+use serde_json::json;
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult}, extract::{Json, Path, Query, RequirePermission, permissions}, models::{entity::{audit_log::AuditAction, common::Password, coupon::{self, DiscountType}, coupon_target::{self, CouponTargetType}, payment_webhook_event::WebhookEventStatus, user}, repository::audit::AuditEntry}, payments::webhook, routes::{dto::admin::{AdminUserDetailsResponse, AdminUserListResponse, AdminUserResponse, CreateRefundRequestBody, DEFAULT_PAGE_SIZE, ListUsersQuery, ListWebhookEventsQuery, MAX_PAGE_SIZE, SetUserRolesRequestBody, UserRolesResponse, WebhookEventListResponse, WebhookEventResponse}, dto::coupons::{CouponResponse, CreateCouponRequestBody}, dto::payments::RefundResponse, dto::entitlements::{EntitlementResponse, GrantEntitlementRequestBody, UserEntitlementsResponse}, endpoints::{auth::send_password_reset_email, payments}}, state::AppState, utils::token::generate_token};
+
+pub fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route("/users", get(list_users))
+        .route("/users/{id}", get(get_user))
+        .route("/users/{id}/deactivate", post(deactivate_user))
+        .route("/users/{id}/reactivate", post(reactivate_user))
+        .route("/users/{id}/logout", post(force_logout))
+        .route("/users/{id}/password-reset", post(force_password_reset))
+        .route("/users/{id}/roles", put(set_user_roles))
+        .route("/users/{id}/entitlements", get(list_user_entitlements).post(grant_entitlement))
+        .route("/users/{id}/entitlements/{course_id}", delete(revoke_entitlement))
+        .route("/coupons", get(list_coupons).post(create_coupon))
+        .route("/coupons/{id}/deactivate", post(deactivate_coupon))
+        .route("/orders/{id}/refunds", get(list_order_refunds).post(refund_order))
+        .route("/payments/webhooks", get(list_webhook_events))
+        .route("/payments/webhooks/{id}/retry", post(retry_webhook_event))
+}
+
+
+/// Case-insensitive `LIKE %value%`
+fn contains_ci(column: user::Column, value: &str) -> sea_orm::sea_query::SimpleExpr {
+    let escaped = value.to_lowercase()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    Expr::expr(Func::lower(Expr::col(column)))
+        .like(LikeExpr::new(format!("%{}%", escaped)).escape('\\'))
+}
+
+async fn find_user(state: &AppState, user_id: uuid::Uuid) -> LocalResult<user::Model> {
+    state.users_service.get_any_user(user_id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))
+}
+
+/// Staff can't lock themselves out with the account they are using
+fn ensure_not_self(actor_id: uuid::Uuid, user_id: uuid::Uuid) -> LocalResult<()> {
+    match actor_id == user_id {
+        true => Err(LocalErr::new(LocalErrKind::CannotManageSelf, StatusCode::BAD_REQUEST)),
+        false => Ok(()),
+    }
+}
+
+
+#[utoipa::path(get, path = "/api/admin/users", params(ListUsersQuery), responses((status = 200, body = AdminUserListResponse)))]
+pub async fn list_users(
+    State(AppState { users_service, .. }): State<AppState>,
+    _: RequirePermission<permissions::ReadUsers>,
+    Query(query): Query<ListUsersQuery>,
+) -> LocalResult<Json<AdminUserListResponse>> {
+    let mut filters = Condition::all();
+    if let Some(email) = query.email.as_deref().filter(|e| !e.is_empty()) {
+        filters = filters.add(contains_ci(user::Column::Email, email));
+    }
+    if let Some(username) = query.username.as_deref().filter(|u| !u.is_empty()) {
+        filters = filters.add(contains_ci(user::Column::Username, username));
+    }
+    if let Some(from) = query.created_from {
+        filters = filters.add(user::Column::CreationDate.gte(from));
+    }
+    if let Some(to) = query.created_to {
+        filters = filters.add(user::Column::CreationDate.lt(to));
+    }
+    if let Some(active) = query.active {
+        filters = filters.add(user::Column::IsActive.eq(active));
+    }
+
+    let page = query.page.unwrap_or(0);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let (users, total) = users_service.list_users(filters, page, per_page).await?;
+
+    Ok(Json(AdminUserListResponse {
+        users: users.into_iter().map(AdminUserResponse::from).collect(),
+        page,
+        per_page,
+        total,
+    }))
+}
+
+
+#[utoipa::path(get, path = "/api/admin/users/{id}", responses((status = 200, body = AdminUserDetailsResponse)))]
+pub async fn get_user(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ReadUsers>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> LocalResult<Json<AdminUserDetailsResponse>> {
+    let user = find_user(&state, user_id).await?;
+    let roles = state.roles_service.get_user_roles(user_id).await?;
+    let mfa_enabled = state.mfa_service.get_enabled_mfa(user_id).await?.is_some();
+
+    state.audit_service.record_read(AuditEntry::new(actor_id, AuditAction::UserViewed, user_id)).await?;
+
+    Ok(Json(AdminUserDetailsResponse { user: user.into(), roles, mfa_enabled }))
+}
+
+
+/// The user can't log in and every session is revoked
+#[utoipa::path(post, path = "/api/admin/users/{id}/deactivate", responses((status = 204)))]
+pub async fn deactivate_user(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ManageUsers>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> LocalResult<StatusCode> {
+    ensure_not_self(actor_id, user_id)?;
+    find_user(&state, user_id).await?;
+
+    // `is_current_version` only accepts active users, so issued tokens stop working too
+    state.users_service.update_user_audited(user::ActiveModel {
+        id: Set(user_id),
+        is_active: Set(false),
+        ..Default::default()
+    }, true, AuditEntry::new(actor_id, AuditAction::UserDeactivated, user_id)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+#[utoipa::path(post, path = "/api/admin/users/{id}/reactivate", responses((status = 204)))]
+pub async fn reactivate_user(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ManageUsers>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> LocalResult<StatusCode> {
+    find_user(&state, user_id).await?;
+
+    state.users_service.update_user_audited(user::ActiveModel {
+        id: Set(user_id),
+        is_active: Set(true),
+        ..Default::default()
+    }, false, AuditEntry::new(actor_id, AuditAction::UserReactivated, user_id)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// Revokes every session and access token of the user
+#[utoipa::path(post, path = "/api/admin/users/{id}/logout", responses((status = 204)))]
+pub async fn force_logout(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ManageUsers>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> LocalResult<StatusCode> {
+    find_user(&state, user_id).await?;
+
+    state.users_service.update_user_audited(user::ActiveModel {
+        id: Set(user_id),
+        version: Set(uuid::Uuid::new_v4()),
+        ..Default::default()
+    }, true, AuditEntry::new(actor_id, AuditAction::UserLoggedOut, user_id)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// Replaces the password with an unknown one, logs the user out and mails them a reset link
+#[utoipa::path(post, path = "/api/admin/users/{id}/password-reset", responses((status = 202)))]
+pub async fn force_password_reset(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ManageUsers>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> LocalResult<StatusCode> {
+    ensure_not_self(actor_id, user_id)?;
+    find_user(&state, user_id).await?;
+
+    // setting the hash bumps `version`
+    let (unknown_password, _) = generate_token();
+    let user = state.users_service.update_user_audited(user::ActiveModel {
+        id: Set(user_id),
+        password_hash: Set(Password(unknown_password).hash_password()?),
+        ..Default::default()
+    }, true, AuditEntry::new(actor_id, AuditAction::PasswordResetForced, user_id)).await?;
+
+    send_password_reset_email(&state.user_tokens_service, &state.mailer, &user).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+
+/// The user is logged out of every device, their tokens carry the old roles
+#[utoipa::path(put, path = "/api/admin/users/{id}/roles", responses((status = 200, body = UserRolesResponse)))]
+pub async fn set_user_roles(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::AssignRoles>,
+    Path(user_id): Path<uuid::Uuid>,
+    Json(body): Json<SetUserRolesRequestBody>,
+) -> LocalResult<Json<UserRolesResponse>> {
+    ensure_not_self(actor_id, user_id)?;
+    find_user(&state, user_id).await?;
+
+    let mut roles: Vec<String> = body.roles.into_iter().map(|r| r.0).collect();
+    roles.sort();
+    roles.dedup();
+
+    let previous = state.roles_service.get_user_roles(user_id).await?;
+    let audit = AuditEntry::new(actor_id, AuditAction::RolesChanged, user_id).with_details(json!({ "from": previous, "to": roles }));
+    state.roles_service.set_user_roles(user_id, &roles, Some(audit)).await?;
+    state.users_service.forget_version(user_id).await;
+
+    Ok(Json(UserRolesResponse { roles }))
+}
+
+
+
+#[utoipa::path(get, path = "/api/admin/users/{id}/entitlements", responses((status = 200, body = UserEntitlementsResponse)))]
+pub async fn list_user_entitlements(
+    State(state): State<AppState>,
+    _: RequirePermission<permissions::ReadUsers>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> LocalResult<Json<UserEntitlementsResponse>> {
+    find_user(&state, user_id).await?;
+    let entitlements = state.entitlements_service.get_user_entitlements(user_id).await?;
+
+    Ok(Json(UserEntitlementsResponse {
+        user_id,
+        entitlements: entitlements.into_iter().map(EntitlementResponse::from).collect(),
+    }))
+}
+
+
+/// Gives access to the course without a purchase
+#[utoipa::path(post, path = "/api/admin/users/{id}/entitlements", responses((status = 204)))]
+pub async fn grant_entitlement(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ManageEntitlements>,
+    Path(user_id): Path<uuid::Uuid>,
+    Json(body): Json<GrantEntitlementRequestBody>,
+) -> LocalResult<StatusCode> {
+    find_user(&state, user_id).await?;
+
+    let audit = AuditEntry::new(actor_id, AuditAction::EntitlementGranted, user_id).with_details(json!({ "course_id": body.course_id }));
+    state.entitlements_service.grant_course(user_id, body.course_id, audit).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// Only revokes the staff grant, a purchase still gives access
+#[utoipa::path(delete, path = "/api/admin/users/{id}/entitlements/{course_id}", responses((status = 204)))]
+pub async fn revoke_entitlement(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ManageEntitlements>,
+    Path((user_id, course_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> LocalResult<StatusCode> {
+    let audit = AuditEntry::new(actor_id, AuditAction::EntitlementRevoked, user_id).with_details(json!({ "course_id": course_id }));
+    if !state.entitlements_service.revoke_grant(user_id, course_id, audit).await? {
+        return Err(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+#[utoipa::path(get, path = "/api/admin/coupons", responses((status = 200, body = Vec<CouponResponse>)))]
+pub async fn list_coupons(
+    State(AppState { coupons_service, .. }): State<AppState>,
+    _: RequirePermission<permissions::ManageCoupons>,
+) -> LocalResult<Json<Vec<CouponResponse>>> {
+    let coupons = coupons_service.get_coupons().await?;
+    Ok(Json(coupons.into_iter().map(|(c, targets, redemptions)| CouponResponse::new(c, &targets, redemptions)).collect()))
+}
+
+
+/// Users enter the code at the course or subscription checkout
+#[utoipa::path(post, path = "/api/admin/coupons", responses((status = 201, body = CouponResponse)))]
+pub async fn create_coupon(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ManageCoupons>,
+    Json(body): Json<CreateCouponRequestBody>,
+) -> LocalResult<(StatusCode, Json<CouponResponse>)> {
+    let invalid = |msg: &str| LocalErr::new(LocalErrKind::InvalidCoupon, StatusCode::BAD_REQUEST).with_msg(msg);
+
+    let code = body.code.0.trim().to_uppercase();
+    if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(invalid("`code` can only contain letters, digits, `-` and `_`"))
+    }
+    let currency = body.currency.map(|c| c.0.to_lowercase());
+    match body.discount_type {
+        DiscountType::Percent if !(1..=100).contains(&body.discount_value) => return Err(invalid("a percentage must be between 1 and 100")),
+        DiscountType::Percent if currency.is_some() => return Err(invalid("a percentage applies to every currency")),
+        DiscountType::Fixed if body.discount_value <= 0 => return Err(invalid("`discount_value` must be positive")),
+        DiscountType::Fixed if !currency.as_ref().is_some_and(|c| c.len() == 3 && c.chars().all(|c| c.is_ascii_alphabetic())) => {
+            return Err(invalid("a fixed discount needs the ISO 4217 `currency` of the prices it applies to"))
+        },
+        _ => {},
+    }
+    if body.max_redemptions.is_some_and(|m| m <= 0) || body.max_redemptions_per_user.is_some_and(|m| m <= 0) {
+        return Err(invalid("redemption limits must be positive"))
+    }
+    if body.expiration_date.is_some_and(|d| d <= chrono::Utc::now()) {
+        return Err(invalid("`expiration_date` is in the past"))
+    }
+
+    if state.coupons_service.get_coupon_by_code(&code).await?.is_some() {
+        return Err(LocalErr::new(LocalErrKind::CouponCodeTaken, StatusCode::CONFLICT))
+    }
+
+    let mut targets: Vec<_> = body.courses.iter().map(|id| (CouponTargetType::Course, *id)).collect();
+    for plan_code in &body.plans {
+        let plan = state.subscriptions_service.get_active_plan(&plan_code.0)
+            .await?
+            .ok_or(LocalErr::new(LocalErrKind::UnknownPlan, StatusCode::BAD_REQUEST).with_msg(plan_code.0.clone()))?;
+        targets.push((CouponTargetType::Plan, plan.id));
+    }
+    targets.sort();
+    targets.dedup();
+
+    let coupon = state.coupons_service.create_coupon(coupon::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        code: Set(code),
+        discount_type: Set(body.discount_type),
+        discount_value: Set(body.discount_value),
+        currency: Set(currency),
+        expiration_date: Set(body.expiration_date.map(Into::into)),
+        max_redemptions: Set(body.max_redemptions),
+        max_redemptions_per_user: Set(body.max_redemptions_per_user.unwrap_or(1)),
+        is_active: Set(true),
+        created_by: Set(Some(actor_id)),
+        creation_date: Set(chrono::Utc::now().into()),
+    }, &targets).await?;
+
+    let targets = targets.into_iter()
+        .map(|(target_type, target_id)| coupon_target::Model { coupon_id: coupon.id, target_type, target_id })
+        .collect::<Vec<_>>();
+    Ok((StatusCode::CREATED, Json(CouponResponse::new(coupon, &targets, 0))))
+}
+
+
+/// The code can't be used anymore, checkouts already opened with it still go through
+#[utoipa::path(post, path = "/api/admin/coupons/{id}/deactivate", responses((status = 204)))]
+pub async fn deactivate_coupon(
+    State(AppState { coupons_service, .. }): State<AppState>,
+    _: RequirePermission<permissions::ManageCoupons>,
+    Path(coupon_id): Path<uuid::Uuid>,
+) -> LocalResult<StatusCode> {
+    match coupons_service.deactivate_coupon(coupon_id).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND)),
+    }
+}
+
+
+#[utoipa::path(get, path = "/api/admin/orders/{id}/refunds", responses((status = 200, body = Vec<RefundResponse>)))]
+pub async fn list_order_refunds(
+    State(AppState { refunds_service, .. }): State<AppState>,
+    _: RequirePermission<permissions::ManagePayments>,
+    Path(order_id): Path<uuid::Uuid>,
+) -> LocalResult<Json<Vec<RefundResponse>>> {
+    let refunds = refunds_service.get_order_refunds(order_id).await?;
+    Ok(Json(refunds.into_iter().map(RefundResponse::from).collect()))
+}
+
+
+/// Refunds part or all of a paid order, a full refund takes the course away
+#[utoipa::path(post, path = "/api/admin/orders/{id}/refunds", responses((status = 200, body = RefundResponse)))]
+pub async fn refund_order(
+    State(state): State<AppState>,
+    RequirePermission { user_id: actor_id, .. }: RequirePermission<permissions::ManagePayments>,
+    Path(order_id): Path<uuid::Uuid>,
+    Json(body): Json<CreateRefundRequestBody>,
+) -> LocalResult<Json<RefundResponse>> {
+    let refund = payments::refund_order(&state, order_id, body.amount, body.reason.map(|r| r.0), actor_id, true).await?;
+    Ok(Json(refund.into()))
+}
+
+
+/// Failed events (`status=failed`) are the ones needing attention
+#[utoipa::path(get, path = "/api/admin/payments/webhooks", params(ListWebhookEventsQuery), responses((status = 200, body = WebhookEventListResponse)))]
+pub async fn list_webhook_events(
+    State(AppState { payment_webhooks_service, .. }): State<AppState>,
+    _: RequirePermission<permissions::ManagePayments>,
+    Query(query): Query<ListWebhookEventsQuery>,
+) -> LocalResult<Json<WebhookEventListResponse>> {
+    let page = query.page.unwrap_or(0);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let (events, total) = payment_webhooks_service.list_events(query.status, page, per_page).await?;
+
+    Ok(Json(WebhookEventListResponse {
+        events: events.into_iter().map(WebhookEventResponse::from).collect(),
+        page,
+        per_page,
+        total,
+    }))
+}
+
+
+/// Applies the stored event again, the outcome is in the returned `status` and `last_error`
+#[utoipa::path(post, path = "/api/admin/payments/webhooks/{id}/retry", responses((status = 200, body = WebhookEventResponse)))]
+pub async fn retry_webhook_event(
+    State(state): State<AppState>,
+    _: RequirePermission<permissions::ManagePayments>,
+    Path(event_id): Path<String>,
+) -> LocalResult<Json<WebhookEventResponse>> {
+    let find_event = async || state.payment_webhooks_service.get_event(&event_id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND));
+
+    let event = find_event().await?;
+    if event.status != WebhookEventStatus::Processed {
+        // a failure is saved on the event
+        let _ = webhook::process(&state, &event).await;
+    }
+
+    Ok(Json(find_event().await?.into()))
+}
\ No newline at end of file
@@ -1,21 +1,188 @@
-use axum::{Router, extract::State, http::StatusCode, routing::{get, post}};
-use axum_extra::extract::CookieJar;
-use sea_orm::{ColumnTrait, Condition};
+use std::sync::Arc;
 
-use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult}, extract::{Json, UserId}, models::entity::user, routes::dto::auth::{LoginRequestBody, RefreshAccessTokenResponse, RegisterRequestBody, UserRequestsResponse}, state::AppState};
+use axum::{Router, extract::{DefaultBodyLimit, State}, http::{HeaderName, StatusCode, header::CONTENT_DISPOSITION}, routing::{get, post, put}};
+use axum_extra::extract::{CookieJar, cookie::Cookie};
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition};
+
+use crate::{config::{CONFIG, EmailVerificationPolicy}, error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, extract::{ClientIp, Json, Multipart, UserId, VerifiedUserId}, mailer::{Mail, Mailer}, models::{entity::{common::Password, role, user, user_token::UserTokenKind}, repository::{entitlement::EntitlementRepository, role::RoleRepository, session::SessionRepository, user_token::UserTokenRepository}}, routes::dto::{auth::{AccountDeletionResponse, ChangeEmailRequestBody, DeleteAccountRequestBody, ExportedAuditEvent, ExportedSession, UserExportResponse, ChangePasswordRequestBody, ConfirmEmailChangeRequestBody, ForgotPasswordRequestBody, LoginRequestBody, LoginResponse, MfaPendingResponse, RefreshAccessTokenResponse, RegisterRequestBody, ResendVerificationEmailRequestBody, ResetPasswordRequestBody, UpdateUserRequestBody, UserProfileResponse, UserRequestsResponse, VerifyEmailRequestBody}, payments::{InvoiceResponse, OrderResponse, RefundResponse}, subscriptions::SubscriptionResponse, entitlements::EntitlementResponse}, state::AppState, throttle::LoginAccount, utils::{image::{self, ImageKind, MAX_IMAGE_BYTES}, jwt::JwtRepository}};
 
 pub fn auth_routes() -> Router<AppState> {
     Router::new()
         .route("/register", post(register))
         .route("/login", post(login))
-        .route("/user", get(get_user_profile))
+        .route("/user", get(get_user_profile).patch(update_user_profile).delete(delete_account))
+        .route("/user/restore", post(restore_account))
+        .route("/user/export", get(export_user_data))
+        .route("/user/avatar", post(upload_avatar).delete(delete_avatar).layer(DefaultBodyLimit::max(MAX_IMAGE_BYTES + 64 * 1024)))
+        .route("/user/banner", post(upload_banner).delete(delete_banner).layer(DefaultBodyLimit::max(MAX_IMAGE_BYTES + 64 * 1024)))
         .route("/refresh", post(refresh_access_token))
+        .route("/logout", post(logout))
+        .route("/logout-all", post(logout_all))
+        .route("/password/forgot", post(forgot_password))
+        .route("/password/reset", post(reset_password))
+        .route("/email/verify", post(verify_email))
+        .route("/email/verify/resend", post(resend_verification_email))
+        .route("/password", put(change_password))
+        .route("/email", post(change_email))
+        .route("/email/confirm", post(confirm_email_change))
+}
+
+
+/// More courses than this and the `courses` claim is left out, the token would get too big
+const MAX_TOKEN_COURSES: usize = 100;
+
+/// Courses for the `courses` claim, only with `JWT_ACCESS_ENTITLEMENTS`. They are read after `entitlements_version`,
+/// so a change in between makes the token outdated rather than the courses
+async fn token_courses(entitlements_service: &EntitlementRepository, user_id: uuid::Uuid, entitlements_version: uuid::Uuid) -> LocalResult<Option<(Vec<uuid::Uuid>, uuid::Uuid)>> {
+    if !CONFIG.jwt_access_entitlements {
+        return Ok(None)
+    }
+
+    let courses = entitlements_service.get_user_courses(user_id).await?;
+    Ok((courses.len() <= MAX_TOKEN_COURSES).then_some((courses, entitlements_version)))
+}
+
+/// Creates a new session for the user, returning its access token and refresh cookie
+pub(super) async fn start_session(sessions_service: &SessionRepository, roles_service: &RoleRepository, entitlements_service: &EntitlementRepository, jwt_service: &JwtRepository, user: &user::Model) -> LocalResult<(String, Cookie<'static>)> {
+    let session = sessions_service.create_session(user.id).await?;
+    let roles = roles_service.get_user_roles(user.id).await?;
+    let courses = token_courses(entitlements_service, user.id, user.entitlements_version).await?;
+    let access_token = jwt_service.generate_access_token(session.id, user.id, user.version, roles, courses)?;
+    let refresh_token = jwt_service.generate_refresh_token(session.id, user.id, user.version)?;
+    Ok((access_token, refresh_token))
+}
+
+
+/// Reads the `file` field of the form, failing as soon as it exceeds `MAX_IMAGE_BYTES`
+async fn read_image_field(multipart: &mut Multipart) -> LocalResult<Vec<u8>> {
+    let rejection = || LocalErr::new(LocalErrKind::MultipartRejection, StatusCode::BAD_REQUEST);
+
+    while let Some(mut field) = multipart.next_field().await.map_err_print(|_| rejection())? {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err_print(|_| rejection())? {
+            if data.len() + chunk.len() > MAX_IMAGE_BYTES {
+                return Err(LocalErr::new(LocalErrKind::ImageTooLarge, StatusCode::PAYLOAD_TOO_LARGE)
+                    .with_msg(format!("images can't be bigger than {} bytes", MAX_IMAGE_BYTES)))
+            }
+            data.extend_from_slice(&chunk);
+        }
+        return Ok(data)
+    }
+
+    Err(rejection().with_msg("missing `file` field"))
+}
+
+
+async fn store_user_image(state: AppState, user_id: uuid::Uuid, mut multipart: Multipart, kind: ImageKind) -> LocalResult<Json<UserProfileResponse>> {
+    let AppState { users_service, storage, .. } = state;
+
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    let data = read_image_field(&mut multipart).await?;
+    let encoded = tokio::task::spawn_blocking(move || image::process_image(kind, &data))
+        .await
+        .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))??;
+
+    let key = format!("{}/{}", kind.prefix(), uuid::Uuid::new_v4());
+    for (variant, data) in kind.variants().iter().zip(encoded) {
+        storage.put(&ImageKind::variant_key(&key, variant), data, "image/jpeg").await?;
+    }
+
+    let mut update = user::ActiveModel {
+        id: Set(user.id),
+        ..Default::default()
+    };
+    let old = match kind {
+        ImageKind::Avatar => {
+            update.avatar = Set(Some(key));
+            user.avatar
+        },
+        ImageKind::Banner => {
+            update.banner = Set(Some(key));
+            user.banner
+        },
+    };
+
+    let user = users_service.update_user(update).await?;
+    if let Some(old) = old {
+        image::delete_image(&storage, kind, &old).await;
+    }
+
+    Ok(Json(user.into()))
+}
+
+
+async fn remove_user_image(state: AppState, user_id: uuid::Uuid, kind: ImageKind) -> LocalResult<Json<UserProfileResponse>> {
+    let AppState { users_service, storage, .. } = state;
+
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    let mut update = user::ActiveModel {
+        id: Set(user.id),
+        ..Default::default()
+    };
+    let old = match kind {
+        ImageKind::Avatar => {
+            update.avatar = Set(None);
+            user.avatar.clone()
+        },
+        ImageKind::Banner => {
+            update.banner = Set(None);
+            user.banner.clone()
+        },
+    };
+
+    let Some(old) = old else {
+        return Ok(Json(user.into()))
+    };
+
+    let user = users_service.update_user(update).await?;
+    image::delete_image(&storage, kind, &old).await;
+
+    Ok(Json(user.into()))
+}
+
+
+async fn send_verification_email(user_tokens_service: &UserTokenRepository, mailer: &Arc<dyn Mailer>, user: &user::Model) -> LocalResult<()> {
+    let token = user_tokens_service.create_token(user.id, UserTokenKind::EmailVerification, None, CONFIG.email_verification_exp_time).await?;
+
+    mailer.send(Mail {
+        to: user.email.clone(),
+        subject: "Verify your email".to_string(),
+        body: format!(
+            "Hi {},\n\nConfirm this is your email address using the following link:\n{}/verify-email?token={}",
+            user.username, CONFIG.frontend_url, token
+        ),
+    }).await
+}
+
+
+pub(super) async fn send_password_reset_email(user_tokens_service: &UserTokenRepository, mailer: &Arc<dyn Mailer>, user: &user::Model) -> LocalResult<()> {
+    let token = user_tokens_service.create_token(user.id, UserTokenKind::PasswordReset, None, CONFIG.password_reset_exp_time).await?;
+
+    mailer.send(Mail {
+        to: user.email.clone(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {},\n\nUse the following link to choose a new password, it expires in {} minutes:\n{}/reset-password?token={}\n\nIf you didn't ask for it, ignore this email.",
+            user.username, CONFIG.password_reset_exp_time.num_minutes(), CONFIG.frontend_url, token
+        ),
+    }).await
 }
 
 
 #[utoipa::path(post, path = "/api/auth/register", responses((status = 200, body = UserRequestsResponse)))]
 pub async fn register(
-    State(AppState { users_service, jwt_service , .. }): State<AppState>,
+    State(AppState { users_service, sessions_service, roles_service, entitlements_service, user_tokens_service, jwt_service, mailer, .. }): State<AppState>,
     Json(body): Json<RegisterRequestBody>
 ) -> LocalResult<(CookieJar, Json<UserRequestsResponse>)> {
     let exists_cond = Condition::any()
@@ -30,10 +197,21 @@ pub async fn register(
         return Err(LocalErr::new(LocalErrKind::UserAlredyExists, StatusCode::BAD_REQUEST))
     }
     
-    let user = users_service.insert_user(body.try_into()?).await?;
+    let user = users_service.insert_user(body.try_into()?, role::STUDENT).await?;
+    send_verification_email(&user_tokens_service, &mailer, &user).await?;
+
+    // the account can't be used until the email is verified
+    if CONFIG.email_verification == EmailVerificationPolicy::Login {
+        let resp_body = UserRequestsResponse {
+            avatar: user.avatar,
+            email: user.email,
+            username: user.username,
+            token: None
+        };
+        return Ok((CookieJar::new(), Json(resp_body)))
+    }
 
-    let access_token = jwt_service.generate_access_token(user.id, user.version)?;
-    let refresh_token = jwt_service.generate_refresh_token(user.id, user.version)?;
+    let (access_token, refresh_token) = start_session(&sessions_service, &roles_service, &entitlements_service, &jwt_service, &user).await?;
     let jar = CookieJar::new().add(refresh_token);
 
     let resp_body = UserRequestsResponse {
@@ -47,23 +225,48 @@ pub async fn register(
 }
 
 
-#[utoipa::path(post, path = "/api/auth/login", responses((status = 200, body = UserRequestsResponse)))]
+#[utoipa::path(post, path = "/api/auth/login", responses((status = 200, body = LoginResponse)))]
 pub async fn login(
-    State(AppState { users_service, jwt_service , .. }): State<AppState>,
+    State(AppState { users_service, sessions_service, roles_service, entitlements_service, mfa_service, jwt_service, login_guard, .. }): State<AppState>,
+    ClientIp(ip): ClientIp,
     Json(body): Json<LoginRequestBody>,
-) -> LocalResult<(CookieJar, Json<UserRequestsResponse>)> {
+) -> LocalResult<(CookieJar, Json<LoginResponse>)> {
+    // the ip, and the credential if it matches no account
+    login_guard.check(ip, LoginAccount::Unknown(&body.credential.0)).await?;
+
     let exists_cond = Condition::any()
         .add(user::Column::Email.eq(&body.credential.0))
         .add(user::Column::Username.eq(&body.credential.0));
 
-    let user = users_service.get_user_by(exists_cond)
-        .await?
-        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+    let user = users_service.get_user_by(exists_cond).await?;
+    let Some(user) = user else {
+        login_guard.failure(ip, LoginAccount::Unknown(&body.credential.0)).await?;
+        return Err(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))
+    };
 
-    user.password_hash.verify_password(&body.password.0)?;
+    let account = LoginAccount::Known(user.id);
+    login_guard.check(ip, account).await?;
+    if let Err(e) = user.password_hash.verify_password(&body.password.0) {
+        login_guard.failure(ip, account).await?;
+        return Err(e)
+    }
+    login_guard.success(ip, account).await?;
+
+    if CONFIG.email_verification == EmailVerificationPolicy::Login && user.email_verified_at.is_none() {
+        return Err(LocalErr::new(LocalErrKind::EmailNotVerified, StatusCode::FORBIDDEN))
+    }
+
+    // no tokens until the second factor is verified
+    if mfa_service.get_enabled_mfa(user.id).await?.is_some() {
+        let login_id = mfa_service.start_login(user.id).await?;
+        let resp_body = MfaPendingResponse {
+            mfa_required: true,
+            mfa_token: jwt_service.generate_mfa_token(login_id, user.id, user.version)?,
+        };
+        return Ok((CookieJar::new(), Json(LoginResponse::MfaRequired(resp_body))))
+    }
 
-    let access_token = jwt_service.generate_access_token(user.id, user.version)?;
-    let refresh_token = jwt_service.generate_refresh_token(user.id, user.version)?;
+    let (access_token, refresh_token) = start_session(&sessions_service, &roles_service, &entitlements_service, &jwt_service, &user).await?;
     let jar = CookieJar::new().add(refresh_token);
 
     let resp_body = UserRequestsResponse {
@@ -73,40 +276,434 @@ pub async fn login(
         token: Some(access_token)
     };
 
-    Ok((jar, Json(resp_body)))
+    Ok((jar, Json(LoginResponse::LoggedIn(resp_body))))
 }
 
 
-#[utoipa::path(get, path = "/api/auth/user", responses((status = 200, body = UserRequestsResponse)))]
+#[utoipa::path(get, path = "/api/auth/user", responses((status = 200, body = UserProfileResponse)))]
 pub async fn get_user_profile(
     State(AppState { users_service, .. }): State<AppState>,
     UserId(user_id): UserId
-) -> LocalResult<Json<UserRequestsResponse>> {
+) -> LocalResult<Json<UserProfileResponse>> {
     let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
         .await?
         .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
 
-    let resp_body = UserRequestsResponse {
-        avatar: user.avatar,
-        email: user.email,
-        username: user.username,
-        token: None
+    Ok(Json(user.into()))
+}
+
+
+#[utoipa::path(patch, path = "/api/auth/user", responses((status = 200, body = UserProfileResponse)))]
+pub async fn update_user_profile(
+    State(AppState { users_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+    Json(body): Json<UpdateUserRequestBody>,
+) -> LocalResult<Json<UserProfileResponse>> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    let mut update = user::ActiveModel {
+        id: Set(user.id),
+        ..Default::default()
     };
 
-    Ok(Json(resp_body))
+    if let Some(username) = body.username.filter(|u| u.0 != user.username) {
+        let taken_cond = Condition::all()
+            .add(user::Column::Username.eq(&username.0))
+            .add(user::Column::Id.ne(user.id));
+
+        if users_service.exists_user(taken_cond).await? {
+            return Err(LocalErr::new(LocalErrKind::UserAlredyExists, StatusCode::BAD_REQUEST))
+        }
+        update.username = Set(username.0);
+    }
+    if let Some(birth_date) = body.birth_date {
+        update.birth_date = Set(birth_date);
+    }
+    if let Some(sex) = body.sex {
+        update.sex = Set(sex);
+    }
+
+    if !update.is_changed() {
+        return Ok(Json(user.into()))
+    }
+
+    let user = users_service.update_user(update).await?;
+    Ok(Json(user.into()))
 }
 
 
 #[utoipa::path(post, path = "/api/auth/refresh", responses((status = 200, body = RefreshAccessTokenResponse)))]
 pub async fn refresh_access_token(
-    State(AppState { jwt_service, .. }): State<AppState>,
+    State(AppState { users_service, sessions_service, roles_service, entitlements_service, jwt_service, .. }): State<AppState>,
     jar: CookieJar,
-) -> LocalResult<Json<RefreshAccessTokenResponse>> {
+) -> LocalResult<(CookieJar, Json<RefreshAccessTokenResponse>)> {
     let refresh_token = jar.get(&CONFIG.jwt_refresh_cookie_name)
         .ok_or(LocalErr::new(LocalErrKind::Unauthorized, StatusCode::UNAUTHORIZED))?
         .value();
         
     let claims = jwt_service.validate_refresh_token(refresh_token)?;
-    let new_access = jwt_service.generate_access_token(claims.user_id, claims.version)?;
-    Ok(Json(RefreshAccessTokenResponse { token: new_access }))
-}
\ No newline at end of file
+
+    // password/email changed since login: this device must log in again (changed courses only need a new access token)
+    if !users_service.is_current_version(claims.user_id, claims.version).await? {
+        sessions_service.revoke_session(claims.jti).await?;
+        return Err(LocalErr::new(LocalErrKind::InvalidRefreshToken, StatusCode::UNAUTHORIZED));
+    }
+
+    let session = sessions_service.rotate_session(claims.jti, claims.user_id).await?;
+
+    let roles = roles_service.get_user_roles(claims.user_id).await?;
+    let entitlements_version = users_service.get_entitlements_version(claims.user_id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::InvalidRefreshToken, StatusCode::UNAUTHORIZED))?;
+    let courses = token_courses(&entitlements_service, claims.user_id, entitlements_version).await?;
+    let new_access = jwt_service.generate_access_token(session.id, claims.user_id, claims.version, roles, courses)?;
+    let new_refresh = jwt_service.generate_refresh_token(session.id, claims.user_id, claims.version)?;
+    let jar = jar.add(new_refresh);
+
+    Ok((jar, Json(RefreshAccessTokenResponse { token: new_access })))
+}
+
+
+#[utoipa::path(post, path = "/api/auth/logout", responses((status = 204)))]
+pub async fn logout(
+    State(AppState { sessions_service, jwt_service, .. }): State<AppState>,
+    jar: CookieJar,
+) -> LocalResult<(StatusCode, CookieJar)> {
+    let claims = jar.get(&CONFIG.jwt_refresh_cookie_name)
+        .and_then(|c| jwt_service.validate_refresh_token(c.value()).ok());
+
+    // an invalid or missing cookie is already "logged out", just clear it
+    if let Some(claims) = claims {
+        sessions_service.revoke_session(claims.jti).await?;
+    }
+
+    Ok((StatusCode::NO_CONTENT, jar.remove(jwt_service.remove_refresh_token())))
+}
+
+
+#[utoipa::path(post, path = "/api/auth/logout-all", responses((status = 204)))]
+pub async fn logout_all(
+    State(AppState { sessions_service, jwt_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+    jar: CookieJar,
+) -> LocalResult<(StatusCode, CookieJar)> {
+    sessions_service.revoke_user_sessions(user_id).await?;
+    Ok((StatusCode::NO_CONTENT, jar.remove(jwt_service.remove_refresh_token())))
+}
+
+
+#[utoipa::path(post, path = "/api/auth/password/forgot", responses((status = 202)))]
+pub async fn forgot_password(
+    State(AppState { users_service, user_tokens_service, mailer, .. }): State<AppState>,
+    Json(body): Json<ForgotPasswordRequestBody>,
+) -> LocalResult<StatusCode> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Email.eq(&body.email.0)))
+        .await?;
+
+    // same response whether the email exists or not
+    let Some(user) = user else {
+        return Ok(StatusCode::ACCEPTED)
+    };
+
+    send_password_reset_email(&user_tokens_service, &mailer, &user).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+
+#[utoipa::path(post, path = "/api/auth/password/reset", responses((status = 204)))]
+pub async fn reset_password(
+    State(AppState { users_service, sessions_service, user_tokens_service, .. }): State<AppState>,
+    Json(body): Json<ResetPasswordRequestBody>,
+) -> LocalResult<StatusCode> {
+    let token = user_tokens_service.consume_token(&body.token.0, UserTokenKind::PasswordReset)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::InvalidPasswordResetToken, StatusCode::BAD_REQUEST))?;
+
+    // setting the hash bumps `version`, so every issued token stops being valid
+    users_service.update_user(user::ActiveModel {
+        id: Set(token.user_id),
+        password_hash: Set(Password(body.password.0).hash_password()?),
+        ..Default::default()
+    }).await?;
+    sessions_service.revoke_user_sessions(token.user_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+#[utoipa::path(post, path = "/api/auth/email/verify", responses((status = 204)))]
+pub async fn verify_email(
+    State(AppState { users_service, user_tokens_service, .. }): State<AppState>,
+    Json(body): Json<VerifyEmailRequestBody>,
+) -> LocalResult<StatusCode> {
+    let token = user_tokens_service.consume_token(&body.token.0, UserTokenKind::EmailVerification)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::InvalidEmailVerificationToken, StatusCode::BAD_REQUEST))?;
+
+    users_service.update_user(user::ActiveModel {
+        id: Set(token.user_id),
+        email_verified_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    }).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+#[utoipa::path(post, path = "/api/auth/email/verify/resend", responses((status = 202)))]
+pub async fn resend_verification_email(
+    State(AppState { users_service, user_tokens_service, mailer, .. }): State<AppState>,
+    Json(body): Json<ResendVerificationEmailRequestBody>,
+) -> LocalResult<StatusCode> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Email.eq(&body.email.0)))
+        .await?
+        .filter(|u| u.email_verified_at.is_none());
+
+    let Some(user) = user else {
+        return Ok(StatusCode::ACCEPTED)
+    };
+
+    let last_sent = user_tokens_service.last_creation_date(user.id, UserTokenKind::EmailVerification).await?;
+    if let Some(last_sent) = last_sent {
+        let wait = last_sent.to_utc() + CONFIG.email_verification_resend_time - Utc::now();
+        if wait > chrono::Duration::zero() {
+            let seconds = wait.num_seconds() as u64 + 1;
+            return Err(LocalErr::new(LocalErrKind::EmailVerificationThrottled, StatusCode::TOO_MANY_REQUESTS)
+                .with_msg(format!("wait {} seconds before asking for another email", seconds))
+                .with_retry_after(seconds))
+        }
+    }
+
+    send_verification_email(&user_tokens_service, &mailer, &user).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+
+#[utoipa::path(put, path = "/api/auth/password", responses((status = 200, body = RefreshAccessTokenResponse)))]
+pub async fn change_password(
+    State(AppState { users_service, sessions_service, roles_service, entitlements_service, jwt_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+    Json(body): Json<ChangePasswordRequestBody>,
+) -> LocalResult<(CookieJar, Json<RefreshAccessTokenResponse>)> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    user.password_hash.verify_password(&body.current_password.0)?;
+
+    // bumps `version`: every other device is logged out, this one gets new tokens
+    let user = users_service.update_user(user::ActiveModel {
+        id: Set(user.id),
+        password_hash: Set(Password(body.new_password.0).hash_password()?),
+        ..Default::default()
+    }).await?;
+    sessions_service.revoke_user_sessions(user.id).await?;
+
+    let (access_token, refresh_token) = start_session(&sessions_service, &roles_service, &entitlements_service, &jwt_service, &user).await?;
+    let jar = CookieJar::new().add(refresh_token);
+
+    Ok((jar, Json(RefreshAccessTokenResponse { token: access_token })))
+}
+
+
+#[utoipa::path(post, path = "/api/auth/email", responses((status = 202)))]
+pub async fn change_email(
+    State(AppState { users_service, user_tokens_service, mailer, .. }): State<AppState>,
+    VerifiedUserId(user_id): VerifiedUserId,
+    Json(body): Json<ChangeEmailRequestBody>,
+) -> LocalResult<StatusCode> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    user.password_hash.verify_password(&body.password.0)?;
+
+    // inactive accounts keep their email too
+    if users_service.exists_user(Condition::all().add(user::Column::Email.eq(&body.new_email.0))).await? {
+        return Err(LocalErr::new(LocalErrKind::UserAlredyExists, StatusCode::BAD_REQUEST))
+    }
+
+    // the address only changes once the link sent to it is used
+    let token = user_tokens_service.create_token(user.id, UserTokenKind::EmailChange, Some(body.new_email.0.clone()), CONFIG.email_change_exp_time).await?;
+
+    mailer.send(Mail {
+        to: body.new_email.0,
+        subject: "Confirm your new email".to_string(),
+        body: format!(
+            "Hi {},\n\nUse the following link to start using this address in your account:\n{}/confirm-email?token={}",
+            user.username, CONFIG.frontend_url, token
+        ),
+    }).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+
+#[utoipa::path(post, path = "/api/auth/email/confirm", responses((status = 200, body = RefreshAccessTokenResponse)))]
+pub async fn confirm_email_change(
+    State(AppState { users_service, sessions_service, roles_service, entitlements_service, user_tokens_service, jwt_service, mailer, .. }): State<AppState>,
+    UserId(user_id): UserId,
+    Json(body): Json<ConfirmEmailChangeRequestBody>,
+) -> LocalResult<(CookieJar, Json<RefreshAccessTokenResponse>)> {
+    let invalid = || LocalErr::new(LocalErrKind::InvalidEmailChangeToken, StatusCode::BAD_REQUEST);
+
+    // a token of another user stays usable by them
+    let token = user_tokens_service.consume_user_token(&body.token.0, UserTokenKind::EmailChange, user_id)
+        .await?
+        .ok_or_else(invalid)?;
+    let new_email = token.payload.ok_or_else(invalid)?;
+
+    // someone may have registered with it while the link was pending
+    if users_service.exists_user(Condition::all().add(user::Column::Email.eq(&new_email))).await? {
+        return Err(LocalErr::new(LocalErrKind::UserAlredyExists, StatusCode::BAD_REQUEST))
+    }
+
+    let old = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    // bumps `version`: every other device is logged out, this one gets new tokens
+    let user = users_service.update_user(user::ActiveModel {
+        id: Set(user_id),
+        email: Set(new_email),
+        email_verified_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    }).await?;
+    sessions_service.revoke_user_sessions(user.id).await?;
+
+    let (access_token, refresh_token) = start_session(&sessions_service, &roles_service, &entitlements_service, &jwt_service, &user).await?;
+    let jar = CookieJar::new().add(refresh_token);
+
+    // only a notification, the change is done
+    let notified = mailer.send(Mail {
+        to: old.email,
+        subject: "Your email was changed".to_string(),
+        body: format!(
+            "Hi {},\n\nThe email of your account was changed to {}. If it wasn't you, contact support.",
+            user.username, user.email
+        ),
+    }).await;
+    if let Err(e) = notified {
+        eprintln!("email change of {}: the old address wasn't notified: {:?}", user.id, e);
+    }
+
+    Ok((jar, Json(RefreshAccessTokenResponse { token: access_token })))
+}
+
+
+#[utoipa::path(
+    post, path = "/api/auth/user/avatar",
+    request_body(content = String, content_type = "multipart/form-data", description = "png, jpeg, webp or gif image in the `file` field"),
+    responses((status = 200, body = UserProfileResponse))
+)]
+pub async fn upload_avatar(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+    multipart: Multipart,
+) -> LocalResult<Json<UserProfileResponse>> {
+    store_user_image(state, user_id, multipart, ImageKind::Avatar).await
+}
+
+
+#[utoipa::path(
+    post, path = "/api/auth/user/banner",
+    request_body(content = String, content_type = "multipart/form-data", description = "png, jpeg, webp or gif image in the `file` field"),
+    responses((status = 200, body = UserProfileResponse))
+)]
+pub async fn upload_banner(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+    multipart: Multipart,
+) -> LocalResult<Json<UserProfileResponse>> {
+    store_user_image(state, user_id, multipart, ImageKind::Banner).await
+}
+
+
+#[utoipa::path(delete, path = "/api/auth/user/avatar", responses((status = 200, body = UserProfileResponse)))]
+pub async fn delete_avatar(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<UserProfileResponse>> {
+    remove_user_image(state, user_id, ImageKind::Avatar).await
+}
+
+
+#[utoipa::path(delete, path = "/api/auth/user/banner", responses((status = 200, body = UserProfileResponse)))]
+pub async fn delete_banner(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<UserProfileResponse>> {
+    remove_user_image(state, user_id, ImageKind::Banner).await
+}
+
+
+/// Logs the user out everywhere and anonymises the account once `ACCOUNT_DELETION_GRACE_DAYS` pass
+#[utoipa::path(delete, path = "/api/auth/user", responses((status = 200, body = AccountDeletionResponse)))]
+pub async fn delete_account(
+    State(AppState { users_service, sessions_service, jwt_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+    jar: CookieJar,
+    Json(body): Json<DeleteAccountRequestBody>,
+) -> LocalResult<(CookieJar, Json<AccountDeletionResponse>)> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    user.password_hash.verify_password(&body.password.0)?;
+
+    let deletion_scheduled_at = Utc::now() + CONFIG.account_deletion_grace_time;
+    users_service.update_user(user::ActiveModel {
+        id: Set(user.id),
+        deletion_scheduled_at: Set(Some(deletion_scheduled_at.into())),
+        ..Default::default()
+    }).await?;
+    sessions_service.revoke_user_sessions(user.id).await?;
+
+    Ok((jar.remove(jwt_service.remove_refresh_token()), Json(AccountDeletionResponse { deletion_scheduled_at })))
+}
+
+
+/// Cancels a scheduled deletion
+#[utoipa::path(post, path = "/api/auth/user/restore", responses((status = 200, body = UserProfileResponse)))]
+pub async fn restore_account(
+    State(AppState { users_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<UserProfileResponse>> {
+    let user = users_service.update_user(user::ActiveModel {
+        id: Set(user_id),
+        deletion_scheduled_at: Set(None),
+        ..Default::default()
+    }).await?;
+
+    Ok(Json(user.into()))
+}
+
+
+#[utoipa::path(get, path = "/api/auth/user/export", responses((status = 200, body = UserExportResponse)))]
+pub async fn export_user_data(
+    State(AppState { users_service, sessions_service, roles_service, mfa_service, audit_service, payments_service, refunds_service, invoices_service, subscriptions_service, entitlements_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<([(HeaderName, String); 1], Json<UserExportResponse>)> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    let export = UserExportResponse {
+        generation_date: Utc::now(),
+        roles: roles_service.get_user_roles(user_id).await?,
+        mfa_enabled: mfa_service.get_enabled_mfa(user_id).await?.is_some(),
+        sessions: sessions_service.get_user_sessions(user_id).await?.into_iter().map(ExportedSession::from).collect(),
+        audit_events: audit_service.get_user_events(user_id).await?.into_iter().map(ExportedAuditEvent::from).collect(),
+        orders: payments_service.get_user_orders(user_id).await?.into_iter().map(OrderResponse::from).collect(),
+        refunds: refunds_service.get_user_refunds(user_id).await?.into_iter().map(RefundResponse::from).collect(),
+        invoices: invoices_service.get_user_invoices(user_id).await?.into_iter().map(InvoiceResponse::from).collect(),
+        subscriptions: subscriptions_service.get_user_subscriptions(user_id).await?.into_iter().map(SubscriptionResponse::from).collect(),
+        entitlements: entitlements_service.get_user_entitlements(user_id).await?.into_iter().map(EntitlementResponse::from).collect(),
+        profile: user.into(),
+    };
+
+    let disposition = format!("attachment; filename=\"{}-export.json\"", user_id);
+    Ok(([(CONTENT_DISPOSITION, disposition)], Json(export)))
+}
@@ -0,0 +1,139 @@
+use axum::{Router, extract::State, http::StatusCode, routing::{get, post}};
+use sea_orm::{ColumnTrait, Condition};
+
+use crate::{config::{CONFIG, EmailVerificationPolicy}, error::{LocalErr, LocalErrKind, LocalResult}, extract::{Form, Json, Path, ServiceClient}, models::entity::user, routes::dto::{entitlements::{AccessCheckResult, CheckAccessRequestBody, CheckAccessResponse, EntitlementResponse, MAX_ACCESS_CHECKS, UserEntitlementsResponse}, internal::{IntrospectRequestBody, IntrospectResponse, UserSubscriptionResponse}}, state::AppState};
+
+/// Endpoints for other backends, authenticated with `ServiceClient`
+pub fn internal_routes() -> Router<AppState> {
+    Router::new()
+        .route("/introspect", post(introspect))
+        .route("/users/{id}/subscription", get(get_user_subscription))
+        .route("/entitlements/check", post(check_access))
+        .route("/entitlements/{user_id}", get(get_user_entitlements))
+}
+
+
+/// Every scope granted to a logged user
+const USER_SCOPE: &str = "user";
+/// Granted once the email is verified (always with `EMAIL_VERIFICATION=off`)
+const VERIFIED_SCOPE: &str = "verified";
+
+#[utoipa::path(
+    post, path = "/api/internal/introspect",
+    request_body(content = IntrospectRequestBody, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 200, body = IntrospectResponse), (status = 401, description = "Invalid client credentials"))
+)]
+pub async fn introspect(
+    State(AppState { users_service, sessions_service, jwt_service, .. }): State<AppState>,
+    ServiceClient(client_id): ServiceClient,
+    Form(body): Form<IntrospectRequestBody>,
+) -> LocalResult<Json<IntrospectResponse>> {
+    let Ok(claims) = jwt_service.validate_access_token(&body.token.0) else {
+        return Ok(Json(IntrospectResponse::inactive()))
+    };
+
+    if !users_service.is_current_access(claims.user_id, claims.version, claims.entitlements_version).await?
+        || !sessions_service.is_family_active(claims.jti).await?
+    {
+        return Ok(Json(IntrospectResponse::inactive()))
+    }
+
+    let Some(user) = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(claims.user_id))).await? else {
+        return Ok(Json(IntrospectResponse::inactive()))
+    };
+
+    let mut scopes = vec![USER_SCOPE];
+    if CONFIG.email_verification == EmailVerificationPolicy::Off || user.email_verified_at.is_some() {
+        scopes.push(VERIFIED_SCOPE);
+    }
+
+    Ok(Json(IntrospectResponse {
+        active: true,
+        token_type: Some("access_token".to_string()),
+        client_id: Some(client_id),
+        sub: Some(user.id),
+        username: Some(user.username),
+        roles: Some(claims.roles),
+        scope: Some(scopes.join(" ")),
+        jti: Some(claims.jti),
+        exp: Some(claims.exp),
+        iat: Some(claims.iat),
+    }))
+}
+
+
+
+#[utoipa::path(
+    get, path = "/api/internal/users/{id}/subscription",
+    responses((status = 200, body = UserSubscriptionResponse), (status = 401, description = "Invalid client credentials"))
+)]
+pub async fn get_user_subscription(
+    State(AppState { subscriptions_service, .. }): State<AppState>,
+    _: ServiceClient,
+    Path(user_id): Path<uuid::Uuid>,
+) -> LocalResult<Json<UserSubscriptionResponse>> {
+    let subscription = subscriptions_service.get_current_subscription(user_id).await?;
+
+    Ok(Json(match subscription {
+        Some((subscription, plan)) => UserSubscriptionResponse {
+            user_id,
+            active: subscription.status.is_live(),
+            status: Some(subscription.status),
+            plan: Some(plan.code),
+            current_period_end: Some(subscription.current_period_end.to_utc()),
+            cancel_at_period_end: subscription.cancel_at_period_end,
+        },
+        None => UserSubscriptionResponse {
+            user_id,
+            active: false,
+            status: None,
+            plan: None,
+            current_period_end: None,
+            cancel_at_period_end: false,
+        },
+    }))
+}
+
+
+/// Courses the user can access, with how they got each one
+#[utoipa::path(
+    get, path = "/api/internal/entitlements/{user_id}",
+    responses((status = 200, body = UserEntitlementsResponse), (status = 401, description = "Invalid client credentials"))
+)]
+pub async fn get_user_entitlements(
+    State(AppState { entitlements_service, .. }): State<AppState>,
+    _: ServiceClient,
+    Path(user_id): Path<uuid::Uuid>,
+) -> LocalResult<Json<UserEntitlementsResponse>> {
+    let entitlements = entitlements_service.get_user_entitlements(user_id).await?;
+
+    Ok(Json(UserEntitlementsResponse {
+        user_id,
+        entitlements: entitlements.into_iter().map(EntitlementResponse::from).collect(),
+    }))
+}
+
+
+#[utoipa::path(
+    post, path = "/api/internal/entitlements/check",
+    responses((status = 200, body = CheckAccessResponse), (status = 401, description = "Invalid client credentials"))
+)]
+pub async fn check_access(
+    State(AppState { entitlements_service, .. }): State<AppState>,
+    _: ServiceClient,
+    Json(body): Json<CheckAccessRequestBody>,
+) -> LocalResult<Json<CheckAccessResponse>> {
+    if body.checks.len() > MAX_ACCESS_CHECKS {
+        return Err(LocalErr::new(LocalErrKind::TooManyAccessChecks, StatusCode::BAD_REQUEST)
+            .with_msg(format!("at most {} checks per request", MAX_ACCESS_CHECKS)))
+    }
+
+    let pairs: Vec<_> = body.checks.iter().map(|c| (c.user_id, c.course_id)).collect();
+    let granted = entitlements_service.check_access(&pairs).await?;
+
+    Ok(Json(CheckAccessResponse {
+        results: body.checks.into_iter()
+            .map(|check| AccessCheckResult { has_access: granted.contains(&(check.user_id, check.course_id)), check })
+            .collect(),
+    }))
+}
\ No newline at end of file
@@ -0,0 +1,132 @@
+use axum::{Router, extract::State, http::StatusCode, routing::post};
+use axum_extra::extract::CookieJar;
+use sea_orm::{ColumnTrait, Condition};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult}, extract::{ClientIp, Json, UserId}, models::entity::user, routes::{dto::{auth::UserRequestsResponse, mfa::{DisableMfaRequestBody, MfaCodeRequestBody, MfaEnrollmentResponse, MfaRecoveryCodesResponse, MfaVerifyRequestBody}}, endpoints::auth::start_session}, state::AppState, utils::totp};
+
+pub fn mfa_routes() -> Router<AppState> {
+    Router::new()
+        .route("/enroll", post(enroll_mfa))
+        .route("/confirm", post(confirm_mfa))
+        .route("/disable", post(disable_mfa))
+        .route("/verify", post(verify_mfa))
+}
+
+
+#[utoipa::path(post, path = "/api/auth/mfa/enroll", responses((status = 200, body = MfaEnrollmentResponse)))]
+pub async fn enroll_mfa(
+    State(AppState { users_service, mfa_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<MfaEnrollmentResponse>> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    if mfa_service.get_enabled_mfa(user.id).await?.is_some() {
+        return Err(LocalErr::new(LocalErrKind::MfaAlreadyEnabled, StatusCode::CONFLICT))
+    }
+
+    let secret = totp::generate_secret();
+    let (otpauth_uri, qr_code) = totp::enrollment_uri(&secret, &user.email)?;
+    mfa_service.set_pending_secret(user.id, secret.clone()).await?;
+
+    Ok(Json(MfaEnrollmentResponse { secret, otpauth_uri, qr_code }))
+}
+
+
+#[utoipa::path(post, path = "/api/auth/mfa/confirm", responses((status = 200, body = MfaRecoveryCodesResponse)))]
+pub async fn confirm_mfa(
+    State(AppState { mfa_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+    Json(body): Json<MfaCodeRequestBody>,
+) -> LocalResult<Json<MfaRecoveryCodesResponse>> {
+    let mfa = mfa_service.get_mfa(user_id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::MfaNotEnrolled, StatusCode::BAD_REQUEST))?;
+
+    if mfa.confirmation_date.is_some() {
+        return Err(LocalErr::new(LocalErrKind::MfaAlreadyEnabled, StatusCode::CONFLICT))
+    }
+
+    let step = totp::verify_code(&mfa.secret, &body.code.0)?
+        .ok_or(LocalErr::new(LocalErrKind::InvalidMfaCode, StatusCode::UNAUTHORIZED))?;
+
+    let recovery_codes = mfa_service.enable(user_id, step).await?;
+    Ok(Json(MfaRecoveryCodesResponse { recovery_codes }))
+}
+
+
+#[utoipa::path(post, path = "/api/auth/mfa/disable", responses((status = 204)))]
+pub async fn disable_mfa(
+    State(AppState { users_service, mfa_service, login_guard, .. }): State<AppState>,
+    ClientIp(ip): ClientIp,
+    UserId(user_id): UserId,
+    Json(body): Json<DisableMfaRequestBody>,
+) -> LocalResult<StatusCode> {
+    login_guard.check_mfa(ip, user_id).await?;
+
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    if let Err(e) = user.password_hash.verify_password(&body.password.0) {
+        login_guard.mfa_failure(ip, user.id).await?;
+        return Err(e)
+    }
+
+    let mfa = mfa_service.get_enabled_mfa(user.id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::MfaNotEnrolled, StatusCode::BAD_REQUEST))?;
+
+    if !mfa_service.check_code(&mfa, &body.code.0).await? {
+        login_guard.mfa_failure(ip, user.id).await?;
+        return Err(LocalErr::new(LocalErrKind::InvalidMfaCode, StatusCode::UNAUTHORIZED))
+    }
+    login_guard.mfa_success(ip, user.id).await?;
+
+    mfa_service.disable(user.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+#[utoipa::path(post, path = "/api/auth/mfa/verify", responses((status = 200, body = UserRequestsResponse)))]
+pub async fn verify_mfa(
+    State(AppState { users_service, sessions_service, roles_service, entitlements_service, mfa_service, jwt_service, login_guard, .. }): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<MfaVerifyRequestBody>,
+) -> LocalResult<(CookieJar, Json<UserRequestsResponse>)> {
+    let claims = jwt_service.validate_mfa_token(&body.mfa_token.0)?;
+    login_guard.check_mfa(ip, claims.user_id).await?;
+
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(claims.user_id)))
+        .await?
+        .filter(|u| u.version == claims.version)
+        .ok_or(LocalErr::new(LocalErrKind::InvalidMfaToken, StatusCode::UNAUTHORIZED))?;
+
+    let mfa = mfa_service.get_enabled_mfa(user.id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::MfaNotEnrolled, StatusCode::BAD_REQUEST))?;
+
+    if !mfa_service.check_code(&mfa, &body.code.0).await? {
+        login_guard.mfa_failure(ip, user.id).await?;
+        return Err(LocalErr::new(LocalErrKind::InvalidMfaCode, StatusCode::UNAUTHORIZED))
+    }
+    login_guard.mfa_success(ip, user.id).await?;
+
+    // a token that was already exchanged (or replaced by a newer login) can't start another session
+    if !mfa_service.finish_login(user.id, claims.jti).await? {
+        return Err(LocalErr::new(LocalErrKind::InvalidMfaToken, StatusCode::UNAUTHORIZED))
+    }
+
+    let (access_token, refresh_token) = start_session(&sessions_service, &roles_service, &entitlements_service, &jwt_service, &user).await?;
+    let jar = CookieJar::new().add(refresh_token);
+
+    let resp_body = UserRequestsResponse {
+        avatar: user.avatar,
+        email: user.email,
+        username: user.username,
+        token: Some(access_token)
+    };
+
+    Ok((jar, Json(resp_body)))
+}
\ No newline at end of file
@@ -1 +1,8 @@
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod users;
+pub mod mfa;
+pub mod well_known;
+pub mod internal;
+pub mod admin;
+pub mod payments;
+pub mod subscriptions;
\ No newline at end of file
@@ -0,0 +1,486 @@
+use std::sync::Arc;
+
+use axum::{Router, body::Bytes, extract::State, http::{HeaderMap, StatusCode, header}, response::{Html, IntoResponse, Redirect}, routing::{get, post}};
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, ColumnTrait, Condition};
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult}, extract::{Json, Path, UserId, VerifiedUserId}, invoices::{self, escape_html}, models::{entity::{coupon_redemption, coupon_target::CouponTargetType, invoice, order::{self, OrderStatus}, payment::{self, PaymentStatus}, refund, subscription::SubscriptionStatus, user}, repository::{coupon::CouponRepository, payment::PaymentRepository}}, payments::{Checkout, CheckoutSession, CheckoutStatus, DisputeStatus, FakePaymentProvider, FakeSession, FakeSubscription, PaymentProvider, Refund, webhook}, routes::dto::payments::{CheckoutRequestBody, CheckoutResponse, InvoiceResponse, OrderResponse, RefundRequestBody, RefundResponse}, state::AppState};
+
+pub fn payments_routes() -> Router<AppState> {
+    Router::new()
+        .route("/checkout", post(create_checkout))
+        .route("/orders", get(list_orders))
+        .route("/orders/{id}", get(get_order))
+        .route("/orders/{id}/refund", post(refund_own_order))
+        .route("/refunds", get(list_refunds))
+        .route("/invoices", get(list_invoices))
+        .route("/invoices/{id}", get(get_invoice))
+        .route("/invoices/{id}/html", get(get_invoice_html))
+        .route("/invoices/{id}/pdf", get(get_invoice_pdf))
+        .route("/webhook", post(payment_webhook))
+        .route("/fake/{session_id}", get(fake_checkout_page))
+        .route("/fake/{session_id}/pay", post(fake_pay))
+        .route("/fake/{session_id}/cancel", post(fake_cancel))
+        .route("/fake/subscriptions/{subscription_id}/renew", post(fake_renew_subscription))
+        .route("/fake/subscriptions/{subscription_id}/fail", post(fake_fail_subscription))
+        .route("/fake/payments/{payment_id}/dispute", post(fake_open_dispute))
+        .route("/fake/payments/{payment_id}/dispute/won", post(fake_win_dispute))
+        .route("/fake/payments/{payment_id}/dispute/lost", post(fake_lose_dispute))
+}
+
+
+/// Prices the course, minus the coupon, and opens a checkout session at the payment provider.
+/// If the user already has one open for the course, it is returned instead (with the price it was opened at),
+/// unless it is older than a session lives or the provider lost it: that order is canceled and a new one opened
+#[utoipa::path(post, path = "/api/payments/checkout", responses((status = 200, body = CheckoutResponse)))]
+pub async fn create_checkout(
+    State(AppState { users_service, payments_service, coupons_service, payment_provider, fake_payments, .. }): State<AppState>,
+    VerifiedUserId(user_id): VerifiedUserId,
+    Json(body): Json<CheckoutRequestBody>,
+) -> LocalResult<Json<CheckoutResponse>> {
+    let price = payments_service.get_course_price(body.course_id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::CourseNotForSale, StatusCode::NOT_FOUND))?;
+
+    if payments_service.has_paid_order(user_id, body.course_id).await? {
+        return Err(LocalErr::new(LocalErrKind::CourseAlreadyPurchased, StatusCode::CONFLICT))
+    }
+    if let Some((order, payment)) = payments_service.get_pending_order(user_id, body.course_id).await? {
+        // the fake provider keeps its sessions in memory only
+        let session_lost = |payment: &payment::Model| fake_payments.as_ref()
+            .is_some_and(|fake| payment.provider == payment_provider.name() && fake.get_session(&payment.provider_session_id).is_none());
+        match payment {
+            Some(payment) if PaymentRepository::is_reusable(&payment, session_lost(&payment), Utc::now()) => {
+                return Ok(Json(CheckoutResponse { order_id: order.id, checkout_url: payment.checkout_url }))
+            },
+            Some(payment) if payment.provider == payment_provider.name() => {
+                // best effort: if it still gets paid, the order is kept as money to give back
+                let _ = payment_provider.expire_checkout_session(&payment.provider_session_id).await;
+            },
+            _ => {},
+        }
+        payments_service.abandon_order(order.id).await?;
+    }
+
+    let coupon = match &body.coupon {
+        Some(code) => Some(coupons_service.quote(&code.0, user_id, (CouponTargetType::Course, price.course_id), price.amount, &price.currency, None).await?),
+        None => None,
+    };
+    let discount = coupon.as_ref().map_or(0, |(_, discount)| *discount);
+    let amount = price.amount - discount;
+
+    let order_id = uuid::Uuid::new_v4();
+    let success_url = format!("{}/orders/{}", CONFIG.frontend_url, order_id);
+    let now = Utc::now();
+    let order = order::ActiveModel {
+        id: Set(order_id),
+        user_id: Set(user_id),
+        course_id: Set(price.course_id),
+        amount: Set(amount),
+        discount_amount: Set(discount),
+        refunded_amount: Set(0),
+        currency: Set(price.currency.clone()),
+        status: Set(OrderStatus::Pending),
+        creation_date: Set(now.into()),
+        update_date: Set(now.into()),
+    };
+    let redemption = |order_id: Option<uuid::Uuid>| coupon.as_ref().map(|(coupon, discount)| coupon_redemption::Model {
+        id: uuid::Uuid::new_v4(),
+        coupon_id: coupon.id,
+        user_id,
+        order_id,
+        provider: None,
+        provider_session_id: None,
+        discount_amount: *discount,
+        creation_date: now.into(),
+        release_date: None,
+    });
+
+    // nothing to pay
+    if let Some(redemption) = redemption(Some(order_id)).filter(|_| amount == 0) {
+        payments_service.create_free_order(order::ActiveModel { status: Set(OrderStatus::Paid), ..order }, redemption).await?;
+        return Ok(Json(CheckoutResponse { order_id, checkout_url: success_url }))
+    }
+
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    let session = payment_provider.create_checkout_session(Checkout {
+        order_id,
+        amount,
+        currency: &price.currency,
+        description: &price.title,
+        customer_email: &user.email,
+        success_url,
+        cancel_url: format!("{}/orders/{}?canceled=true", CONFIG.frontend_url, order_id),
+    });
+    let checkout_url = open_checkout(&coupons_service, &*payment_provider, redemption(None), session, async |session, redemption_id| {
+        payments_service.create_order(
+            order,
+            payment::ActiveModel {
+                id: Set(uuid::Uuid::new_v4()),
+                order_id: Set(order_id),
+                provider: Set(payment_provider.name().to_string()),
+                provider_session_id: Set(session.id.clone()),
+                provider_payment_id: Set(None),
+                checkout_url: Set(session.url.clone()),
+                amount: Set(amount),
+                currency: Set(price.currency.clone()),
+                status: Set(PaymentStatus::Pending),
+                creation_date: Set(now.into()),
+                update_date: Set(now.into()),
+            },
+            redemption_id,
+        ).await.map(|_| ())
+    }).await?;
+    Ok(Json(CheckoutResponse { order_id, checkout_url }))
+}
+
+/// Opens a checkout session with `open` and saves it with `save`, holding the use of the coupon if any before
+/// the session is opened with its discount. If either fails, the session is expired and the coupon given back
+pub(super) async fn open_checkout(
+    coupons_service: &CouponRepository,
+    payment_provider: &dyn PaymentProvider,
+    redemption: Option<coupon_redemption::Model>,
+    open: impl Future<Output = LocalResult<CheckoutSession>>,
+    save: impl AsyncFnOnce(&CheckoutSession, Option<uuid::Uuid>) -> LocalResult<()>,
+) -> LocalResult<String> {
+    let redemption_id = redemption.as_ref().map(|r| r.id);
+    if let Some(redemption) = redemption {
+        coupons_service.reserve(redemption).await?;
+    }
+
+    let opened: LocalResult<String> = async {
+        let session = open.await?;
+        if let Err(e) = save(&session, redemption_id).await {
+            // nothing refers to the session, it mustn't be paid
+            let _ = payment_provider.expire_checkout_session(&session.id).await;
+            return Err(e)
+        }
+        Ok(session.url)
+    }.await;
+
+    // the error that stopped the checkout is the one reported, the coupon then stays held until released by hand
+    if opened.is_err() && let Some(redemption_id) = redemption_id
+        && let Err(e) = coupons_service.release_reservation(redemption_id).await {
+        eprintln!("coupon use {} couldn't be given back: {}", redemption_id, e);
+    }
+    opened
+}
+
+
+#[utoipa::path(get, path = "/api/payments/orders", responses((status = 200, body = Vec<OrderResponse>)))]
+pub async fn list_orders(
+    State(AppState { payments_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<Vec<OrderResponse>>> {
+    let orders = payments_service.get_user_orders(user_id).await?;
+    Ok(Json(orders.into_iter().map(OrderResponse::from).collect()))
+}
+
+
+#[utoipa::path(get, path = "/api/payments/orders/{id}", responses((status = 200, body = OrderResponse)))]
+pub async fn get_order(
+    State(AppState { payments_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+    Path(order_id): Path<uuid::Uuid>,
+) -> LocalResult<Json<OrderResponse>> {
+    let order = payments_service.get_user_order(user_id, order_id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    Ok(Json(order.into()))
+}
+
+
+/// Reserves the amount, then asks the provider for the refund. The provider's answer is applied right away,
+/// its webhook event will be a no-op (a pending refund is settled by that event). Only a refusal fails the refund
+/// and gives the amount back: without an answer it is returned pending, until the webhook event settles it
+pub async fn refund_order(state: &AppState, order_id: uuid::Uuid, amount: Option<i64>, reason: Option<String>, requested_by: uuid::Uuid, audited: bool) -> LocalResult<refund::Model> {
+    let (refund, payment) = state.refunds_service.create_refund(order_id, amount, reason, requested_by, audited).await?;
+
+    let Some(payment_id) = payment.provider_payment_id.as_deref().filter(|_| payment.provider == state.payment_provider.name()) else {
+        state.refunds_service.fail_refund(refund.id).await?;
+        return Err(LocalErr::new(LocalErrKind::PaymentProviderError, StatusCode::CONFLICT)
+            .with_msg(format!("the payment can't be refunded through the `{}` provider", state.payment_provider.name())))
+    };
+
+    let reported = match state.payment_provider.create_refund(Refund { refund_id: refund.id, payment_id, amount: refund.amount }).await {
+        Ok(reported) => reported,
+        Err(e) if matches!(e.error, LocalErrKind::PaymentRefused) => {
+            state.refunds_service.fail_refund(refund.id).await?;
+            return Err(e)
+        },
+        // the provider may have made it anyway: it stays pending (and counted) until its webhook event settles it
+        Err(e) => {
+            eprintln!("refund {} left pending: {:?}", refund.id, e);
+            return Ok(refund)
+        },
+    };
+
+    state.refunds_service.apply_refund(None, &payment.provider, &reported).await?;
+    state.refunds_service.get_refund(refund.id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))
+}
+
+
+/// Refunds the whole order, which takes the course away. Only within `REFUND_WINDOW_DAYS` of the payment, unless it was paid twice
+#[utoipa::path(post, path = "/api/payments/orders/{id}/refund", responses((status = 200, body = RefundResponse)))]
+pub async fn refund_own_order(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+    Path(order_id): Path<uuid::Uuid>,
+    Json(body): Json<RefundRequestBody>,
+) -> LocalResult<Json<RefundResponse>> {
+    let order = state.payments_service.get_user_order(user_id, order_id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    // partial refunds are left to staff
+    if order.refunded_amount > 0 {
+        return Err(LocalErr::new(LocalErrKind::OrderNotRefundable, StatusCode::CONFLICT).with_msg("the order was partially refunded"))
+    }
+    // money paid for nothing (see `OrderStatus::RefundDue`) can be taken back at any time
+    let payment = state.refunds_service.get_order_payment(order.id).await?.filter(|_| order.status != OrderStatus::RefundDue);
+    if payment.is_some_and(|p| p.update_date.to_utc() + CONFIG.refund_window < Utc::now()) {
+        return Err(LocalErr::new(LocalErrKind::RefundWindowExpired, StatusCode::CONFLICT))
+    }
+
+    let refund = refund_order(&state, order.id, None, body.reason.map(|r| r.0), user_id, false).await?;
+    Ok(Json(refund.into()))
+}
+
+
+#[utoipa::path(get, path = "/api/payments/refunds", responses((status = 200, body = Vec<RefundResponse>)))]
+pub async fn list_refunds(
+    State(AppState { refunds_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<Vec<RefundResponse>>> {
+    let refunds = refunds_service.get_user_refunds(user_id).await?;
+    Ok(Json(refunds.into_iter().map(RefundResponse::from).collect()))
+}
+
+
+async fn find_invoice(state: &AppState, user_id: uuid::Uuid, invoice_id: uuid::Uuid) -> LocalResult<invoice::Model> {
+    state.invoices_service.get_user_invoice(user_id, invoice_id)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))
+}
+
+
+/// One per successful charge (course purchases and subscription payments), newest first
+#[utoipa::path(get, path = "/api/payments/invoices", responses((status = 200, body = Vec<InvoiceResponse>)))]
+pub async fn list_invoices(
+    State(AppState { invoices_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<Vec<InvoiceResponse>>> {
+    let invoices = invoices_service.get_user_invoices(user_id).await?;
+    Ok(Json(invoices.into_iter().map(InvoiceResponse::from).collect()))
+}
+
+
+#[utoipa::path(get, path = "/api/payments/invoices/{id}", responses((status = 200, body = InvoiceResponse)))]
+pub async fn get_invoice(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+    Path(invoice_id): Path<uuid::Uuid>,
+) -> LocalResult<Json<InvoiceResponse>> {
+    Ok(Json(find_invoice(&state, user_id, invoice_id).await?.into()))
+}
+
+
+#[utoipa::path(get, path = "/api/payments/invoices/{id}/html", responses((status = 200, description = "Printable invoice", content_type = "text/html")))]
+pub async fn get_invoice_html(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+    Path(invoice_id): Path<uuid::Uuid>,
+) -> LocalResult<Html<String>> {
+    let invoice = find_invoice(&state, user_id, invoice_id).await?;
+    Ok(Html(invoices::render_html(&invoice)))
+}
+
+
+/// Downloaded as `<number>.pdf`
+#[utoipa::path(get, path = "/api/payments/invoices/{id}/pdf", responses((status = 200, description = "Invoice document", content_type = "application/pdf")))]
+pub async fn get_invoice_pdf(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+    Path(invoice_id): Path<uuid::Uuid>,
+) -> LocalResult<impl IntoResponse> {
+    let invoice = find_invoice(&state, user_id, invoice_id).await?;
+    let disposition = format!("attachment; filename=\"{}.pdf\"", invoice.invoice_number());
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/pdf".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        invoices::render_pdf(&invoice),
+    ))
+}
+
+
+/// Events of the payment provider, the only way orders get paid or canceled
+#[utoipa::path(post, path = "/api/payments/webhook", request_body(content = Object, description = "Stripe event, signed in the `Stripe-Signature` header"), responses((status = 200)))]
+pub async fn payment_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> LocalResult<StatusCode> {
+    let signature = headers.get(webhook::SIGNATURE_HEADER)
+        .and_then(|s| s.to_str().ok())
+        .ok_or(LocalErr::new(LocalErrKind::InvalidWebhookSignature, StatusCode::BAD_REQUEST))?;
+
+    webhook::receive(&state, &body, signature).await?;
+    Ok(StatusCode::OK)
+}
+
+
+fn fake_provider(fake_payments: Option<Arc<FakePaymentProvider>>) -> LocalResult<Arc<FakePaymentProvider>> {
+    fake_payments.ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))
+}
+
+/// Signs the event like the provider would and hands it to the webhook
+pub(super) async fn deliver_fake_event(state: &AppState, event: serde_json::Value) -> LocalResult<()> {
+    let payload = event.to_string();
+    let signature = webhook::sign(payload.as_bytes(), &CONFIG.payment_webhook_secret, Utc::now().timestamp());
+    webhook::receive(state, payload.as_bytes(), &signature).await
+}
+
+fn require_session(session: Option<FakeSession>) -> LocalResult<FakeSession> {
+    session.ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND).with_msg("unknown or closed checkout session"))
+}
+
+
+/// Checkout page of the fake provider (`PAYMENT_PROVIDER=fake`)
+#[utoipa::path(get, path = "/api/payments/fake/{session_id}", responses((status = 200, description = "HTML checkout page", content_type = "text/html")))]
+pub async fn fake_checkout_page(
+    State(AppState { fake_payments, .. }): State<AppState>,
+    Path(session_id): Path<String>,
+) -> LocalResult<Html<String>> {
+    let session = fake_provider(fake_payments)?
+        .get_session(&session_id)
+        .filter(|s| s.status == CheckoutStatus::Open);
+    let session = require_session(session)?;
+
+    let id = escape_html(&session.id);
+    Ok(Html(format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>Fake checkout</title></head><body>\n\
+         <h1>{}</h1>\n<p>{:.2} {}</p>\n\
+         <form method=\"post\" action=\"/api/payments/fake/{}/pay\"><button>Pay</button></form>\n\
+         <form method=\"post\" action=\"/api/payments/fake/{}/cancel\"><button>Cancel</button></form>\n\
+         </body></html>",
+        escape_html(&session.description),
+        session.amount as f64 / 100.0,
+        escape_html(&session.currency.to_uppercase()),
+        id, id,
+    )))
+}
+
+
+#[utoipa::path(post, path = "/api/payments/fake/{session_id}/pay", responses((status = 303, description = "Back to the frontend")))]
+pub async fn fake_pay(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> LocalResult<Redirect> {
+    let fake = fake_provider(state.fake_payments.clone())?;
+    let session = require_session(fake.pay(&session_id))?;
+
+    deliver_fake_event(&state, fake.event(&session)).await?;
+    if let Some(subscription) = session.subscription_id.as_deref().and_then(|id| fake.get_subscription(id)) {
+        deliver_fake_event(&state, fake.subscription_event(&subscription, true)).await?;
+        // nothing is charged before the end of the trial
+        if subscription.status == SubscriptionStatus::Active {
+            deliver_fake_event(&state, fake.invoice_event(&subscription, session.amount)).await?;
+        }
+    }
+    Ok(Redirect::to(&session.success_url))
+}
+
+
+#[utoipa::path(post, path = "/api/payments/fake/{session_id}/cancel", responses((status = 303, description = "Back to the frontend")))]
+pub async fn fake_cancel(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> LocalResult<Redirect> {
+    let fake = fake_provider(state.fake_payments.clone())?;
+    let session = require_session(fake.cancel(&session_id))?;
+
+    deliver_fake_event(&state, fake.event(&session)).await?;
+    Ok(Redirect::to(&session.cancel_url))
+}
+
+
+fn require_subscription(subscription: Option<FakeSubscription>) -> LocalResult<FakeSubscription> {
+    subscription.ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND).with_msg("unknown or ended subscription"))
+}
+
+
+/// Ends the current period of a fake subscription: it is renewed, or ends if it was canceled
+#[utoipa::path(post, path = "/api/payments/fake/subscriptions/{subscription_id}/renew", responses((status = 204)))]
+pub async fn fake_renew_subscription(
+    State(state): State<AppState>,
+    Path(subscription_id): Path<String>,
+) -> LocalResult<StatusCode> {
+    let fake = fake_provider(state.fake_payments.clone())?;
+    let subscription = require_subscription(fake.renew(&subscription_id))?;
+
+    deliver_fake_event(&state, fake.subscription_event(&subscription, false)).await?;
+    if subscription.status == SubscriptionStatus::Active {
+        deliver_fake_event(&state, fake.invoice_event(&subscription, subscription.amount)).await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// The renewal of a fake subscription fails, twice in a row ends it
+#[utoipa::path(post, path = "/api/payments/fake/subscriptions/{subscription_id}/fail", responses((status = 204)))]
+pub async fn fake_fail_subscription(
+    State(state): State<AppState>,
+    Path(subscription_id): Path<String>,
+) -> LocalResult<StatusCode> {
+    let fake = fake_provider(state.fake_payments.clone())?;
+    let subscription = require_subscription(fake.fail(&subscription_id))?;
+
+    deliver_fake_event(&state, fake.subscription_event(&subscription, false)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+
+async fn deliver_fake_dispute(state: &AppState, payment_id: &str, status: DisputeStatus) -> LocalResult<StatusCode> {
+    let event = fake_provider(state.fake_payments.clone())?
+        .dispute_event(payment_id, status)
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND).with_msg("unknown payment"))?;
+
+    deliver_fake_event(state, event).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// The buyer's bank disputes a fake payment, which takes the course away
+#[utoipa::path(post, path = "/api/payments/fake/payments/{payment_id}/dispute", responses((status = 204)))]
+pub async fn fake_open_dispute(
+    State(state): State<AppState>,
+    Path(payment_id): Path<String>,
+) -> LocalResult<StatusCode> {
+    deliver_fake_dispute(&state, &payment_id, DisputeStatus::Open).await
+}
+
+
+/// The dispute is closed in our favor, the course is given back
+#[utoipa::path(post, path = "/api/payments/fake/payments/{payment_id}/dispute/won", responses((status = 204)))]
+pub async fn fake_win_dispute(
+    State(state): State<AppState>,
+    Path(payment_id): Path<String>,
+) -> LocalResult<StatusCode> {
+    deliver_fake_dispute(&state, &payment_id, DisputeStatus::Won).await
+}
+
+
+#[utoipa::path(post, path = "/api/payments/fake/payments/{payment_id}/dispute/lost", responses((status = 204)))]
+pub async fn fake_lose_dispute(
+    State(state): State<AppState>,
+    Path(payment_id): Path<String>,
+) -> LocalResult<StatusCode> {
+    deliver_fake_dispute(&state, &payment_id, DisputeStatus::Lost).await
+}
\ No newline at end of file
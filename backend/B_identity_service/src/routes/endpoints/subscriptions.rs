@@ -0,0 +1,218 @@
+use axum::{Router, extract::State, http::StatusCode, routing::{get, post, put}};
+use chrono::Utc;
+use sea_orm::{ColumnTrait, Condition};
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult}, extract::{Json, UserId, VerifiedUserId}, models::entity::{coupon_redemption, coupon_target::CouponTargetType, payment::PaymentStatus, plan, subscription::{self, SubscriptionStatus}, subscription_checkout, user}, payments::{SubscriptionCheckout, SubscriptionUpdate}, routes::{dto::subscriptions::{ChangePlanRequestBody, ChangePlanResponse, PlanResponse, SubscribeRequestBody, SubscriptionCheckoutResponse, SubscriptionResponse}, endpoints::payments::{deliver_fake_event, open_checkout}}, state::AppState};
+
+pub fn subscriptions_routes() -> Router<AppState> {
+    Router::new()
+        .route("/plans", get(list_plans))
+        .route("/checkout", post(subscribe))
+        .route("/current", get(get_subscription))
+        .route("/current/cancel", post(cancel_subscription))
+        .route("/current/resume", post(resume_subscription))
+        .route("/current/plan", put(change_plan))
+}
+
+
+fn no_subscription() -> LocalErr {
+    LocalErr::new(LocalErrKind::NoActiveSubscription, StatusCode::NOT_FOUND)
+}
+
+/// The subscription that hasn't ended, it must be managed by the configured provider
+async fn live_subscription(state: &AppState, user_id: uuid::Uuid) -> LocalResult<(subscription::Model, plan::Model)> {
+    let (subscription, plan) = state.subscriptions_service.get_current_subscription(user_id)
+        .await?
+        .filter(|(s, _)| s.status.is_live())
+        .ok_or_else(no_subscription)?;
+
+    if subscription.provider != state.payment_provider.name() {
+        return Err(LocalErr::new(LocalErrKind::PaymentProviderError, StatusCode::CONFLICT)
+            .with_msg(format!("the subscription is managed by the `{}` provider", subscription.provider)))
+    }
+    Ok((subscription, plan))
+}
+
+/// Applies the provider's answer right away, returning it with what the provider invoiced for the update
+async fn update_subscription(state: &AppState, user_id: uuid::Uuid, subscription: &subscription::Model, update: SubscriptionUpdate<'_>) -> LocalResult<(SubscriptionResponse, i64)> {
+    let updated = state.payment_provider.update_subscription(&subscription.provider_subscription_id, update).await?;
+    state.subscriptions_service.apply_subscription(None, &subscription.provider, &updated.subscription, None).await?;
+
+    let response = state.subscriptions_service.get_current_subscription(user_id)
+        .await?
+        .map(SubscriptionResponse::from)
+        .ok_or_else(no_subscription)?;
+    Ok((response, updated.invoiced_amount))
+}
+
+
+#[utoipa::path(get, path = "/api/subscriptions/plans", responses((status = 200, body = Vec<PlanResponse>)))]
+pub async fn list_plans(
+    State(AppState { subscriptions_service, .. }): State<AppState>,
+) -> LocalResult<Json<Vec<PlanResponse>>> {
+    let plans = subscriptions_service.get_active_plans().await?;
+    Ok(Json(plans.into_iter().map(PlanResponse::from).collect()))
+}
+
+
+/// Opens a checkout session at the payment provider, the trial is only offered to first-time subscribers.
+/// A coupon is taken off the first payment, which is then made right away instead of after the trial.
+/// A user has one checkout open at a time: the one for the same plan is returned again (as it was opened),
+/// one for another plan or coupon is expired and replaced (giving its coupon back)
+#[utoipa::path(post, path = "/api/subscriptions/checkout", responses((status = 200, body = SubscriptionCheckoutResponse)))]
+pub async fn subscribe(
+    State(AppState { users_service, subscriptions_service, coupons_service, payment_provider, .. }): State<AppState>,
+    VerifiedUserId(user_id): VerifiedUserId,
+    Json(body): Json<SubscribeRequestBody>,
+) -> LocalResult<Json<SubscriptionCheckoutResponse>> {
+    let plan = subscriptions_service.get_active_plan(&body.plan.0)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::UnknownPlan, StatusCode::NOT_FOUND))?;
+
+    let subscriptions = subscriptions_service.get_user_subscriptions(user_id).await?;
+    if subscriptions.iter().any(|(s, _)| s.status.is_live()) {
+        return Err(LocalErr::new(LocalErrKind::SubscriptionAlreadyActive, StatusCode::CONFLICT))
+    }
+    let pending = subscriptions_service.get_pending_checkout(user_id).await?;
+    if let Some(checkout) = pending.as_ref().filter(|c| c.plan_id == plan.id && body.coupon.is_none()) {
+        return Ok(Json(SubscriptionCheckoutResponse { checkout_url: checkout.checkout_url.clone() }))
+    }
+
+    let replaced_session = pending.as_ref().map(|c| c.provider_session_id.as_str());
+    let coupon = match &body.coupon {
+        Some(code) => Some(coupons_service.quote(&code.0, user_id, (CouponTargetType::Plan, plan.id), plan.amount, &plan.currency, replaced_session).await?),
+        None => None,
+    };
+
+    if let Some(checkout) = pending {
+        if checkout.provider != payment_provider.name() {
+            return Err(LocalErr::new(LocalErrKind::CheckoutPending, StatusCode::CONFLICT)
+                .with_msg(format!("a checkout is already open at the `{}` provider", checkout.provider)))
+        }
+        payment_provider.expire_checkout_session(&checkout.provider_session_id).await?;
+        subscriptions_service.expire_checkout(&checkout).await?;
+    }
+
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Id.eq(user_id)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    let now = Utc::now();
+    let redemption = coupon.as_ref().map(|(coupon, discount)| coupon_redemption::Model {
+        id: uuid::Uuid::new_v4(),
+        coupon_id: coupon.id,
+        user_id,
+        order_id: None,
+        provider: None,
+        provider_session_id: None,
+        discount_amount: *discount,
+        creation_date: now.into(),
+        release_date: None,
+    });
+    let session = payment_provider.create_subscription_checkout(SubscriptionCheckout {
+        user_id,
+        plan_id: plan.id,
+        price_id: plan.provider_price_id.as_deref(),
+        amount: plan.amount,
+        currency: &plan.currency,
+        interval: plan.billing_interval,
+        trial_days: if subscriptions.is_empty() && coupon.is_none() { plan.trial_days.max(0) as u32 } else { 0 },
+        discount: coupon.as_ref().map_or(0, |(_, discount)| *discount),
+        description: &plan.name,
+        customer_email: &user.email,
+        success_url: format!("{}/subscription", CONFIG.frontend_url),
+        cancel_url: format!("{}/subscription?canceled=true", CONFIG.frontend_url),
+    });
+    let checkout_url = open_checkout(&coupons_service, &*payment_provider, redemption, session, async |session, redemption_id| {
+        subscriptions_service.create_checkout(subscription_checkout::Model {
+            id: uuid::Uuid::new_v4(),
+            user_id,
+            plan_id: plan.id,
+            provider: payment_provider.name().to_string(),
+            provider_session_id: session.id.clone(),
+            checkout_url: session.url.clone(),
+            status: PaymentStatus::Pending,
+            creation_date: now.into(),
+            update_date: now.into(),
+        }, redemption_id).await
+    }).await?;
+    Ok(Json(SubscriptionCheckoutResponse { checkout_url }))
+}
+
+
+/// The subscription that hasn't ended, or else the latest one
+#[utoipa::path(get, path = "/api/subscriptions/current", responses((status = 200, body = SubscriptionResponse)))]
+pub async fn get_subscription(
+    State(AppState { subscriptions_service, .. }): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<SubscriptionResponse>> {
+    subscriptions_service.get_current_subscription(user_id)
+        .await?
+        .map(|s| Json(s.into()))
+        .ok_or_else(no_subscription)
+}
+
+
+/// The subscription stays usable until the end of the period, and isn't renewed
+#[utoipa::path(post, path = "/api/subscriptions/current/cancel", responses((status = 200, body = SubscriptionResponse)))]
+pub async fn cancel_subscription(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<SubscriptionResponse>> {
+    let (subscription, _) = live_subscription(&state, user_id).await?;
+    Ok(Json(update_subscription(&state, user_id, &subscription, SubscriptionUpdate::CancelAtPeriodEnd(true)).await?.0))
+}
+
+
+/// Undoes a cancellation before the end of the period
+#[utoipa::path(post, path = "/api/subscriptions/current/resume", responses((status = 200, body = SubscriptionResponse)))]
+pub async fn resume_subscription(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+) -> LocalResult<Json<SubscriptionResponse>> {
+    let (subscription, _) = live_subscription(&state, user_id).await?;
+    Ok(Json(update_subscription(&state, user_id, &subscription, SubscriptionUpdate::CancelAtPeriodEnd(false)).await?.0))
+}
+
+
+/// Switches plan right away, charging (or crediting) the prorated difference for the rest of the period.
+/// A plan with another interval starts a new period
+#[utoipa::path(put, path = "/api/subscriptions/current/plan", responses((status = 200, body = ChangePlanResponse)))]
+pub async fn change_plan(
+    State(state): State<AppState>,
+    UserId(user_id): UserId,
+    Json(body): Json<ChangePlanRequestBody>,
+) -> LocalResult<Json<ChangePlanResponse>> {
+    let (subscription, current_plan) = live_subscription(&state, user_id).await?;
+    let plan = state.subscriptions_service.get_active_plan(&body.plan.0)
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::UnknownPlan, StatusCode::NOT_FOUND))?;
+
+    let invalid = |msg: &str| LocalErr::new(LocalErrKind::InvalidPlanChange, StatusCode::BAD_REQUEST).with_msg(msg);
+    if plan.id == current_plan.id {
+        return Err(invalid("already on this plan"))
+    }
+    if plan.currency != current_plan.currency {
+        return Err(invalid("plans are billed in different currencies"))
+    }
+    if subscription.status == SubscriptionStatus::PastDue {
+        return Err(invalid("the last renewal must be paid first"))
+    }
+
+    // the provider prorates the change and invoices it right away (nothing during the trial)
+    let (updated, proration_amount) = update_subscription(&state, user_id, &subscription, SubscriptionUpdate::ChangePlan {
+        plan_id: plan.id,
+        price_id: plan.provider_price_id.as_deref(),
+        amount: plan.amount,
+        interval: plan.billing_interval,
+    }).await?;
+
+    // the fake provider charges it like a renewal
+    if let Some(fake) = state.fake_payments.clone().filter(|_| proration_amount > 0)
+        && let Some(fake_subscription) = fake.get_subscription(&subscription.provider_subscription_id)
+    {
+        deliver_fake_event(&state, fake.invoice_event(&fake_subscription, proration_amount)).await?;
+    }
+
+    Ok(Json(ChangePlanResponse { subscription: updated, proration_amount }))
+}
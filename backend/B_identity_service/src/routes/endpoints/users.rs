@@ -0,0 +1,22 @@
+use axum::{Router, extract::State, http::StatusCode, routing::get};
+use sea_orm::{ColumnTrait, Condition};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult}, extract::{Json, Path}, models::entity::user, routes::dto::users::PublicUserProfileResponse, state::AppState};
+
+pub fn users_routes() -> Router<AppState> {
+    Router::new()
+        .route("/{username}", get(get_public_profile))
+}
+
+
+#[utoipa::path(get, path = "/api/users/{username}", responses((status = 200, body = PublicUserProfileResponse)))]
+pub async fn get_public_profile(
+    State(AppState { users_service, .. }): State<AppState>,
+    Path(username): Path<String>,
+) -> LocalResult<Json<PublicUserProfileResponse>> {
+    let user = users_service.get_user_by(Condition::all().add(user::Column::Username.eq(username)))
+        .await?
+        .ok_or(LocalErr::new(LocalErrKind::NotFound, StatusCode::NOT_FOUND))?;
+
+    Ok(Json(user.into()))
+}
\ No newline at end of file
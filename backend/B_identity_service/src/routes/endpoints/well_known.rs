@@ -0,0 +1,19 @@
+use axum::{Router, extract::State, http::header, response::IntoResponse, routing::get};
+
+use crate::{extract::Json, state::AppState};
+
+pub fn well_known_routes() -> Router<AppState> {
+    Router::new()
+        .route("/jwks.json", get(get_jwks))
+}
+
+
+#[utoipa::path(get, path = "/.well-known/jwks.json", responses((status = 200, description = "JWK set with the access token public keys", body = Object)))]
+pub async fn get_jwks(
+    State(AppState { jwt_service, .. }): State<AppState>,
+) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(jwt_service.jwks()),
+    )
+}
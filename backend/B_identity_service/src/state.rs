@@ -1,21 +1,75 @@
+use std::sync::Arc;
+
 use sea_orm::DatabaseConnection;
 
-use crate::{db, models::repository::user::UserRepository, utils::jwt::JwtRepository};
+use crate::{config::{CONFIG, LoginThrottleBackend, PaymentProviderKind}, db, events::EventPublisher, mailer::{Mailer, OutboxMailer}, models::repository::{audit::AuditRepository, coupon::CouponRepository, invoice::InvoiceRepository, refund::RefundRepository, entitlement::EntitlementRepository, mfa::MfaRepository, payment::PaymentRepository, payment_webhook::PaymentWebhookRepository, processed_message::ProcessedMessageRepository, subscription::SubscriptionRepository, role::RoleRepository, session::SessionRepository, user::UserRepository, user_token::UserTokenRepository}, payments::{FakePaymentProvider, PaymentProvider, StripePaymentProvider}, storage::{LocalStorage, Storage}, throttle::{AttemptStore, LoginGuard, MemoryAttemptStore, PostgresAttemptStore}, utils::jwt::JwtRepository};
 
 #[derive(Clone)]
 pub struct AppState {
     pg: DatabaseConnection,
     pub users_service: UserRepository,
+    pub sessions_service: SessionRepository,
+    pub user_tokens_service: UserTokenRepository,
+    pub mfa_service: MfaRepository,
+    pub roles_service: RoleRepository,
+    pub audit_service: AuditRepository,
+    pub processed_messages_service: ProcessedMessageRepository,
+    pub payments_service: PaymentRepository,
+    pub payment_webhooks_service: PaymentWebhookRepository,
+    pub subscriptions_service: SubscriptionRepository,
+    pub entitlements_service: EntitlementRepository,
+    pub coupons_service: CouponRepository,
+    pub refunds_service: RefundRepository,
+    pub invoices_service: InvoiceRepository,
     pub jwt_service: JwtRepository,
+    pub publisher: EventPublisher,
+    pub mailer: Arc<dyn Mailer>,
+    pub storage: Arc<dyn Storage>,
+    pub login_guard: LoginGuard,
+    pub payment_provider: Arc<dyn PaymentProvider>,
+    pub fake_payments: Option<Arc<FakePaymentProvider>>, // same as `payment_provider` with `PAYMENT_PROVIDER=fake`
 }
 
 impl AppState{
     pub async fn new() -> anyhow::Result<Self> {
         let pg = db::postgres::connect_db().await?;
+
+        let attempts: Arc<dyn AttemptStore> = match CONFIG.login_throttle_backend {
+            LoginThrottleBackend::Memory => Arc::new(MemoryAttemptStore::default()),
+            LoginThrottleBackend::Postgres => Arc::new(PostgresAttemptStore::new(pg.clone())),
+        };
+
+        let fake_payments = match CONFIG.payment_provider {
+            PaymentProviderKind::Fake => Some(Arc::new(FakePaymentProvider::default())),
+            PaymentProviderKind::Stripe => None,
+        };
+        let payment_provider: Arc<dyn PaymentProvider> = match &fake_payments {
+            Some(fake) => fake.clone(),
+            None => Arc::new(StripePaymentProvider::new(&CONFIG.stripe_api_url, CONFIG.stripe_secret_key.as_deref())?),
+        };
         
         Ok(Self {
             users_service: UserRepository::new(pg.clone()),
-            jwt_service: JwtRepository,
+            sessions_service: SessionRepository::new(pg.clone()),
+            user_tokens_service: UserTokenRepository::new(pg.clone()),
+            mfa_service: MfaRepository::new(pg.clone()),
+            roles_service: RoleRepository::new(pg.clone()),
+            audit_service: AuditRepository::new(pg.clone()),
+            processed_messages_service: ProcessedMessageRepository::new(pg.clone()),
+            payments_service: PaymentRepository::new(pg.clone()),
+            payment_webhooks_service: PaymentWebhookRepository::new(pg.clone()),
+            subscriptions_service: SubscriptionRepository::new(pg.clone()),
+            entitlements_service: EntitlementRepository::new(pg.clone()),
+            coupons_service: CouponRepository::new(pg.clone()),
+            refunds_service: RefundRepository::new(pg.clone()),
+            invoices_service: InvoiceRepository::new(pg.clone()),
+            jwt_service: JwtRepository::new()?,
+            publisher: EventPublisher::new(pg.clone()),
+            mailer: Arc::new(OutboxMailer::new(&CONFIG.mail_outbox_dir)?),
+            storage: Arc::new(LocalStorage::new(&CONFIG.storage_local_dir)?),
+            login_guard: LoginGuard::new(attempts),
+            payment_provider,
+            fake_payments,
             pg,
         })
     }
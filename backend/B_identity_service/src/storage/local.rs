@@ -0,0 +1,86 @@
+use std::path::{Component, Path, PathBuf};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, storage::Storage};
+
+/// Stores files under `root`, which is also served at `/media`
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    fn path(&self, key: &str) -> LocalResult<PathBuf> {
+        // keys are generated by us, but never let one escape `root` (or be `root` itself)
+        let valid = !key.is_empty() && Path::new(key).components().all(|c| matches!(c, Component::Normal(_)));
+        if !valid {
+            return Err(LocalErr::new(LocalErrKind::StoreImage, StatusCode::INTERNAL_SERVER_ERROR))
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>, _content_type: &str) -> LocalResult<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err_print(|_| LocalErr::new(LocalErrKind::StoreImage, StatusCode::INTERNAL_SERVER_ERROR))?;
+        }
+
+        tokio::fs::write(&path, data)
+            .await
+            .map_err_print(|_| LocalErr::new(LocalErrKind::StoreImage, StatusCode::INTERNAL_SERVER_ERROR))
+    }
+
+    async fn delete(&self, key: &str) -> LocalResult<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).map_err_print(|_| LocalErr::new(LocalErrKind::StoreImage, StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage() -> LocalStorage {
+        LocalStorage::new(std::env::temp_dir().join(format!("storage-test-{}", uuid::Uuid::new_v4()))).unwrap()
+    }
+
+    #[test]
+    fn keys_stay_under_the_root() {
+        let storage = storage();
+        assert_eq!(storage.path("avatars/abc_lg.jpg").unwrap(), storage.root.join("avatars/abc_lg.jpg"));
+
+        for key in ["../abc.jpg", "avatars/../../abc.jpg", "/etc/passwd", ""] {
+            assert!(storage.path(key).is_err(), "`{}` should be rejected", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn files_are_written_and_deleted() {
+        let storage = storage();
+        storage.put("avatars/abc_lg.jpg", vec![1, 2, 3], "image/jpeg").await.unwrap();
+        assert_eq!(std::fs::read(storage.root.join("avatars/abc_lg.jpg")).unwrap(), vec![1, 2, 3]);
+
+        storage.delete("avatars/abc_lg.jpg").await.unwrap();
+        assert!(!storage.root.join("avatars/abc_lg.jpg").exists());
+        // already gone
+        storage.delete("avatars/abc_lg.jpg").await.unwrap();
+        assert!(storage.put("../abc.jpg", vec![1], "image/jpeg").await.is_err());
+
+        let _ = std::fs::remove_dir_all(&storage.root);
+    }
+}
@@ -0,0 +1,14 @@
+use async_trait::async_trait;
+
+use crate::error::LocalResult;
+
+mod local;
+
+pub use local::LocalStorage;
+
+/// Where uploaded files are kept, addressed by key (e.g. `avatars/<uuid>_lg.jpg`)
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> LocalResult<()>;
+    async fn delete(&self, key: &str) -> LocalResult<()>;
+}
@@ -0,0 +1,55 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{error::LocalResult, throttle::{AttemptStore, Attempts, ThrottlePolicy}};
+
+const MAX_ENTRIES: usize = 100_000;
+
+struct Entry {
+    attempts: Attempts,
+    last_failure: DateTime<Utc>,
+}
+
+/// Per instance store, enough for a single server
+#[derive(Default)]
+pub struct MemoryAttemptStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+#[async_trait]
+impl AttemptStore for MemoryAttemptStore {
+    async fn get(&self, key: &str) -> LocalResult<Option<Attempts>> {
+        let entries = self.entries.lock().unwrap();
+        Ok(entries.get(key).map(|e| e.attempts))
+    }
+
+    async fn record_failure(&self, key: &str, policy: &ThrottlePolicy) -> LocalResult<Attempts> {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, e| e.last_failure + policy.max_lockout > now);
+        }
+
+        let entry = entries.entry(key.to_string()).or_insert(Entry {
+            attempts: Attempts { failures: 0, locked_until: None },
+            last_failure: now,
+        });
+
+        if entry.last_failure + policy.max_lockout < now {
+            entry.attempts.failures = 0;
+        }
+        entry.attempts.failures += 1;
+        entry.attempts.locked_until = policy.lockout(entry.attempts.failures).map(|l| now + l);
+        entry.last_failure = now;
+
+        Ok(entry.attempts)
+    }
+
+    async fn reset(&self, key: &str) -> LocalResult<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
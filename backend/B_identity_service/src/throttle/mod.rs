@@ -0,0 +1,210 @@
+use std::{net::IpAddr, sync::Arc};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult}};
+
+mod memory;
+mod postgres;
+
+pub use memory::MemoryAttemptStore;
+pub use postgres::PostgresAttemptStore;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Attempts {
+    pub failures: u32,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// How many failures are allowed and how long the lockouts after them are
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottlePolicy {
+    pub free_attempts: u32,
+    pub base_lockout: Duration,
+    pub max_lockout: Duration, // failures older than this are forgotten
+}
+
+impl ThrottlePolicy {
+    /// Exponential backoff: `base_lockout` once the free attempts are spent, doubled on every further failure
+    pub fn lockout(&self, failures: u32) -> Option<Duration> {
+        let over = failures.checked_sub(self.free_attempts)?;
+        let lockout = self.base_lockout * 2i32.saturating_pow(over.min(30));
+        Some(lockout.min(self.max_lockout))
+    }
+}
+
+/// Keeps the failed attempts per key, shared by every instance or not depending on the backend
+#[async_trait]
+pub trait AttemptStore: Send + Sync {
+    async fn get(&self, key: &str) -> LocalResult<Option<Attempts>>;
+    /// Adds a failure (starting over if the last one is older than `max_lockout`) and applies the lockout
+    async fn record_failure(&self, key: &str, policy: &ThrottlePolicy) -> LocalResult<Attempts>;
+    async fn reset(&self, key: &str) -> LocalResult<()>;
+}
+
+
+/// Whose password is tried: attempts on a user are counted together whether they typed their username or email
+#[derive(Debug, Clone, Copy)]
+pub enum LoginAccount<'a> {
+    Known(uuid::Uuid),
+    Unknown(&'a str), // the credential as typed
+}
+
+/// Throttles login attempts per account and per ip
+#[derive(Clone)]
+pub struct LoginGuard {
+    store: Arc<dyn AttemptStore>,
+    account_policy: ThrottlePolicy,
+    ip_policy: ThrottlePolicy,
+}
+
+impl LoginGuard {
+    pub fn new(store: Arc<dyn AttemptStore>) -> Self {
+        let policy = |free_attempts| ThrottlePolicy {
+            free_attempts,
+            base_lockout: CONFIG.login_lockout_base_time,
+            max_lockout: CONFIG.login_lockout_max_time,
+        };
+
+        Self {
+            store,
+            account_policy: policy(CONFIG.login_account_free_attempts),
+            ip_policy: policy(CONFIG.login_ip_free_attempts),
+        }
+    }
+
+    fn keys(ip: IpAddr, account: LoginAccount) -> [String; 2] {
+        let account_key = match account {
+            LoginAccount::Known(user_id) => format!("user:{}", user_id),
+            LoginAccount::Unknown(credential) => format!("account:{}", credential.to_lowercase()),
+        };
+        [account_key, format!("ip:{}", ip)]
+    }
+
+    /// 2FA codes are counted apart from passwords: the password was right, and a success resets the account key
+    fn mfa_keys(ip: IpAddr, user_id: uuid::Uuid) -> [String; 2] {
+        [format!("mfa:{}", user_id), format!("ip:{}", ip)]
+    }
+
+    /// Fails with `TooManyLoginAttempts` if either the account or the ip is locked.
+    /// Must be called before checking the password so locked attempts don't cost a bcrypt verify.
+    pub async fn check(&self, ip: IpAddr, account: LoginAccount<'_>) -> LocalResult<()> {
+        self.check_keys(Self::keys(ip, account)).await
+    }
+
+    pub async fn failure(&self, ip: IpAddr, account: LoginAccount<'_>) -> LocalResult<()> {
+        self.record_failure(Self::keys(ip, account)).await
+    }
+
+    /// Only the account is forgiven, an ip trying many accounts keeps its count
+    pub async fn success(&self, ip: IpAddr, account: LoginAccount<'_>) -> LocalResult<()> {
+        let [account_key, _] = Self::keys(ip, account);
+        self.store.reset(&account_key).await
+    }
+
+    /// Same as `check`, for the 2FA code of the user
+    pub async fn check_mfa(&self, ip: IpAddr, user_id: uuid::Uuid) -> LocalResult<()> {
+        self.check_keys(Self::mfa_keys(ip, user_id)).await
+    }
+
+    pub async fn mfa_failure(&self, ip: IpAddr, user_id: uuid::Uuid) -> LocalResult<()> {
+        self.record_failure(Self::mfa_keys(ip, user_id)).await
+    }
+
+    pub async fn mfa_success(&self, ip: IpAddr, user_id: uuid::Uuid) -> LocalResult<()> {
+        let [user_key, _] = Self::mfa_keys(ip, user_id);
+        self.store.reset(&user_key).await
+    }
+
+    async fn check_keys(&self, keys: [String; 2]) -> LocalResult<()> {
+        let now = Utc::now();
+        let mut locked_until = None;
+
+        for key in keys {
+            let until = self.store.get(&key)
+                .await?
+                .and_then(|a| a.locked_until)
+                .filter(|until| *until > now);
+            locked_until = locked_until.max(until);
+        }
+
+        match locked_until {
+            Some(until) => {
+                let seconds = (until - now).num_seconds() as u64 + 1;
+                Err(LocalErr::new(LocalErrKind::TooManyLoginAttempts, StatusCode::TOO_MANY_REQUESTS)
+                    .with_msg(format!("too many failed attempts, try again in {} seconds", seconds))
+                    .with_retry_after(seconds))
+            },
+            None => Ok(())
+        }
+    }
+
+    /// The first key is the account (or user), the second the ip
+    async fn record_failure(&self, [account_key, ip_key]: [String; 2]) -> LocalResult<()> {
+        self.store.record_failure(&account_key, &self.account_policy).await?;
+        self.store.record_failure(&ip_key, &self.ip_policy).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ThrottlePolicy {
+        ThrottlePolicy {
+            free_attempts: 3,
+            base_lockout: Duration::seconds(30),
+            max_lockout: Duration::seconds(300),
+        }
+    }
+
+    #[test]
+    fn free_attempts_are_not_locked() {
+        assert_eq!(policy().lockout(0), None);
+        assert_eq!(policy().lockout(2), None);
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_max() {
+        let policy = policy();
+        assert_eq!(policy.lockout(3), Some(Duration::seconds(30)));
+        assert_eq!(policy.lockout(4), Some(Duration::seconds(60)));
+        assert_eq!(policy.lockout(6), Some(Duration::seconds(240)));
+        assert_eq!(policy.lockout(7), Some(Duration::seconds(300)));
+        assert_eq!(policy.lockout(u32::MAX), Some(Duration::seconds(300)));
+    }
+
+    #[test]
+    fn known_accounts_are_counted_by_user() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let user_id = uuid::Uuid::new_v4();
+
+        let [account_key, ip_key] = LoginGuard::keys(ip, LoginAccount::Known(user_id));
+        assert_eq!(account_key, format!("user:{}", user_id));
+        assert_eq!(ip_key, "ip:10.0.0.1");
+
+        let [username, _] = LoginGuard::keys(ip, LoginAccount::Unknown("Alice"));
+        let [email, _] = LoginGuard::keys(ip, LoginAccount::Unknown("alice@example.com"));
+        assert_eq!(username, "account:alice");
+        assert_ne!(username, email);
+    }
+
+    #[tokio::test]
+    async fn memory_store_locks_and_resets() {
+        let store = MemoryAttemptStore::default();
+        let policy = policy();
+
+        for _ in 0..2 {
+            assert!(store.record_failure("ip:1", &policy).await.unwrap().locked_until.is_none());
+        }
+        let attempts = store.record_failure("ip:1", &policy).await.unwrap();
+        assert_eq!(attempts.failures, 3);
+        assert!(attempts.locked_until.is_some_and(|until| until > Utc::now()));
+
+        store.reset("ip:1").await.unwrap();
+        assert!(store.get("ip:1").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,79 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, ConnectionTrait, DatabaseConnection, DbBackend, EntityTrait, Statement};
+
+use crate::{error::{LocalErr, LocalResult, MapErrPrint}, models::entity::login_attempt, throttle::{AttemptStore, Attempts, ThrottlePolicy}};
+
+/// Store shared by every instance through the `login_attempts` table
+pub struct PostgresAttemptStore {
+    db: DatabaseConnection,
+}
+
+impl PostgresAttemptStore {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl AttemptStore for PostgresAttemptStore {
+    async fn get(&self, key: &str) -> LocalResult<Option<Attempts>> {
+        let found = login_attempt::Entity::find_by_id(key)
+            .one(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        Ok(found.map(|a| Attempts {
+            failures: a.failures as u32,
+            locked_until: a.locked_until.map(|l| l.to_utc()),
+        }))
+    }
+
+    async fn record_failure(&self, key: &str, policy: &ThrottlePolicy) -> LocalResult<Attempts> {
+        // a single upsert so concurrent failures on different instances all count
+        let statement = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+            INSERT INTO login_attempts (key, failures, last_failure) VALUES ($1, 1, now())
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN login_attempts.last_failure < now() - make_interval(secs => $2) THEN 1
+                    ELSE login_attempts.failures + 1
+                END,
+                last_failure = now()
+            RETURNING failures
+            "#,
+            [key.into(), (policy.max_lockout.num_seconds() as f64).into()],
+        );
+
+        let failures: i32 = self.db.query_one(statement)
+            .await
+            .map_err_print(LocalErr::from)?
+            .map(|row| row.try_get("", "failures"))
+            .transpose()
+            .map_err_print(LocalErr::from)?
+            .unwrap_or(1);
+
+        let failures = failures as u32;
+        let locked_until = policy.lockout(failures).map(|l| Utc::now() + l);
+
+        login_attempt::Entity::update(login_attempt::ActiveModel {
+            key: Set(key.to_string()),
+            locked_until: Set(locked_until.map(Into::into)),
+            ..Default::default()
+        })
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)?;
+
+        Ok(Attempts { failures, locked_until })
+    }
+
+    async fn reset(&self, key: &str) -> LocalResult<()> {
+        login_attempt::Entity::delete_by_id(key)
+            .exec(&self.db)
+            .await
+            .map_err_print(LocalErr::from)
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,164 @@
+use std::{io::Cursor, sync::Arc};
+
+use axum::http::StatusCode;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits, codecs::jpeg::JpegEncoder, imageops::FilterType};
+
+use crate::{error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, storage::Storage};
+
+pub const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+const MAX_IMAGE_SIDE: u32 = 8000;
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ImageKind {
+    Avatar,
+    Banner,
+}
+
+/// A fixed size every upload is resized (and cropped) to
+pub struct ImageVariant {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ImageKind {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::Avatar => "avatars",
+            Self::Banner => "banners",
+        }
+    }
+
+    /// Largest first, the uploaded image must be at least as big as the first one
+    pub fn variants(self) -> &'static [ImageVariant] {
+        match self {
+            Self::Avatar => &[
+                ImageVariant { name: "lg", width: 256, height: 256 },
+                ImageVariant { name: "sm", width: 64, height: 64 },
+            ],
+            Self::Banner => &[
+                ImageVariant { name: "lg", width: 1500, height: 500 },
+                ImageVariant { name: "sm", width: 600, height: 200 },
+            ],
+        }
+    }
+
+    /// Storage key of one variant of the image stored as `key`
+    pub fn variant_key(key: &str, variant: &ImageVariant) -> String {
+        format!("{}_{}.jpg", key, variant.name)
+    }
+}
+
+/// Checks the real format and size of `data` and re-encodes it to every variant of `kind`
+/// (in the same order as `ImageKind::variants`). CPU bound, run it in a blocking task.
+pub fn process_image(kind: ImageKind, data: &[u8]) -> LocalResult<Vec<Vec<u8>>> {
+    let invalid = || LocalErr::new(LocalErrKind::InvalidImageFormat, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // the client provided content type is not trusted
+    let format = image::guess_format(data).map_err(|_| invalid())?;
+    if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Gif) {
+        return Err(invalid().with_msg("only png, jpeg, webp and gif images are allowed"))
+    }
+
+    let (width, height) = ImageReader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(|_| invalid())?;
+
+    if width > MAX_IMAGE_SIDE || height > MAX_IMAGE_SIDE {
+        return Err(LocalErr::new(LocalErrKind::ImageTooLarge, StatusCode::PAYLOAD_TOO_LARGE)
+            .with_msg(format!("images can't be bigger than {0}x{0}", MAX_IMAGE_SIDE)))
+    }
+
+    let min = &kind.variants()[0];
+    if width < min.width || height < min.height {
+        return Err(LocalErr::new(LocalErrKind::ImageResolutionTooLow, StatusCode::BAD_REQUEST)
+            .with_msg(format!("image must be at least {}x{}", min.width, min.height)))
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_IMAGE_SIDE);
+
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|_| invalid())?;
+
+    kind.variants()
+        .iter()
+        .map(|v| encode_jpeg(image.resize_to_fill(v.width, v.height, FilterType::Lanczos3)))
+        .collect()
+}
+
+fn encode_jpeg(image: DynamicImage) -> LocalResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY);
+
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_with_encoder(encoder)
+        .map_err_print(|_| LocalErr::new(LocalErrKind::StoreImage, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok(out)
+}
+
+
+/// Removes every variant of a stored image. Failures are only logged, the user already stopped using it.
+pub async fn delete_image(storage: &Arc<dyn Storage>, kind: ImageKind, key: &str) {
+    for variant in kind.variants() {
+        let _ = storage.delete(&ImageKind::variant_key(key, variant)).await;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut out, ImageFormat::Png)
+            .unwrap();
+        out.into_inner()
+    }
+
+    fn error(result: LocalResult<Vec<Vec<u8>>>) -> LocalErrKind {
+        result.expect_err("the image should be rejected").error
+    }
+
+    #[test]
+    fn the_format_is_sniffed_from_the_content() {
+        // uploaded as `image/png`, but a bmp
+        let mut bmp = b"BM".to_vec();
+        bmp.resize(64, 0);
+        assert!(matches!(error(process_image(ImageKind::Avatar, &bmp)), LocalErrKind::InvalidImageFormat));
+
+        let svg = b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+        assert!(matches!(error(process_image(ImageKind::Avatar, svg)), LocalErrKind::InvalidImageFormat));
+    }
+
+    #[test]
+    fn images_smaller_than_the_largest_variant_are_rejected() {
+        assert!(matches!(error(process_image(ImageKind::Avatar, &png(255, 300))), LocalErrKind::ImageResolutionTooLow));
+        assert!(matches!(error(process_image(ImageKind::Banner, &png(1500, 499))), LocalErrKind::ImageResolutionTooLow));
+        assert!(process_image(ImageKind::Avatar, &png(256, 256)).is_ok());
+    }
+
+    #[test]
+    fn images_over_the_maximum_side_are_rejected() {
+        assert!(matches!(error(process_image(ImageKind::Banner, &png(MAX_IMAGE_SIDE + 1, 500))), LocalErrKind::ImageTooLarge));
+        assert!(matches!(error(process_image(ImageKind::Avatar, &png(256, MAX_IMAGE_SIDE + 1))), LocalErrKind::ImageTooLarge));
+    }
+
+    #[test]
+    fn every_variant_has_its_exact_size() {
+        for (kind, (width, height)) in [(ImageKind::Avatar, (300, 400)), (ImageKind::Banner, (1600, 900))] {
+            let encoded = process_image(kind, &png(width, height)).unwrap();
+            assert_eq!(encoded.len(), kind.variants().len());
+
+            for (variant, data) in kind.variants().iter().zip(encoded) {
+                assert_eq!(image::guess_format(&data).unwrap(), ImageFormat::Jpeg);
+                let decoded = image::load_from_memory(&data).unwrap();
+                assert_eq!((decoded.width(), decoded.height()), (variant.width, variant.height), "{:?} {}", kind, variant.name);
+            }
+        }
+    }
+}
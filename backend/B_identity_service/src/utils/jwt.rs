@@ -1,72 +1,172 @@
 use axum::http::StatusCode;
 use axum_extra::extract::cookie::{Cookie, SameSite};
 use chrono::Utc;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
-use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+use jsonwebtoken::{Header, Validation, decode, decode_header, encode, jwk::JwkSet};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use time::OffsetDateTime;
 
-use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}};
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}, utils::jwt_keys::Keyring};
 
 #[derive(Serialize, Deserialize)]
 pub struct JwtClaims {
-    exp: usize, // expiration time
-    iat: usize, // issued at
+    pub exp: usize, // expiration time
+    pub iat: usize, // issued at
+
+    pub jti: uuid::Uuid, // session id
+    pub user_id: uuid::Uuid,
+    pub version: uuid::Uuid, // user version (for password/mail/role changes)
+    #[serde(default)]
+    pub roles: Vec<String>, // only in access tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub courses: Option<Vec<uuid::Uuid>>, // access tokens with `JWT_ACCESS_ENTITLEMENTS`, missing if there are too many
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entitlements_version: Option<uuid::Uuid>, // the user's when `courses` was read, with `courses`
+}
+
+/// Proof that the password was right, exchanged for real tokens once the 2FA code is.
+/// Signed with the internal refresh keyring, never with a published access key
+const MFA_AUDIENCE: &str = "mfa";
+
+#[derive(Serialize, Deserialize)]
+pub struct MfaPendingClaims {
+    exp: usize,
+    iat: usize,
+    aud: String,
 
+    pub jti: uuid::Uuid, // pending login id, the token is only accepted once
     pub user_id: uuid::Uuid,
-    pub version: uuid::Uuid, // user version (for password/mail changes)
+    pub version: uuid::Uuid,
 }
 
 
 #[derive(Clone)]
-pub struct JwtRepository;
+pub struct JwtRepository {
+    access: Arc<RwLock<Keyring>>,
+    refresh: Arc<RwLock<Keyring>>, // refresh and mfa tokens, never published
+}
+
+/// Polls the manifest at `path` and swaps the keyring when it changes.
+/// An invalid manifest is reported and the current keyring is kept
+async fn watch(keyring: Arc<RwLock<Keyring>>, path: Option<String>) {
+    let Some(path) = path else { return };
+    let modified = || std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+
+    let mut last_modified = modified();
+    let mut interval = tokio::time::interval(CONFIG.jwt_access_keyring_reload_time);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let current = modified();
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+
+        match Keyring::from_file(&path) {
+            Ok(new) => {
+                *keyring.write().unwrap() = new;
+                println!("jwt keyring reloaded from {}", path);
+            },
+            Err(e) => eprintln!("jwt keyring reload failed, keeping the current one: {:#}", e),
+        }
+    }
+}
+
+fn sign<T: Serialize>(keyring: &RwLock<Keyring>, claims: &T) -> LocalResult<String> {
+    let key = keyring.read().unwrap().active();
+    let encoding = key.encoding.as_ref()
+        .ok_or(LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let mut header = Header::new(key.alg);
+    header.kid = Some(key.kid.clone());
+
+    encode(&header, claims, encoding)
+        .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+/// Picks the verification key by the token `kid`, so tokens signed by retired keys stay valid
+fn verify<T: DeserializeOwned>(keyring: &RwLock<Keyring>, token: &str, audience: Option<&str>) -> Option<T> {
+    let header = decode_header(token).ok()?;
+    let key = keyring.read().unwrap().get(header.kid.as_deref())?;
+
+    let mut validation = Validation::new(key.alg);
+    if let Some(aud) = audience {
+        validation.set_audience(&[aud]);
+    }
+
+    decode::<T>(token, &key.decoding, &validation).ok().map(|d| d.claims)
+}
+
 impl JwtRepository {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            access: Arc::new(RwLock::new(Keyring::access_from_config()?)),
+            refresh: Arc::new(RwLock::new(Keyring::refresh_from_config()?)),
+        })
+    }
+
+    /// Reloads `JWT_ACCESS_KEYRING_FILE` and `JWT_REFRESH_KEYRING_FILE` when they change
+    pub async fn watch_keyring(self) {
+        tokio::join!(
+            watch(self.access, CONFIG.jwt_access_keyring_file.clone()),
+            watch(self.refresh, CONFIG.jwt_refresh_keyring_file.clone()),
+        );
+    }
+
+    /// Public keys that verify access tokens, HS keys are never published
+    pub fn jwks(&self) -> JwkSet {
+        self.access.read().unwrap().jwks()
+    }
+
     pub fn validate_access_token(&self, token: &str) -> LocalResult<JwtClaims> {
-        let key = DecodingKey::from_secret(CONFIG.jwt_access_secret.as_bytes());
-        match decode::<JwtClaims>(token, &key, &Validation::default()) {
-            Ok(decoded) => Ok(decoded.claims),
-            Err(_) => Err(LocalErr::new(LocalErrKind::InvalidAccessToken, StatusCode::UNAUTHORIZED))
-        }
+        verify(&self.access, token, None)
+            .ok_or(LocalErr::new(LocalErrKind::InvalidAccessToken, StatusCode::UNAUTHORIZED))
     }
     
     pub fn validate_refresh_token(&self, token: &str) -> LocalResult<JwtClaims> {
-        let key = DecodingKey::from_secret(CONFIG.jwt_refresh_secret.as_bytes());
-        match decode::<JwtClaims>(token, &key, &Validation::default()) {
-            Ok(decoded) => Ok(decoded.claims),
-            Err(_) => Err(LocalErr::new(LocalErrKind::InvalidRefreshToken, StatusCode::UNAUTHORIZED))
-        }
+        verify(&self.refresh, token, None)
+            .ok_or(LocalErr::new(LocalErrKind::InvalidRefreshToken, StatusCode::UNAUTHORIZED))
     }
 
-    pub fn generate_access_token(&self, user_id: uuid::Uuid, version: uuid::Uuid) -> LocalResult<String> {
+    /// `courses` come with the entitlements version they were read at
+    pub fn generate_access_token(&self, jti: uuid::Uuid, user_id: uuid::Uuid, version: uuid::Uuid, roles: Vec<String>, courses: Option<(Vec<uuid::Uuid>, uuid::Uuid)>) -> LocalResult<String> {
         let iat = Utc::now();
         let exp = (iat + CONFIG.jwt_access_exp_time).timestamp() as usize;
 
         let claims = JwtClaims {
             exp, 
             iat: iat.timestamp() as usize,
+            jti,
             user_id,
-            version
+            version,
+            roles,
+            entitlements_version: courses.as_ref().map(|(_, version)| *version),
+            courses: courses.map(|(courses, _)| courses),
         };
 
-        let key = EncodingKey::from_secret(CONFIG.jwt_access_secret.as_bytes());        
-
-        encode(&Header::default(), &claims, &key)
-            .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))
+        sign(&self.access, &claims)
     }
 
-    pub fn generate_refresh_token(&self, user_id: uuid::Uuid, version: uuid::Uuid) -> LocalResult<Cookie<'static>> {
+    pub fn generate_refresh_token(&self, jti: uuid::Uuid, user_id: uuid::Uuid, version: uuid::Uuid) -> LocalResult<Cookie<'static>> {
         let iat = Utc::now();
         let exp = (iat + CONFIG.jwt_refresh_exp_time).timestamp() as usize;
 
         let claims = JwtClaims {
             exp, 
             iat: iat.timestamp() as usize,
+            jti,
             user_id,
             version,
+            roles: Vec::new(),
+            courses: None,
+            entitlements_version: None,
         };
 
-        let key = EncodingKey::from_secret(CONFIG.jwt_refresh_secret.as_bytes());
-        let token = encode(&Header::default(), &claims, &key)
-            .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))?;
+        let token = sign(&self.refresh, &claims)?;
 
         let cookie = Cookie::build((CONFIG.jwt_refresh_cookie_name.clone(), token))
             .http_only(true)
@@ -79,4 +179,33 @@ impl JwtRepository {
 
         Ok(cookie)
     }
+
+    pub fn generate_mfa_token(&self, jti: uuid::Uuid, user_id: uuid::Uuid, version: uuid::Uuid) -> LocalResult<String> {
+        let iat = Utc::now();
+        let exp = (iat + CONFIG.mfa_pending_exp_time).timestamp() as usize;
+
+        let claims = MfaPendingClaims {
+            exp,
+            iat: iat.timestamp() as usize,
+            aud: MFA_AUDIENCE.to_string(),
+            jti,
+            user_id,
+            version,
+        };
+
+        sign(&self.refresh, &claims)
+    }
+
+    pub fn validate_mfa_token(&self, token: &str) -> LocalResult<MfaPendingClaims> {
+        verify(&self.refresh, token, Some(MFA_AUDIENCE))
+            .ok_or(LocalErr::new(LocalErrKind::InvalidMfaToken, StatusCode::UNAUTHORIZED))
+    }
+
+    /// Cookie matching the refresh token one, to be passed to `CookieJar::remove`
+    pub fn remove_refresh_token(&self) -> Cookie<'static> {
+        Cookie::build(CONFIG.jwt_refresh_cookie_name.clone())
+            .path("/")
+            .domain(CONFIG.jwt_domain.clone())
+            .build()
+    }
 }
@@ -0,0 +1,282 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Context, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, jwk::{AlgorithmParameters, CommonParameters, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType, EllipticCurve}};
+use rsa::{RsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts};
+use serde::Deserialize;
+
+use crate::config::CONFIG;
+
+/// Key used to sign and/or verify tokens
+pub struct JwtKey {
+    pub kid: String,
+    pub alg: Algorithm,
+    pub encoding: Option<EncodingKey>, // None for verification-only keys
+    pub decoding: DecodingKey,
+    pub jwk: Option<Jwk>, // only asymmetric keys are published
+}
+
+impl JwtKey {
+    /// HS* keys need `secret`, RS*/EdDSA keys need `public_key_file` (and `private_key_file` to sign)
+    fn load(kid: &str, alg: Algorithm, secret: Option<&str>, private_key_file: Option<&str>, public_key_file: Option<&str>) -> anyhow::Result<Self> {
+        if matches!(alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            let secret = secret.with_context(|| format!("key `{}`: a secret is required for HS algorithms", kid))?;
+
+            return Ok(Self {
+                kid: kid.to_string(),
+                alg,
+                encoding: Some(EncodingKey::from_secret(secret.as_bytes())),
+                decoding: DecodingKey::from_secret(secret.as_bytes()),
+                jwk: None,
+            })
+        }
+
+        let public_file = public_key_file.with_context(|| format!("key `{}`: a public key file is required for asymmetric algorithms", kid))?;
+        let public_pem = std::fs::read_to_string(public_file).with_context(|| format!("reading {}", public_file))?;
+        let private_pem = private_key_file
+            .map(|f| std::fs::read(f).with_context(|| format!("reading {}", f)))
+            .transpose()?;
+
+        let (encoding, decoding) = match alg {
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => (
+                private_pem.map(|pem| EncodingKey::from_rsa_pem(&pem)).transpose()?,
+                DecodingKey::from_rsa_pem(public_pem.as_bytes())?,
+            ),
+            Algorithm::EdDSA => (
+                private_pem.map(|pem| EncodingKey::from_ed_pem(&pem)).transpose()?,
+                DecodingKey::from_ed_pem(public_pem.as_bytes())?,
+            ),
+            alg => bail!("key `{}`: unsupported algorithm {:?}", kid, alg),
+        };
+
+        Ok(Self {
+            kid: kid.to_string(),
+            alg,
+            encoding,
+            decoding,
+            jwk: Some(public_jwk(kid, alg, &public_pem)?),
+        })
+    }
+}
+
+
+#[derive(Deserialize)]
+struct KeyringManifest {
+    active: String,
+    keys: Vec<KeyringManifestEntry>,
+}
+
+#[derive(Deserialize)]
+struct KeyringManifestEntry {
+    kid: String,
+    alg: Algorithm,
+    secret: Option<String>,
+    private_key_file: Option<String>,
+    public_key_file: Option<String>,
+}
+
+/// One active signing key plus any number of keys that are still trusted for verification.
+///
+/// Retired keys are kept in the manifest until every token they signed has expired
+pub struct Keyring {
+    active: Arc<JwtKey>,
+    keys: HashMap<String, Arc<JwtKey>>,
+}
+
+impl Keyring {
+    /// Loads `JWT_ACCESS_KEYRING_FILE` if set, otherwise the single key from the `JWT_ACCESS_*` variables
+    pub fn access_from_config() -> anyhow::Result<Self> {
+        match &CONFIG.jwt_access_keyring_file {
+            Some(path) => Self::from_file(path),
+            None => {
+                let key = JwtKey::load(
+                    &CONFIG.jwt_access_kid,
+                    CONFIG.jwt_access_algorithm,
+                    CONFIG.jwt_access_secret.as_deref(),
+                    CONFIG.jwt_access_private_key_file.as_deref(),
+                    CONFIG.jwt_access_public_key_file.as_deref(),
+                )?;
+                Self::new(key.kid.clone(), vec![key])
+            }
+        }
+    }
+
+    /// Keys of the internal tokens (refresh and mfa), never published.
+    /// Loads `JWT_REFRESH_KEYRING_FILE` if set, otherwise `JWT_REFRESH_SECRET`
+    pub fn refresh_from_config() -> anyhow::Result<Self> {
+        match &CONFIG.jwt_refresh_keyring_file {
+            Some(path) => Self::from_file(path),
+            None => {
+                let key = JwtKey::load(&CONFIG.jwt_refresh_kid, Algorithm::HS256, Some(&CONFIG.jwt_refresh_secret), None, None)?;
+                Self::new(key.kid.clone(), vec![key])
+            }
+        }
+    }
+
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let raw = std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+        let manifest: KeyringManifest = serde_json::from_str(&raw).with_context(|| format!("parsing {}", path))?;
+
+        let keys = manifest.keys.iter()
+            .map(|k| JwtKey::load(&k.kid, k.alg, k.secret.as_deref(), k.private_key_file.as_deref(), k.public_key_file.as_deref()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Self::new(manifest.active, keys)
+    }
+
+    fn new(active: String, keys: Vec<JwtKey>) -> anyhow::Result<Self> {
+        let mut by_kid = HashMap::new();
+        for key in keys {
+            let kid = key.kid.clone();
+            if by_kid.insert(kid.clone(), Arc::new(key)).is_some() {
+                bail!("duplicated key id `{}`", kid);
+            }
+        }
+
+        let active = by_kid.get(&active).cloned()
+            .with_context(|| format!("active key `{}` is not in the keyring", active))?;
+        if active.encoding.is_none() {
+            bail!("active key `{}` has no private key", active.kid);
+        }
+
+        Ok(Self { active, keys: by_kid })
+    }
+
+    pub fn active(&self) -> Arc<JwtKey> {
+        self.active.clone()
+    }
+
+    /// Tokens without `kid` (issued before key ids existed) are checked against the active key
+    pub fn get(&self, kid: Option<&str>) -> Option<Arc<JwtKey>> {
+        match kid {
+            Some(kid) => self.keys.get(kid).cloned(),
+            None => Some(self.active.clone()),
+        }
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet { keys: self.keys.values().filter_map(|k| k.jwk.clone()).collect() }
+    }
+}
+
+/// JWK of a PEM (SPKI) public key, as published at `/.well-known/jwks.json`
+fn public_jwk(kid: &str, alg: Algorithm, public_pem: &str) -> anyhow::Result<Jwk> {
+    let (key_algorithm, algorithm) = match alg {
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => {
+            let key = RsaPublicKey::from_public_key_pem(public_pem).context("invalid RSA public key")?;
+            let params = AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+            });
+            let key_algorithm = match alg {
+                Algorithm::RS384 => KeyAlgorithm::RS384,
+                Algorithm::RS512 => KeyAlgorithm::RS512,
+                _ => KeyAlgorithm::RS256,
+            };
+            (key_algorithm, params)
+        },
+        Algorithm::EdDSA => {
+            let key = ed25519_dalek::VerifyingKey::from_public_key_pem(public_pem).context("invalid Ed25519 public key")?;
+            let params = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key.as_bytes()),
+            });
+            (KeyAlgorithm::EdDSA, params)
+        },
+        alg => bail!("{:?} keys can't be published", alg),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Header, Validation, decode, encode};
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Claims {
+        exp: usize,
+    }
+
+    fn hs_key(kid: &str) -> JwtKey {
+        JwtKey::load(kid, Algorithm::HS256, Some(&format!("secret-{}", kid)), None, None).unwrap()
+    }
+
+    fn sign(keyring: &Keyring) -> (String, String) {
+        let key = keyring.active();
+        let mut header = Header::new(key.alg);
+        header.kid = Some(key.kid.clone());
+        let token = encode(&header, &Claims { exp: usize::MAX / 2 }, key.encoding.as_ref().unwrap()).unwrap();
+        (key.kid.clone(), token)
+    }
+
+    fn verifies(keyring: &Keyring, kid: Option<&str>, token: &str) -> bool {
+        keyring.get(kid)
+            .is_some_and(|key| decode::<Claims>(token, &key.decoding, &Validation::new(key.alg)).is_ok())
+    }
+
+    #[test]
+    fn retired_keys_still_verify_after_rotation() {
+        let before = Keyring::new("k1".to_string(), vec![hs_key("k1")]).unwrap();
+        let (kid, token) = sign(&before);
+
+        let rotated = Keyring::new("k2".to_string(), vec![hs_key("k1"), hs_key("k2")]).unwrap();
+        assert_eq!(rotated.active().kid, "k2");
+        assert!(verifies(&rotated, Some(&kid), &token));
+
+        let (new_kid, new_token) = sign(&rotated);
+        assert_eq!(new_kid, "k2");
+        assert!(!verifies(&before, Some(&new_kid), &new_token));
+    }
+
+    #[test]
+    fn removed_keys_no_longer_verify() {
+        let before = Keyring::new("k1".to_string(), vec![hs_key("k1")]).unwrap();
+        let (kid, token) = sign(&before);
+
+        let after = Keyring::new("k2".to_string(), vec![hs_key("k2")]).unwrap();
+        assert!(after.get(Some(&kid)).is_none());
+        // tokens without kid fall back to the active key, which didn't sign it
+        assert!(!verifies(&after, None, &token));
+        assert!(verifies(&before, None, &token));
+    }
+
+    #[test]
+    fn invalid_keyrings_are_rejected() {
+        assert!(Keyring::new("k1".to_string(), vec![hs_key("k1"), hs_key("k1")]).is_err());
+        assert!(Keyring::new("k3".to_string(), vec![hs_key("k1")]).is_err());
+        assert!(JwtKey::load("k1", Algorithm::HS256, None, None, None).is_err());
+        assert!(hs_key("k1").jwk.is_none());
+    }
+
+    #[test]
+    fn loads_the_manifest_file() {
+        let path = std::env::temp_dir().join(format!("keyring-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{"active": "b", "keys": [
+            {"kid": "a", "alg": "HS256", "secret": "secret-a"},
+            {"kid": "b", "alg": "HS256", "secret": "secret-b"}
+        ]}"#).unwrap();
+
+        let keyring = Keyring::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let keyring = keyring.unwrap();
+        assert_eq!(keyring.active().kid, "b");
+        assert!(keyring.get(Some("a")).is_some());
+        assert!(keyring.jwks().keys.is_empty());
+    }
+}
@@ -1 +1,5 @@
-pub mod jwt;
\ No newline at end of file
+pub mod jwt;
+pub mod jwt_keys;
+pub mod token;
+pub mod image;
+pub mod totp;
\ No newline at end of file
@@ -0,0 +1,12 @@
+use sha2::{Digest, Sha256};
+
+/// Random url-safe token and its sha256, the hash is what gets stored
+pub fn generate_token() -> (String, String) {
+    let plain = hex::encode(rand::random::<[u8; 32]>());
+    let hash = hash_token(&plain);
+    (plain, hash)
+}
+
+pub fn hash_token(plain: &str) -> String {
+    hex::encode(Sha256::digest(plain.as_bytes()))
+}
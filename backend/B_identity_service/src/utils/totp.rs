@@ -0,0 +1,108 @@
+use axum::http::StatusCode;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{config::CONFIG, error::{LocalErr, LocalErrKind, LocalResult, MapErrPrint}};
+
+const STEP_SECONDS: u64 = 30;
+const RECOVERY_CODES: usize = 10;
+
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, issuer: Option<String>, account: &str) -> LocalResult<TOTP> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    // skew 0: `verify_code` checks the neighbouring steps itself to know which one matched
+    TOTP::new(Algorithm::SHA1, 6, 0, STEP_SECONDS, bytes, issuer, account.to_string())
+        .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+/// `otpauth://` uri and the same uri as a base64 png QR code
+pub fn enrollment_uri(secret: &str, account: &str) -> LocalResult<(String, String)> {
+    let totp = totp(secret, Some(CONFIG.mfa_issuer.clone()), account)?;
+    let qr = totp.get_qr_base64()
+        .map_err_print(|_| LocalErr::new(LocalErrKind::Code500, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((totp.get_url(), qr))
+}
+
+/// Returns the time step `code` belongs to (allowing one step of clock drift),
+/// so the caller can reject a code that was already used
+pub fn verify_code(secret: &str, code: &str) -> LocalResult<Option<i64>> {
+    verify_code_at(secret, code, chrono::Utc::now().timestamp() as u64)
+}
+
+fn verify_code_at(secret: &str, code: &str, now: u64) -> LocalResult<Option<i64>> {
+    let totp = totp(secret, None, "")?;
+    let current = now / STEP_SECONDS;
+
+    let step = [current - 1, current, current + 1]
+        .into_iter()
+        .find(|step| totp.check(code, step * STEP_SECONDS));
+
+    Ok(step.map(|s| s as i64))
+}
+
+/// Plain codes, shown once to the user (format `xxxxx-xxxxx`)
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = hex::encode(rand::random::<[u8; 5]>());
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without dashes, spaces or case
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn code_at(secret: &str, time: u64) -> String {
+        totp(secret, None, "").unwrap().generate(time)
+    }
+
+    #[test]
+    fn accepts_the_current_step_and_one_step_of_drift() {
+        let secret = generate_secret();
+        let current = (NOW / STEP_SECONDS) as i64;
+
+        assert_eq!(verify_code_at(&secret, &code_at(&secret, NOW), NOW).unwrap(), Some(current));
+        assert_eq!(verify_code_at(&secret, &code_at(&secret, NOW - STEP_SECONDS), NOW).unwrap(), Some(current - 1));
+        assert_eq!(verify_code_at(&secret, &code_at(&secret, NOW + STEP_SECONDS), NOW).unwrap(), Some(current + 1));
+    }
+
+    #[test]
+    fn rejects_old_and_wrong_codes() {
+        let secret = generate_secret();
+        let old = code_at(&secret, NOW - 2 * STEP_SECONDS);
+        let neighbours = [NOW - STEP_SECONDS, NOW, NOW + STEP_SECONDS].map(|t| code_at(&secret, t));
+
+        if !neighbours.contains(&old) {
+            assert_eq!(verify_code_at(&secret, &old, NOW).unwrap(), None);
+        }
+        let wrong = (0..1_000_000).map(|n| format!("{:06}", n)).find(|c| !neighbours.contains(c)).unwrap();
+        assert_eq!(verify_code_at(&secret, &wrong, NOW).unwrap(), None);
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(codes.iter().all(|c| c.len() == 11 && &c[5..6] == "-"));
+        assert_eq!(normalize_recovery_code(" AbCde-12345 "), "abcde12345");
+    }
+}
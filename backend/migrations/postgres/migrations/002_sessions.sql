@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS sessions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expiration_date TIMESTAMPTZ NOT NULL,
+    revocation_date TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_idx ON sessions(user_id);
@@ -0,0 +1,9 @@
+-- every refresh creates a new session row in the same family (one family per login)
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS family_id UUID;
+UPDATE sessions SET family_id = id WHERE family_id IS NULL;
+ALTER TABLE sessions ALTER COLUMN family_id SET NOT NULL;
+
+-- set when the session's refresh token has been rotated
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS replaced_by UUID REFERENCES sessions(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS sessions_family_id_idx ON sessions(family_id);
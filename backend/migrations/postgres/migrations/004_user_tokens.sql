@@ -0,0 +1,14 @@
+CREATE TYPE "UserTokenKind" as ENUM ('PasswordReset');
+
+-- single-use tokens sent by email, only their sha256 is stored
+CREATE TABLE IF NOT EXISTS user_tokens (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind "UserTokenKind" NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expiration_date TIMESTAMPTZ NOT NULL,
+    use_date TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS user_tokens_user_id_idx ON user_tokens(user_id, kind);
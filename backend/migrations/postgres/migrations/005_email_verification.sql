@@ -0,0 +1,6 @@
+ALTER TYPE "UserTokenKind" ADD VALUE IF NOT EXISTS 'EmailVerification';
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMPTZ;
+
+-- accounts created before verification existed are grandfathered, `EMAIL_VERIFICATION=login` would lock them out
+UPDATE users SET email_verified_at = creation_date WHERE email_verified_at IS NULL;
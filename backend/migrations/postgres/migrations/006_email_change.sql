@@ -0,0 +1,4 @@
+ALTER TYPE "UserTokenKind" ADD VALUE IF NOT EXISTS 'EmailChange';
+
+-- token specific data, e.g. the new address for `EmailChange`
+ALTER TABLE user_tokens ADD COLUMN IF NOT EXISTS payload VARCHAR(100);
@@ -0,0 +1,7 @@
+-- failed login attempts per account/ip, used when LOGIN_THROTTLE_BACKEND=postgres
+CREATE TABLE IF NOT EXISTS login_attempts (
+    key VARCHAR(150) PRIMARY KEY, -- `account:<credential>` or `ip:<address>`
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure TIMESTAMPTZ NOT NULL DEFAULT now(),
+    locked_until TIMESTAMPTZ
+);
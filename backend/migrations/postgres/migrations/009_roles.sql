@@ -0,0 +1,57 @@
+CREATE TABLE IF NOT EXISTS roles (
+    name VARCHAR(50) PRIMARY KEY,
+    description VARCHAR(200) NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS permissions (
+    name VARCHAR(50) PRIMARY KEY,
+    description VARCHAR(200) NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role_name VARCHAR(50) NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+    permission_name VARCHAR(50) NOT NULL REFERENCES permissions(name) ON DELETE CASCADE,
+    PRIMARY KEY (role_name, permission_name)
+);
+
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role_name VARCHAR(50) NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, role_name)
+);
+
+INSERT INTO roles (name, description) VALUES
+    ('student', 'Can enroll in and follow courses'),
+    ('instructor', 'Can publish and manage their own courses'),
+    ('admin', 'Manages users and the platform')
+ON CONFLICT DO NOTHING;
+
+INSERT INTO permissions (name, description) VALUES
+    ('courses.enroll', 'Enroll in courses'),
+    ('courses.create', 'Create courses'),
+    ('courses.manage_own', 'Edit and publish own courses'),
+    ('courses.manage_all', 'Edit, publish or remove any course'),
+    ('users.read', 'List and inspect user accounts'),
+    ('users.manage', 'Deactivate, reactivate and log out users'),
+    ('roles.assign', 'Change the roles of users')
+ON CONFLICT DO NOTHING;
+
+INSERT INTO role_permissions (role_name, permission_name) VALUES
+    ('student', 'courses.enroll'),
+    ('instructor', 'courses.enroll'),
+    ('instructor', 'courses.create'),
+    ('instructor', 'courses.manage_own'),
+    ('admin', 'courses.enroll'),
+    ('admin', 'courses.create'),
+    ('admin', 'courses.manage_own'),
+    ('admin', 'courses.manage_all'),
+    ('admin', 'users.read'),
+    ('admin', 'users.manage'),
+    ('admin', 'roles.assign')
+ON CONFLICT DO NOTHING;
+
+-- existing accounts are students
+INSERT INTO user_roles (user_id, role_name)
+SELECT id, 'student' FROM users
+ON CONFLICT DO NOTHING;
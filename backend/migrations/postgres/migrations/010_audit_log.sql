@@ -0,0 +1,12 @@
+-- actions taken by staff on user accounts
+CREATE TABLE IF NOT EXISTS audit_log (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL, -- NULL once the staff account is gone
+    action VARCHAR(50) NOT NULL,
+    target_user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    details JSONB NOT NULL DEFAULT '{}',
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_target_user_id_idx ON audit_log(target_user_id);
+CREATE INDEX IF NOT EXISTS audit_log_actor_id_idx ON audit_log(actor_id);
@@ -0,0 +1,17 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_scheduled_at TIMESTAMPTZ; -- anonymised once reached
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_date TIMESTAMPTZ; -- when the PII was removed
+
+CREATE INDEX IF NOT EXISTS users_deletion_scheduled_at_idx ON users(deletion_scheduled_at)
+    WHERE deletion_scheduled_at IS NOT NULL AND deletion_date IS NULL;
+
+-- events for other services, written in the same transaction as the change they describe
+CREATE TABLE IF NOT EXISTS outbox_events (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    event_type VARCHAR(100) NOT NULL,
+    payload JSONB NOT NULL,
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    publication_date TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS outbox_events_unpublished_idx ON outbox_events(creation_date)
+    WHERE publication_date IS NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE outbox_events ADD COLUMN IF NOT EXISTS schema_version SMALLINT NOT NULL DEFAULT 1; -- of `payload`, per event type
+ALTER TABLE outbox_events ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0; -- failed publications
+ALTER TABLE outbox_events ADD COLUMN IF NOT EXISTS last_error TEXT;
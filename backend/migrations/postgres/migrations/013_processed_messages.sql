@@ -0,0 +1,6 @@
+-- commands received from other services, so a redelivered message isn't applied twice
+CREATE TABLE IF NOT EXISTS processed_messages (
+    id UUID PRIMARY KEY, -- idempotency key chosen by the sender
+    command_type VARCHAR(100) NOT NULL,
+    processing_date TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,42 @@
+-- what a course costs, sent by the course service (`course.set_price` command)
+CREATE TABLE IF NOT EXISTS course_prices (
+    course_id UUID PRIMARY KEY,
+    title VARCHAR(255) NOT NULL, -- shown on the checkout page
+    amount BIGINT NOT NULL CHECK (amount > 0), -- minor units (cents)
+    currency VARCHAR(3) NOT NULL, -- ISO 4217, lowercase
+    update_date TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- one-time purchases of a course, priced when the checkout starts
+CREATE TABLE IF NOT EXISTS orders (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id), -- accounts are anonymised, never removed
+    course_id UUID NOT NULL,
+    amount BIGINT NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- pending | paid | canceled
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    update_date TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS orders_user_id_idx ON orders(user_id);
+-- a course is bought at most once
+CREATE UNIQUE INDEX IF NOT EXISTS orders_paid_course_idx ON orders(user_id, course_id) WHERE status = 'paid';
+
+-- checkout sessions opened at the payment provider for an order
+CREATE TABLE IF NOT EXISTS payments (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    order_id UUID NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    provider VARCHAR(20) NOT NULL, -- fake | stripe
+    provider_session_id VARCHAR(255) NOT NULL,
+    provider_payment_id VARCHAR(255), -- set once paid (Stripe payment intent)
+    checkout_url TEXT NOT NULL,
+    amount BIGINT NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- pending | succeeded | failed | expired
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    update_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (provider, provider_session_id)
+);
+
+CREATE INDEX IF NOT EXISTS payments_order_id_idx ON payments(order_id);
@@ -0,0 +1,22 @@
+-- events received from the payment provider, a replayed event id is acknowledged without being applied again
+CREATE TABLE IF NOT EXISTS payment_webhook_events (
+    id VARCHAR(255) PRIMARY KEY, -- provider event id
+    provider VARCHAR(20) NOT NULL,
+    event_type VARCHAR(100) NOT NULL,
+    payload JSONB NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'received', -- received | processed | failed
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    receipt_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    processing_date TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS payment_webhook_events_status_idx ON payment_webhook_events(status, receipt_date);
+
+INSERT INTO permissions (name, description) VALUES
+    ('payments.manage', 'Inspect and retry payment webhook events')
+ON CONFLICT DO NOTHING;
+
+INSERT INTO role_permissions (role_name, permission_name) VALUES
+    ('admin', 'payments.manage')
+ON CONFLICT DO NOTHING;
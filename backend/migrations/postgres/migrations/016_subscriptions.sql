@@ -0,0 +1,42 @@
+-- recurring plans users can subscribe to
+CREATE TABLE IF NOT EXISTS plans (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    code VARCHAR(50) NOT NULL UNIQUE,
+    name VARCHAR(255) NOT NULL,
+    amount BIGINT NOT NULL CHECK (amount > 0), -- minor units, per interval
+    currency VARCHAR(3) NOT NULL,
+    billing_interval VARCHAR(10) NOT NULL, -- month | year
+    trial_days INT NOT NULL DEFAULT 0,
+    provider_price_id VARCHAR(255), -- Stripe price, required by the stripe provider
+    is_active BOOLEAN NOT NULL DEFAULT TRUE, -- inactive plans can't be subscribed to, existing subscriptions continue
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO plans (code, name, amount, currency, billing_interval, trial_days) VALUES
+    ('monthly', 'Monthly', 1500, 'eur', 'month', 7),
+    ('yearly', 'Yearly', 15000, 'eur', 'year', 7)
+ON CONFLICT DO NOTHING;
+
+-- mirror of the subscriptions at the payment provider, only changed by what the provider reports
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id),
+    plan_id UUID NOT NULL REFERENCES plans(id),
+    status VARCHAR(20) NOT NULL, -- trialing | active | past_due | canceled | expired
+    provider VARCHAR(20) NOT NULL,
+    provider_subscription_id VARCHAR(255) NOT NULL,
+    current_period_start TIMESTAMPTZ NOT NULL,
+    current_period_end TIMESTAMPTZ NOT NULL,
+    trial_end TIMESTAMPTZ,
+    cancel_at_period_end BOOLEAN NOT NULL DEFAULT FALSE,
+    canceled_at TIMESTAMPTZ,
+    last_event_date TIMESTAMPTZ NOT NULL, -- provider time of the last applied change, older events are ignored
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    update_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (provider, provider_subscription_id)
+);
+
+CREATE INDEX IF NOT EXISTS subscriptions_user_id_idx ON subscriptions(user_id);
+-- at most one subscription that hasn't ended per user
+CREATE UNIQUE INDEX IF NOT EXISTS subscriptions_live_user_idx ON subscriptions(user_id)
+    WHERE status IN ('trialing', 'active', 'past_due');
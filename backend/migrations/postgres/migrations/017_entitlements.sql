@@ -0,0 +1,27 @@
+-- access to a course, a user can hold it through several sources (e.g. bought, then granted by staff)
+CREATE TABLE IF NOT EXISTS entitlements (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id),
+    course_id UUID NOT NULL,
+    source VARCHAR(20) NOT NULL, -- purchase | grant
+    order_id UUID REFERENCES orders(id), -- purchases
+    granted_by UUID REFERENCES users(id) ON DELETE SET NULL, -- grants
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revocation_date TIMESTAMPTZ, -- kept for history once revoked
+    UNIQUE (user_id, course_id, source)
+);
+
+CREATE INDEX IF NOT EXISTS entitlements_course_id_idx ON entitlements(course_id);
+
+-- courses already paid for
+INSERT INTO entitlements (user_id, course_id, source, order_id, creation_date)
+SELECT user_id, course_id, 'purchase', id, update_date FROM orders WHERE status = 'paid'
+ON CONFLICT DO NOTHING;
+
+INSERT INTO permissions (name, description) VALUES
+    ('entitlements.manage', 'Grant and revoke access to courses')
+ON CONFLICT DO NOTHING;
+
+INSERT INTO role_permissions (role_name, permission_name) VALUES
+    ('admin', 'entitlements.manage')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,52 @@
+-- promotional codes, taken off the first payment of a course or plan
+CREATE TABLE IF NOT EXISTS coupons (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    code VARCHAR(50) NOT NULL UNIQUE, -- uppercase, matched case-insensitively
+    discount_type VARCHAR(10) NOT NULL, -- percent | fixed
+    discount_value BIGINT NOT NULL, -- percent: 1..100, fixed: minor units
+    currency VARCHAR(3), -- fixed discounts only apply to prices in this currency
+    expiration_date TIMESTAMPTZ,
+    max_redemptions INT CHECK (max_redemptions > 0), -- in total, unlimited when null
+    max_redemptions_per_user INT NOT NULL DEFAULT 1 CHECK (max_redemptions_per_user > 0),
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (
+        (discount_type = 'percent' AND discount_value BETWEEN 1 AND 100 AND currency IS NULL)
+        OR (discount_type = 'fixed' AND discount_value > 0 AND currency IS NOT NULL)
+    )
+);
+
+-- courses and plans a coupon is restricted to, a coupon without targets applies to everything
+CREATE TABLE IF NOT EXISTS coupon_targets (
+    coupon_id UUID NOT NULL REFERENCES coupons(id) ON DELETE CASCADE,
+    target_type VARCHAR(10) NOT NULL, -- course | plan
+    target_id UUID NOT NULL, -- course id, or plan id
+    PRIMARY KEY (coupon_id, target_type, target_id)
+);
+
+-- uses of a coupon, taken with the order (or subscription checkout) and given back if its checkout doesn't go through
+CREATE TABLE IF NOT EXISTS coupon_redemptions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    coupon_id UUID NOT NULL REFERENCES coupons(id),
+    user_id UUID NOT NULL REFERENCES users(id),
+    order_id UUID REFERENCES orders(id), -- course purchases
+    provider VARCHAR(20),
+    provider_session_id VARCHAR(255), -- checkout session the discount was applied to, none when the coupon covers the whole price
+    discount_amount BIGINT NOT NULL, -- minor units
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    release_date TIMESTAMPTZ, -- the checkout expired or failed, the use doesn't count anymore
+    UNIQUE (provider, provider_session_id)
+);
+
+CREATE INDEX IF NOT EXISTS coupon_redemptions_coupon_id_idx ON coupon_redemptions(coupon_id, user_id);
+
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS discount_amount BIGINT NOT NULL DEFAULT 0; -- `amount` is what was charged
+
+INSERT INTO permissions (name, description) VALUES
+    ('coupons.manage', 'Create and deactivate coupons')
+ON CONFLICT DO NOTHING;
+
+INSERT INTO role_permissions (role_name, permission_name) VALUES
+    ('admin', 'coupons.manage')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,23 @@
+-- money given back on a paid order, in full or in part
+CREATE TABLE IF NOT EXISTS refunds (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    order_id UUID NOT NULL REFERENCES orders(id),
+    payment_id UUID NOT NULL REFERENCES payments(id),
+    user_id UUID NOT NULL REFERENCES users(id),
+    amount BIGINT NOT NULL CHECK (amount > 0), -- minor units
+    currency VARCHAR(3) NOT NULL,
+    reason TEXT,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- pending | succeeded | failed | canceled
+    provider VARCHAR(20) NOT NULL,
+    provider_refund_id VARCHAR(255), -- set once the provider accepted it
+    requested_by UUID REFERENCES users(id) ON DELETE SET NULL, -- the buyer or staff, none when made at the provider
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    update_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (provider, provider_refund_id)
+);
+
+CREATE INDEX IF NOT EXISTS refunds_order_id_idx ON refunds(order_id);
+CREATE INDEX IF NOT EXISTS refunds_user_id_idx ON refunds(user_id);
+
+-- orders can now also be refunded (in full) or disputed (chargeback opened by the buyer's bank)
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS refunded_amount BIGINT NOT NULL DEFAULT 0; -- succeeded refunds
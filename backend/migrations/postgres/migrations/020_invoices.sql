@@ -0,0 +1,44 @@
+-- invoice numbering, one series per prefix and year (e.g. `INV-2026`): numbers follow each other without gaps
+CREATE TABLE IF NOT EXISTS invoice_series (
+    code VARCHAR(20) PRIMARY KEY,
+    next_number BIGINT NOT NULL DEFAULT 1
+);
+
+-- one per successful charge, with everything shown on it copied at issue time
+CREATE TABLE IF NOT EXISTS invoices (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    series VARCHAR(20) NOT NULL REFERENCES invoice_series(code),
+    number BIGINT NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id),
+    order_id UUID REFERENCES orders(id), -- course purchases
+    subscription_id UUID REFERENCES subscriptions(id), -- subscription charges
+    provider VARCHAR(20) NOT NULL,
+    provider_charge_id VARCHAR(255) NOT NULL, -- checkout session, or provider invoice
+    seller_name VARCHAR(255) NOT NULL,
+    seller_address TEXT NOT NULL,
+    seller_vat_id VARCHAR(50),
+    buyer_name VARCHAR(255) NOT NULL,
+    buyer_email VARCHAR(255) NOT NULL,
+    lines JSONB NOT NULL, -- [{ description, quantity, unit_amount, amount }], minor units, taxes included
+    currency VARCHAR(3) NOT NULL,
+    tax_rate INT NOT NULL, -- basis points (2000 is 20%)
+    subtotal_amount BIGINT NOT NULL, -- without taxes
+    tax_amount BIGINT NOT NULL,
+    total_amount BIGINT NOT NULL,
+    issue_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (series, number),
+    UNIQUE (provider, provider_charge_id)
+);
+
+CREATE INDEX IF NOT EXISTS invoices_user_id_idx ON invoices(user_id, issue_date);
+
+-- an issued invoice can't be changed nor removed, mistakes are corrected by issuing credit notes
+CREATE OR REPLACE FUNCTION invoices_immutable() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'invoice % is immutable', OLD.id;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS invoices_immutable ON invoices;
+CREATE TRIGGER invoices_immutable BEFORE UPDATE OR DELETE ON invoices
+    FOR EACH ROW EXECUTE FUNCTION invoices_immutable();
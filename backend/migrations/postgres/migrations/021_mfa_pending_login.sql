@@ -0,0 +1,2 @@
+-- id (jti) of the last mfa token handed out by login, cleared when it is exchanged so it can only be used once
+ALTER TABLE user_mfa ADD COLUMN IF NOT EXISTS pending_login_id UUID;
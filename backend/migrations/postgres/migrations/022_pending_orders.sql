@@ -0,0 +1,10 @@
+-- one open checkout per user and course: a second one is refused, the first one is reused
+-- older duplicates are canceled, if they still get paid the order becomes `refund_due`
+UPDATE orders SET status = 'canceled', update_date = now()
+WHERE status = 'pending' AND id NOT IN (
+    SELECT DISTINCT ON (user_id, course_id) id FROM orders
+    WHERE status = 'pending'
+    ORDER BY user_id, course_id, creation_date DESC
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS orders_pending_course_idx ON orders(user_id, course_id) WHERE status = 'pending';
@@ -0,0 +1,16 @@
+-- subscription checkout sessions opened at the payment provider, a user has at most one pending:
+-- it is reused for the same plan, so two of them can't both be paid
+CREATE TABLE IF NOT EXISTS subscription_checkouts (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id),
+    plan_id UUID NOT NULL REFERENCES plans(id),
+    provider VARCHAR(20) NOT NULL,
+    provider_session_id VARCHAR(255) NOT NULL,
+    checkout_url TEXT NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- pending | succeeded | failed | expired
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    update_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (provider, provider_session_id)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS subscription_checkouts_pending_user_idx ON subscription_checkouts(user_id) WHERE status = 'pending';
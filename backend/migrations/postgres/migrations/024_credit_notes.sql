@@ -0,0 +1,5 @@
+-- credit notes are invoices with negative amounts, in their own series (e.g. `INV-CN-2026`), one per refund
+ALTER TABLE invoices ADD COLUMN IF NOT EXISTS credited_invoice_id UUID REFERENCES invoices(id);
+ALTER TABLE invoices ADD COLUMN IF NOT EXISTS refund_id UUID REFERENCES refunds(id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS invoices_refund_id_idx ON invoices(refund_id) WHERE refund_id IS NOT NULL;
@@ -0,0 +1,2 @@
+-- changed with the courses of the user: only access tokens carrying them (`JWT_ACCESS_ENTITLEMENTS`) stop being accepted, refreshing still works
+ALTER TABLE users ADD COLUMN IF NOT EXISTS entitlements_version UUID NOT NULL DEFAULT uuid_generate_v4();